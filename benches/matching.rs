@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion,
+    Throughput};
+use criterion::measurement::WallTime;
+
+use calob::account::{Account, AccountId, Accounts};
+use calob::book::{Book, OrderView};
+use calob::order::{Order, OrderId, OrderPrice, OrderQuantity, OrderType};
+use calob::side::Band;
+
+/* a baseline for the matching engine: each benchmark times one request at a
+ * time, so that criterion reports requests per second as its throughput and
+ * the latencies of the individual requests can be summarised afterwards;
+ * the deep book holds `CALOB_BENCH_DEPTH` orders, a million by default, and
+ * each book is benchmarked again with its levels in a ladder */
+
+const BUYER: AccountId = 1;
+const SELLER: AccountId = 2;
+
+/* asks rest on the levels from here up and bids from a little below, leaving
+ * room in between for the asks which are crossed and swept */
+const ASK: OrderPrice = 10_000;
+const BID: OrderPrice = ASK - 20;
+const LEVELS: OrderPrice = 1_000;
+const SHALLOW: usize = 10_000;
+const DEPTH: usize = 1_000_000;
+
+/* xorshift64*, so that every run sends the same requests */
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+/* a book resting `depth` asks spread over `LEVELS` levels, along with the
+ * accounts behind them */
+struct Bench {
+    book: Book,
+    accounts: Accounts,
+    resting: Vec<OrderId>,
+    next_id: OrderId,
+    random: Random
+}
+
+impl Bench {
+    fn new(depth: usize, band: Option<Band>) -> Self {
+        let mut accounts: Accounts = HashMap::new();
+
+        for id in [BUYER, SELLER] {
+            let mut account: Account = Account::new(id, format!("{}", id),
+                HashMap::new(), HashMap::new());
+
+            account.add_balance("USD".to_string(), 1_000_000_000_000_000)
+                .unwrap();
+            account.add_holding("VOC".to_string(), 1_000_000_000_000)
+                .unwrap();
+            accounts.insert(id, account);
+        }
+
+        let mut bench: Bench = Bench {
+            book: Book::new(1, "Vereenigde Oostindische Compagnie"
+                .to_string(), "VOC".to_string(), "USD".to_string()),
+            accounts,
+            resting: vec![],
+            next_id: 1,
+            random: Random(0x5eed)
+        };
+
+        bench.book.set_band(band).unwrap();
+
+        for _ in 0..depth {
+            let price: OrderPrice = ASK + bench.random.below(LEVELS as usize)
+                as OrderPrice;
+            let id: OrderId = bench.rest(OrderType::Ask, price, 10);
+            bench.resting.push(id);
+        }
+
+        bench
+    }
+
+    fn id(&mut self) -> OrderId {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn rest(&mut self, r#type: OrderType, price: OrderPrice,
+        quantity: OrderQuantity) -> OrderId {
+        let order: Order = self.order(r#type, price, quantity);
+        let id: OrderId = order.id();
+
+        self.book.submit(order, &mut self.accounts).unwrap();
+        self.book.take_reports();
+        id
+    }
+
+    fn order(&mut self, r#type: OrderType, price: OrderPrice,
+        quantity: OrderQuantity) -> Order {
+        let owner: AccountId = match r#type {
+            OrderType::Bid => BUYER,
+            OrderType::Ask => SELLER
+        };
+
+        Order::new(self.id(), owner, r#type, price, quantity)
+    }
+
+    /* times a single request, leaving out whatever is done to prepare it
+     * or to tidy up after it */
+    fn time(&mut self, latencies: &mut Vec<Duration>,
+        request: impl FnOnce(&mut Bench)) -> Duration {
+        let start: Instant = Instant::now();
+        request(self);
+        let elapsed: Duration = start.elapsed();
+
+        latencies.push(elapsed);
+        self.book.take_reports();
+        elapsed
+    }
+}
+
+fn percentile(sorted: &[Duration], percent: f64) -> Duration {
+    let rank: usize = ((sorted.len() - 1) as f64 * percent / 100.0).round()
+        as usize;
+    sorted[rank]
+}
+
+fn report(name: &str, latencies: &mut [Duration]) {
+    if latencies.is_empty() {
+        return;
+    }
+
+    latencies.sort();
+    println!("{:<24} latency p50 {:>10?} p90 {:>10?} p99 {:>10?} \
+        p99.9 {:>10?} max {:>10?}", name, percentile(latencies, 50.0),
+        percentile(latencies, 90.0), percentile(latencies, 99.0),
+        percentile(latencies, 99.9), latencies[latencies.len() - 1]);
+}
+
+type Request = fn(&mut Bench, &mut Vec<Duration>) -> Duration;
+
+/* every kind of request against a book `depth` orders deep; each leaves the
+ * book as deep as it found it, except for resting, which comes last */
+fn suite(c: &mut Criterion, name: &str, depth: usize, band: Option<Band>,
+    samples: usize) {
+    let requests: [(&str, Request); 4] = [("cross", cross), ("sweep", sweep),
+        ("cancel", cancel), ("rest", rest)];
+    let mut group: BenchmarkGroup<WallTime> = c.benchmark_group(name);
+    let mut bench: Bench = Bench::new(depth, band);
+
+    group.throughput(Throughput::Elements(1));
+    group.sample_size(samples);
+
+    for (request_name, request) in requests.iter() {
+        let mut latencies: Vec<Duration> = vec![];
+
+        group.bench_function(*request_name, |b| b.iter_custom(|iters|
+            (0..iters).map(|_| request(&mut bench, &mut latencies)).sum()));
+        report(&format!("{}/{}", name, request_name), &mut latencies);
+    }
+
+    group.finish();
+}
+
+/* bids which rest below the asks */
+fn rest(bench: &mut Bench, latencies: &mut Vec<Duration>) -> Duration {
+    let price: OrderPrice = BID - bench.random.below(LEVELS as usize)
+        as OrderPrice;
+    let order: Order = bench.order(OrderType::Bid, price, 10);
+
+    bench.time(latencies, |bench| bench.book.submit(order,
+        &mut bench.accounts).unwrap())
+}
+
+/* a bid which takes exactly the ask resting at the best price */
+fn cross(bench: &mut Bench, latencies: &mut Vec<Duration>) -> Duration {
+    bench.rest(OrderType::Ask, ASK - 1, 10);
+    let order: Order = bench.order(OrderType::Bid, ASK - 1, 10);
+
+    bench.time(latencies, |bench| bench.book.submit(order,
+        &mut bench.accounts).unwrap())
+}
+
+/* a bid which takes ten levels of asks, each resting below the last */
+fn sweep(bench: &mut Bench, latencies: &mut Vec<Duration>) -> Duration {
+    for offset in 1..=10 {
+        bench.rest(OrderType::Ask, ASK - offset, 10);
+    }
+
+    let order: Order = bench.order(OrderType::Bid, ASK - 1, 100);
+
+    bench.time(latencies, |bench| bench.book.submit(order,
+        &mut bench.accounts).unwrap())
+}
+
+/* a resting order chosen at random, which is then replaced */
+fn cancel(bench: &mut Bench, latencies: &mut Vec<Duration>) -> Duration {
+    let index: usize = bench.random.below(bench.resting.len());
+    let id: OrderId = bench.resting.swap_remove(index);
+    let view: OrderView = bench.book.order(id).unwrap();
+    let (r#type, price): (OrderType, OrderPrice) = (view.r#type(),
+        view.price());
+
+    let elapsed: Duration = bench.time(latencies, |bench| bench.book
+        .cancel(id).unwrap());
+    let replacement: OrderId = bench.rest(r#type, price, 10);
+
+    bench.resting.push(replacement);
+    elapsed
+}
+
+/* a band wide enough for every price the requests use */
+fn band() -> Option<Band> {
+    Some(Band::new(BID - LEVELS, ASK + LEVELS, 1).unwrap())
+}
+
+fn bench_shallow(c: &mut Criterion) {
+    suite(c, "shallow", SHALLOW, None, 100);
+    suite(c, "shallow-ladder", SHALLOW, band(), 100);
+}
+
+fn bench_deep(c: &mut Criterion) {
+    let depth: usize = env::var("CALOB_BENCH_DEPTH").ok()
+        .and_then(|depth| depth.parse().ok())
+        .unwrap_or(DEPTH);
+
+    suite(c, "deep", depth, None, 10);
+    suite(c, "deep-ladder", depth, band(), 10);
+}
+
+criterion_group!(benches, bench_shallow, bench_deep);
+criterion_main!(benches);
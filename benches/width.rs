@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use calob::account::{Account, Accounts};
+use calob::book::Book;
+use calob::order::{Order, OrderId, OrderPrice, OrderType};
+
+/* compares the default build with a `narrow` one; run
+ *
+ *     cargo bench --bench width -- --save-baseline wide
+ *     cargo bench --bench width --features narrow -- --baseline wide
+ *
+ * and criterion reports the change in each benchmark */
+
+const ORDERS: OrderId = 10_000;
+const LEVELS: OrderPrice = 1_000;
+
+fn accounts() -> Accounts {
+    let mut accounts: Accounts = HashMap::new();
+
+    for id in 1..3 {
+        let mut account: Account = Account::new(id, format!("{}", id),
+            HashMap::new(), HashMap::new());
+
+        account.add_balance("USD".to_string(), 1_000_000_000_000).unwrap();
+        account.add_holding("VOC".to_string(), 1_000_000_000).unwrap();
+        accounts.insert(id, account);
+    }
+
+    accounts
+}
+
+fn book() -> Book {
+    Book::new(1, "Vereenigde Oostindische Compagnie".to_string(),
+        "VOC".to_string(), "USD".to_string())
+}
+
+/* the asks to rest, spread over every level above 1000 */
+fn asks() -> Vec<Order> {
+    (0..ORDERS).map(|id| Order::new(id + 1, 2, OrderType::Ask,
+        1_000 + id % LEVELS, 10)).collect()
+}
+
+fn populated() -> (Book, Accounts) {
+    let mut book: Book = book();
+    let mut accounts: Accounts = accounts();
+
+    for order in asks().into_iter() {
+        book.submit(order, &mut accounts).unwrap();
+    }
+
+    book.take_reports();
+    (book, accounts)
+}
+
+fn bench_rest(c: &mut Criterion) {
+    c.bench_function("rest", |b| b.iter_batched(|| (book(), accounts(),
+        asks()), |(mut book, mut accounts, asks)| {
+        for order in asks.into_iter() {
+            book.submit(order, &mut accounts).unwrap();
+        }
+
+        book
+    }, BatchSize::LargeInput));
+}
+
+fn bench_sweep(c: &mut Criterion) {
+    c.bench_function("sweep", |b| b.iter_batched(populated,
+        |(mut book, mut accounts)| {
+        book.submit_ioc(Order::market(ORDERS + 1, 1, OrderType::Bid,
+            ORDERS * 10), &mut accounts).unwrap();
+        book
+    }, BatchSize::LargeInput));
+}
+
+fn bench_cancel(c: &mut Criterion) {
+    c.bench_function("cancel", |b| b.iter_batched(populated,
+        |(mut book, _)| {
+        for id in (1..=ORDERS).step_by(10) {
+            book.cancel(id).unwrap();
+        }
+
+        book
+    }, BatchSize::LargeInput));
+}
+
+criterion_group!(benches, bench_rest, bench_sweep, bench_cancel);
+criterion_main!(benches);
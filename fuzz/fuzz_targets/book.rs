@@ -0,0 +1,155 @@
+#![no_main]
+
+use std::collections::HashMap;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+use calob::account::{Account, AccountBalance, AccountHolding, AccountId,
+    Accounts};
+use calob::book::Book;
+use calob::invariant::{self, Totals};
+use calob::margin::MarginTerms;
+use calob::order::{Order, OrderId, OrderPrice, OrderQuantity, OrderType};
+use calob::side::Band;
+
+/* ids, owners and prices are drawn from small ranges so that commands
+ * usually find each other; `Huge` reaches for the edges of the types */
+#[derive(Arbitrary, Debug)]
+enum Price {
+    Near(u8),
+    Market,
+    Huge(OrderPrice)
+}
+
+#[derive(Arbitrary, Debug)]
+enum Quantity {
+    Small(u8),
+    Huge(OrderQuantity)
+}
+
+#[derive(Arbitrary, Debug)]
+enum Command {
+    Submit {
+        id: u8,
+        owner: u8,
+        bid: bool,
+        price: Price,
+        quantity: Quantity
+    },
+    SubmitIoc {
+        id: u8,
+        owner: u8,
+        bid: bool,
+        price: Price,
+        quantity: Quantity
+    },
+    Cancel {
+        id: u8
+    },
+    Amend {
+        id: u8,
+        price: Price,
+        quantity: Quantity
+    }
+}
+
+impl Price {
+    fn value(&self, r#type: OrderType) -> OrderPrice {
+        match self {
+            Price::Near(offset) => 90 + (*offset as OrderPrice % 20),
+            Price::Market => match r#type {
+                OrderType::Bid => OrderPrice::MAX,
+                OrderType::Ask => 0
+            },
+            Price::Huge(price) => *price
+        }
+    }
+}
+
+impl Quantity {
+    fn value(&self) -> OrderQuantity {
+        match self {
+            Quantity::Small(quantity) => *quantity as OrderQuantity,
+            Quantity::Huge(quantity) => *quantity
+        }
+    }
+}
+
+/* rich and poor accounts, one on margin and one which may sell short;
+ * account 0 does not exist */
+fn accounts() -> Accounts {
+    let mut accounts: Accounts = HashMap::new();
+
+    for id in 1..6 {
+        let mut account: Account = Account::new(id, format!("{}", id),
+            HashMap::new(), HashMap::new());
+        let (cash, shares): (AccountBalance, AccountHolding) = match id {
+            1 => (1_000_000, 1_000),
+            2 => (500, 5),
+            _ => (20_000, 200)
+        };
+
+        account.add_balance("USD".to_string(), cash).unwrap();
+        account.add_holding("VOC".to_string(), shares).unwrap();
+
+        match id {
+            4 => account.set_margin(Some(MarginTerms::new("USD".to_string(),
+                5000, 2500))),
+            5 => account.set_short_selling(true),
+            _ => {}
+        }
+
+        accounts.insert(id, account);
+    }
+
+    accounts
+}
+
+fn order(id: u8, owner: u8, bid: bool, price: &Price, quantity: &Quantity) ->
+    Order {
+    let r#type: OrderType = match bid {
+        true => OrderType::Bid,
+        false => OrderType::Ask
+    };
+
+    Order::new(id as OrderId, (owner % 6) as AccountId, r#type,
+        price.value(r#type), quantity.value())
+}
+
+/* the book keeps its levels in a tree, or in a ladder around the near
+ * prices */
+fuzz_target!(|input: (bool, Vec<Command>)| {
+    let (ladder, commands): (bool, Vec<Command>) = input;
+    let mut book: Book = Book::new(1, "Vereenigde Oostindische Compagnie"
+        .to_string(), "VOC".to_string(), "USD".to_string());
+
+    if ladder {
+        book.set_band(Some(Band::new(50, 150, 1).unwrap())).unwrap();
+    }
+
+    let mut accounts: Accounts = accounts();
+    let before: Totals = Totals::of(&accounts);
+
+    for command in commands.iter() {
+        let _ = match command {
+            Command::Submit { id, owner, bid, price, quantity } =>
+                book.submit(order(*id, *owner, *bid, price, quantity),
+                    &mut accounts),
+            Command::SubmitIoc { id, owner, bid, price, quantity } =>
+                book.submit_ioc(order(*id, *owner, *bid, price, quantity),
+                    &mut accounts).map(|_| ()),
+            Command::Cancel { id } => book.cancel(*id as OrderId),
+            Command::Amend { id, price, quantity } => {
+                let r#type: OrderType = book.order(*id as OrderId)
+                    .map_or(OrderType::Bid, |view| view.r#type());
+                book.amend(*id as OrderId, price.value(r#type),
+                    quantity.value(), &mut accounts)
+            }
+        };
+
+        if let Err(violation) = invariant::check(&book, &accounts, &before) {
+            panic!("{} after {:?}", violation, command);
+        }
+    }
+});
@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use calob::decimal::Precision;
+use calob::replay::{self, Format, Replay};
+use calob::scenario::Scenario;
+
+/* whatever the input, the parsers refuse it rather than panic, and anything
+ * they accept can be run */
+fuzz_target!(|text: &str| {
+    for format in [Format::Csv, Format::JsonLines] {
+        let mut replay: Replay = Replay::with_precision("VOC".to_string(),
+            "USD".to_string(), Precision::new(2, 1).unwrap());
+
+        if let Ok(steps) = replay::parse(text, format, replay.book()) {
+            replay.run(&steps);
+        }
+    }
+
+    if let Ok(scenario) = Scenario::parse(text) {
+        let _ = scenario.run();
+    }
+});
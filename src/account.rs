@@ -1,15 +1,26 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
 
 use thiserror::Error;
 
+use crate::book::Quote;
+use crate::decimal::Precision;
+use crate::margin::MarginTerms;
+use crate::order::{OrderPrice, OrderQuantity};
+use crate::pnl::{CostBasis, CostMethod, Pnl};
+use crate::snapshot::{Codec, Decoder, Encoder, SnapshotError};
+
 #[derive(Copy, Clone, Debug, Error)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AccountError {
     InsufficientFunds,
     InsufficientHoldings,
     BalanceOutOfBounds,
     HoldingOutOfBounds,
-    AssetNotFound
+    AssetNotFound,
+    CurrencyNotFound,
+    InsufficientBorrow
 }
 
 impl fmt::Display for AccountError {
@@ -23,31 +34,65 @@ impl fmt::Display for AccountError {
             AccountError::HoldingOutOfBounds =>
                 write!(f, "Holding (or difference in) too large or too small"),
             AccountError::AssetNotFound =>
-                write!(f, "No such asset in portfolio")
+                write!(f, "No such asset in portfolio"),
+            AccountError::CurrencyNotFound =>
+                write!(f, "No such currency in account"),
+            AccountError::InsufficientBorrow =>
+                write!(f, "Short sale exceeds located borrow")
         }
     }
 }
 
+pub type Currency = String;
+
+#[cfg(not(feature = "narrow"))]
 pub type AccountId = u128;
+#[cfg(not(feature = "narrow"))]
 pub type AccountBalance = u128;
+#[cfg(not(feature = "narrow"))]
 pub type AccountHolding = u128;
+#[cfg(not(feature = "narrow"))]
+pub type AccountPosition = i128;
+
+#[cfg(feature = "narrow")]
+pub type AccountId = u64;
+#[cfg(feature = "narrow")]
+pub type AccountBalance = u64;
+#[cfg(feature = "narrow")]
+pub type AccountHolding = u64;
+#[cfg(feature = "narrow")]
+pub type AccountPosition = i64;
+
+pub type Accounts = HashMap<AccountId, Account>;
 
 #[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Account {
     id: AccountId,
     name: String,
-    balance: AccountBalance,
-    holdings: HashMap<String, AccountHolding>
+    balances: HashMap<Currency, AccountBalance>,
+    holdings: HashMap<String, AccountPosition>,
+    cost_basis: HashMap<String, Vec<(Quote, CostBasis)>>,
+    short_selling: bool,
+    borrowed: HashMap<String, AccountHolding>,
+    margin: Option<MarginTerms>,
+    loans: HashMap<Currency, AccountBalance>
 }
 
 impl Account {
-    pub fn new(id: AccountId, name: String, balance: AccountBalance,
-        holdings: HashMap<String, AccountHolding>) -> Self {
+    pub fn new(id: AccountId, name: String,
+        balances: HashMap<Currency, AccountBalance>,
+        holdings: HashMap<String, AccountPosition>) -> Self {
         Account {
             id,
             name,
-            balance,
-            holdings
+            balances,
+            holdings,
+            cost_basis: HashMap::new(),
+            short_selling: false,
+            borrowed: HashMap::new(),
+            margin: None,
+            loans: HashMap::new()
         }
     }    
 
@@ -59,15 +104,24 @@ impl Account {
         self.name.clone()
     }
 
-    pub fn balance(&self) -> AccountBalance {
-        self.balance
+    pub fn balance(&self, currency: Currency) -> Option<AccountBalance> {
+        self.balances.get(&currency).copied()
+    }
+
+    pub fn balances(&self) -> &HashMap<Currency, AccountBalance> {
+        &self.balances
+    }
+
+    pub fn set_balance(&mut self, currency: Currency,
+        balance: AccountBalance) {
+        self.balances.insert(currency, balance);
     }
 
-    pub fn set_balance(&mut self, balance: AccountBalance) {
-        self.balance = balance;
+    pub fn holdings(&self) -> &HashMap<String, AccountPosition> {
+        &self.holdings
     }
 
-    pub fn holding(&self, ticker: String) -> Option<AccountHolding> {
+    pub fn holding(&self, ticker: String) -> Option<AccountPosition> {
         if !self.holdings.contains_key(&ticker) {
             return None;
         }
@@ -75,35 +129,74 @@ impl Account {
         Some(self.holdings[&ticker])
     }
 
-    pub fn set_holding(&mut self, ticker: String, quantity: AccountHolding) {
+    pub fn set_holding(&mut self, ticker: String, quantity: AccountPosition) {
         self.holdings.entry(ticker).or_insert(quantity);
     }
 
-    pub fn add_balance(&mut self, amount: AccountBalance) ->
-        Result<(), AccountError> {
-        /* bounds check */
-        if self.balance.checked_add(amount).is_none() {
+    /* on margin accounts, credits go towards repaying any loan first */
+    pub fn add_balance(&mut self, currency: Currency,
+        amount: AccountBalance) -> Result<(), AccountError> {
+        let repaid: AccountBalance = match self.loans.get(&currency) {
+            Some(loan) => amount.min(*loan),
+            None => 0
+        };
+        let amount: AccountBalance = amount - repaid;
+
+        /* bounds check (before repaying anything, so a failure changes
+         * nothing) */
+        if self.balances.get(&currency).copied().unwrap_or(0)
+            .checked_add(amount).is_none() {
             return Err(AccountError::BalanceOutOfBounds);
         }
-        
-        self.balance += amount;
+
+        if let Some(loan) = self.loans.get_mut(&currency) {
+            *loan -= repaid;
+        }
+
+        *self.balances.entry(currency).or_insert(0) += amount;
         Ok(())
     }
 
-    pub fn take_balance(&mut self, amount: AccountBalance) ->
-        Result<(), AccountError> {
+    /* on margin accounts, any shortfall in the margin currency is borrowed */
+    pub fn take_balance(&mut self, currency: Currency,
+        amount: AccountBalance) -> Result<(), AccountError> {
+        if self.is_margin_currency(&currency) {
+            let balance: &mut AccountBalance =
+                self.balances.entry(currency.clone()).or_insert(0);
+            let shortfall: AccountBalance = amount.saturating_sub(*balance);
+            let loan: &mut AccountBalance =
+                self.loans.entry(currency).or_insert(0);
+
+            /* bounds check */
+            if loan.checked_add(shortfall).is_none() {
+                return Err(AccountError::BalanceOutOfBounds);
+            }
+
+            *loan += shortfall;
+            *balance -= amount - shortfall;
+            return Ok(());
+        }
+
+        let balance: &mut AccountBalance = match self.balances
+            .get_mut(&currency) {
+            Some(b) => b,
+            None => return Err(AccountError::CurrencyNotFound)
+        };
+
         /* bounds check */
-        if amount > self.balance {
+        if amount > *balance {
             return Err(AccountError::BalanceOutOfBounds);
         }
 
-        self.balance -= amount;
+        *balance -= amount;
         Ok(())
     }
 
     #[allow(clippy::map_entry)]
     pub fn add_holding(&mut self, ticker: String, amount: AccountHolding) ->
         Result<(), AccountError> {
+        let amount: AccountPosition = Account::to_position(amount)?;
+
         if !self.holdings.contains_key(&ticker) {
             self.holdings.insert(ticker, amount);
         } else {
@@ -121,6 +214,26 @@ impl Account {
 
     pub fn take_holding(&mut self, ticker: String, amount: AccountHolding) ->
         Result<(), AccountError> {
+        let amount: AccountPosition = Account::to_position(amount)?;
+
+        if self.short_selling {
+            let position: AccountPosition =
+                self.holdings.get(&ticker).copied().unwrap_or(0);
+            let floor: AccountPosition =
+                -Account::to_position(self.borrowed(ticker.clone()))?;
+
+            /* bounds check */
+            match position.checked_sub(amount) {
+                Some(p) if p < floor =>
+                    return Err(AccountError::InsufficientBorrow),
+                Some(p) => {
+                    self.holdings.insert(ticker, p);
+                    return Ok(());
+                },
+                None => return Err(AccountError::HoldingOutOfBounds)
+            }
+        }
+
         /* bounds check */
         if !self.holdings.contains_key(&ticker) {
             return Err(AccountError::AssetNotFound);
@@ -133,6 +246,247 @@ impl Account {
         *self.holdings.get_mut(&ticker).unwrap() -= amount;
         Ok(())
     }
+
+    pub fn margin(&self) -> Option<&MarginTerms> {
+        self.margin.as_ref()
+    }
+
+    pub fn set_margin(&mut self, margin: Option<MarginTerms>) {
+        self.margin = margin;
+    }
+
+    pub fn loan(&self, currency: Currency) -> AccountBalance {
+        self.loans.get(&currency).copied().unwrap_or(0)
+    }
+
+    pub fn loans(&self) -> &HashMap<Currency, AccountBalance> {
+        &self.loans
+    }
+
+    pub fn short_selling(&self) -> bool {
+        self.short_selling
+    }
+
+    pub fn set_short_selling(&mut self, short_selling: bool) {
+        self.short_selling = short_selling;
+    }
+
+    pub fn short_position(&self, ticker: String) -> AccountHolding {
+        match self.holdings.get(&ticker) {
+            Some(p) if *p < 0 => p.unsigned_abs(),
+            _ => 0
+        }
+    }
+
+    pub fn borrowed(&self, ticker: String) -> AccountHolding {
+        self.borrowed.get(&ticker).copied().unwrap_or(0)
+    }
+
+    pub fn borrows(&self) -> &HashMap<String, AccountHolding> {
+        &self.borrowed
+    }
+
+    pub fn add_borrow(&mut self, ticker: String, amount: AccountHolding) ->
+        Result<(), AccountError> {
+        let borrowed: &mut AccountHolding =
+            self.borrowed.entry(ticker).or_insert(0);
+
+        /* bounds check */
+        if borrowed.checked_add(amount).is_none() {
+            return Err(AccountError::HoldingOutOfBounds);
+        }
+
+        *borrowed += amount;
+        Ok(())
+    }
+
+    /* borrowed shares still backing an open short cannot be returned */
+    pub fn take_borrow(&mut self, ticker: String, amount: AccountHolding) ->
+        Result<(), AccountError> {
+        let short: AccountHolding = self.short_position(ticker.clone());
+        let borrowed: AccountHolding = self.borrowed(ticker.clone());
+
+        /* bounds check */
+        if amount > borrowed || borrowed - amount < short {
+            return Err(AccountError::InsufficientBorrow);
+        }
+
+        self.borrowed.insert(ticker, borrowed - amount);
+        Ok(())
+    }
+
+    /* a ticker traded against more than one quote keeps a separate basis for
+     * each, as costs in different units cannot be added together */
+    pub fn cost_basis(&self, ticker: String, quote: &Quote) ->
+        Option<&CostBasis> {
+        self.cost_basis.get(&ticker)?.iter()
+            .find(|(q, _)| q == quote)
+            .map(|(_, basis)| basis)
+    }
+
+    fn cost_basis_mut(&mut self, ticker: String, quote: &Quote,
+        precision: Precision) -> &mut CostBasis {
+        let bases: &mut Vec<(Quote, CostBasis)> =
+            self.cost_basis.entry(ticker).or_default();
+        let index: usize = match bases.iter().position(|(q, _)| q == quote) {
+            Some(i) => i,
+            None => {
+                bases.push((quote.clone(),
+                    CostBasis::with_precision(precision)));
+                bases.len() - 1
+            }
+        };
+
+        &mut bases[index].1
+    }
+
+    pub fn record_purchase(&mut self, ticker: String, quote: &Quote,
+        precision: Precision, price: OrderPrice, quantity: OrderQuantity) {
+        let basis: &mut CostBasis =
+            self.cost_basis_mut(ticker, quote, precision);
+        let covered: OrderQuantity = quantity.min(basis.short_quantity());
+
+        basis.cover(price, covered);
+        basis.buy(price, quantity - covered);
+    }
+
+    /* the holding is expected to already reflect the sale, so that any part
+     * of it which opened a short position can be told apart */
+    pub fn record_sale(&mut self, ticker: String, quote: &Quote,
+        precision: Precision, price: OrderPrice, quantity: OrderQuantity) {
+        let position: AccountPosition =
+            self.holdings.get(&ticker).copied().unwrap_or(0);
+        let opened: OrderQuantity = if position < 0 {
+            quantity.min(position.unsigned_abs())
+        } else {
+            0
+        };
+
+        let basis: &mut CostBasis =
+            self.cost_basis_mut(ticker, quote, precision);
+        basis.sell(price, quantity - opened);
+        basis.sell_short(price, opened);
+    }
+
+    pub fn realized_pnl(&self, ticker: String, quote: &Quote,
+        method: CostMethod) -> Pnl {
+        match self.cost_basis(ticker, quote) {
+            Some(basis) => basis.realized(method),
+            None => 0
+        }
+    }
+
+    pub fn unrealized_pnl(&self, ticker: String, quote: &Quote,
+        mark: OrderPrice, method: CostMethod) -> Pnl {
+        match self.cost_basis(ticker, quote) {
+            Some(basis) => basis.unrealized(mark, method),
+            None => 0
+        }
+    }
+
+    /* realized P&L across every ticker quoted in the given unit */
+    pub fn total_realized_pnl(&self, quote: &Quote, method: CostMethod) ->
+        Pnl {
+        self.cost_basis.values()
+            .flat_map(|bases| bases.iter())
+            .filter(|(q, _)| q == quote)
+            .map(|(_, basis)| basis.realized(method))
+            .sum()
+    }
+
+    fn is_margin_currency(&self, currency: &str) -> bool {
+        match &self.margin {
+            Some(terms) => terms.currency() == currency,
+            None => false
+        }
+    }
+
+    fn to_position(amount: AccountHolding) ->
+        Result<AccountPosition, AccountError> {
+        match AccountPosition::try_from(amount) {
+            Ok(a) => Ok(a),
+            Err(_) => Err(AccountError::HoldingOutOfBounds)
+        }
+    }
+}
+
+/* the entries of an account which settling a trade in some ticker may
+ * touch, so that a settlement failing part way through can be wound back
+ * exactly (including entries which did not exist beforehand) */
+#[derive(Clone, Debug)]
+pub(crate) struct Savepoint {
+    balances: Vec<(Currency, Option<AccountBalance>)>,
+    loans: Vec<(Currency, Option<AccountBalance>)>,
+    holdings: Vec<(String, Option<AccountPosition>)>
+}
+
+impl Account {
+    pub(crate) fn savepoint(&self, ticker: &str, quote: &Quote) -> Savepoint {
+        let mut savepoint: Savepoint = Savepoint {
+            balances: vec![],
+            loans: vec![],
+            holdings: vec![(ticker.to_string(),
+                self.holdings.get(ticker).copied())]
+        };
+
+        match quote {
+            Quote::Currency(currency) => {
+                savepoint.balances.push((currency.clone(),
+                    self.balances.get(currency).copied()));
+                savepoint.loans.push((currency.clone(),
+                    self.loans.get(currency).copied()));
+            },
+            Quote::Asset(asset) => savepoint.holdings.push((asset.clone(),
+                self.holdings.get(asset).copied()))
+        }
+
+        savepoint
+    }
+
+    pub(crate) fn restore(&mut self, savepoint: Savepoint) {
+        fn put<K: std::hash::Hash + Eq, V>(map: &mut HashMap<K, V>,
+            entries: Vec<(K, Option<V>)>) {
+            /* in reverse, so the earliest copy of a key wins */
+            for (key, value) in entries.into_iter().rev() {
+                match value {
+                    Some(v) => map.insert(key, v),
+                    None => map.remove(&key)
+                };
+            }
+        }
+
+        put(&mut self.balances, savepoint.balances);
+        put(&mut self.loans, savepoint.loans);
+        put(&mut self.holdings, savepoint.holdings);
+    }
+}
+
+impl Codec for Account {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.id.encode(encoder);
+        self.name.encode(encoder);
+        self.balances.encode(encoder);
+        self.holdings.encode(encoder);
+        self.cost_basis.encode(encoder);
+        self.short_selling.encode(encoder);
+        self.borrowed.encode(encoder);
+        self.margin.encode(encoder);
+        self.loans.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(Account {
+            id: Codec::decode(decoder)?,
+            name: Codec::decode(decoder)?,
+            balances: Codec::decode(decoder)?,
+            holdings: Codec::decode(decoder)?,
+            cost_basis: Codec::decode(decoder)?,
+            short_selling: Codec::decode(decoder)?,
+            borrowed: Codec::decode(decoder)?,
+            margin: Codec::decode(decoder)?,
+            loans: Codec::decode(decoder)?
+        })
+    }
 }
 
 #[cfg(test)]
@@ -143,20 +497,132 @@ mod tests {
     fn test_new_normal() {
         let account_id: AccountId = 12;
         let account_name: String = "John Doe".to_string();
-        let account_balance: AccountBalance = 33000;
-        let account_holdings: HashMap<String, AccountHolding> = HashMap::new();
+        let mut account_balances: HashMap<Currency, AccountBalance> =
+            HashMap::new();
+        account_balances.insert("USD".to_string(), 33000);
+        let account_holdings: HashMap<String, AccountPosition> =
+            HashMap::new();
         
         let expected_account: Account = Account {
             id: account_id,
             name: account_name.clone(),
-            balance: account_balance,
-            holdings: account_holdings.clone()
+            balances: account_balances.clone(),
+            holdings: account_holdings.clone(),
+            cost_basis: HashMap::new(),
+            short_selling: false,
+            borrowed: HashMap::new(),
+            margin: None,
+            loans: HashMap::new()
         };
         
         let actual_account: Account = Account::new(account_id, account_name,
-                                        account_balance, account_holdings);
+                                        account_balances, account_holdings);
         
         assert_eq!(actual_account, expected_account);
     }
-}
 
+    #[test]
+    fn test_realized_pnl_normal() {
+        let usd: Quote = Quote::Currency("USD".to_string());
+        let mut actual_account: Account = Account::new(12,
+            "John Doe".to_string(), HashMap::new(), HashMap::new());
+
+        actual_account.record_purchase("VOC".to_string(), &usd,
+            Precision::default(), 100, 10);
+        actual_account.record_purchase("VOC".to_string(), &usd,
+            Precision::default(), 120, 10);
+        actual_account.record_sale("VOC".to_string(), &usd,
+            Precision::default(), 130, 10);
+
+        assert_eq!(actual_account.realized_pnl("VOC".to_string(), &usd,
+            CostMethod::Average), 200);
+        assert_eq!(actual_account.realized_pnl("VOC".to_string(), &usd,
+            CostMethod::Fifo), 300);
+        assert_eq!(actual_account.unrealized_pnl("VOC".to_string(), &usd, 110,
+            CostMethod::Fifo), -100);
+        assert_eq!(actual_account.realized_pnl("MSFT".to_string(), &usd,
+            CostMethod::Fifo), 0);
+    }
+
+    #[test]
+    fn test_realized_pnl_per_quote() {
+        let usd: Quote = Quote::Currency("USD".to_string());
+        let eur: Quote = Quote::Currency("EUR".to_string());
+        let mut actual_account: Account = Account::new(12,
+            "John Doe".to_string(), HashMap::new(), HashMap::new());
+
+        actual_account.record_purchase("VOC".to_string(), &usd,
+            Precision::default(), 100, 10);
+        actual_account.record_purchase("VOC".to_string(), &eur,
+            Precision::default(), 90, 10);
+        actual_account.record_sale("VOC".to_string(), &usd,
+            Precision::default(), 110, 10);
+
+        /* the sale in dollars draws only on the lots bought in dollars */
+        assert_eq!(actual_account.realized_pnl("VOC".to_string(), &usd,
+            CostMethod::Fifo), 100);
+        assert_eq!(actual_account.cost_basis("VOC".to_string(), &eur)
+            .map(|basis| basis.quantity()), Some(10));
+        assert_eq!(actual_account.total_realized_pnl(&eur,
+            CostMethod::Average), 0);
+    }
+
+    #[test]
+    fn test_take_balance_currency_not_found() {
+        let mut balances: HashMap<Currency, AccountBalance> = HashMap::new();
+        balances.insert("USD".to_string(), 100);
+
+        let mut actual_account: Account = Account::new(12,
+            "John Doe".to_string(), balances, HashMap::new());
+
+        assert!(matches!(actual_account.take_balance("EUR".to_string(), 10),
+            Err(AccountError::CurrencyNotFound)));
+        assert!(actual_account.take_balance("USD".to_string(), 10).is_ok());
+        assert!(actual_account.add_balance("JPY".to_string(), 5000).is_ok());
+
+        assert_eq!(actual_account.balance("USD".to_string()), Some(90));
+        assert_eq!(actual_account.balance("JPY".to_string()), Some(5000));
+        assert_eq!(actual_account.balance("EUR".to_string()), None);
+    }
+
+    #[test]
+    fn test_take_holding_short() {
+        let mut actual_account: Account = Account::new(12,
+            "John Doe".to_string(), HashMap::new(), HashMap::new());
+
+        assert!(matches!(actual_account.take_holding("VOC".to_string(), 10),
+            Err(AccountError::AssetNotFound)));
+
+        actual_account.set_short_selling(true);
+
+        assert!(matches!(actual_account.take_holding("VOC".to_string(), 10),
+            Err(AccountError::InsufficientBorrow)));
+
+        assert!(actual_account.add_borrow("VOC".to_string(), 10).is_ok());
+        assert!(actual_account.take_holding("VOC".to_string(), 10).is_ok());
+        assert!(matches!(actual_account.take_borrow("VOC".to_string(), 5),
+            Err(AccountError::InsufficientBorrow)));
+
+        assert_eq!(actual_account.holding("VOC".to_string()), Some(-10));
+        assert_eq!(actual_account.short_position("VOC".to_string()), 10);
+    }
+
+    #[test]
+    fn test_take_balance_margin_loan() {
+        let mut balances: HashMap<Currency, AccountBalance> = HashMap::new();
+        balances.insert("USD".to_string(), 100);
+
+        let mut actual_account: Account = Account::new(12,
+            "John Doe".to_string(), balances, HashMap::new());
+        actual_account.set_margin(Some(MarginTerms::new("USD".to_string(), 2,
+            2500)));
+
+        assert!(actual_account.take_balance("USD".to_string(), 150).is_ok());
+        assert_eq!(actual_account.balance("USD".to_string()), Some(0));
+        assert_eq!(actual_account.loan("USD".to_string()), 50);
+
+        assert!(actual_account.add_balance("USD".to_string(), 80).is_ok());
+        assert_eq!(actual_account.balance("USD".to_string()), Some(30));
+        assert_eq!(actual_account.loan("USD".to_string()), 0);
+    }
+}
@@ -1,43 +1,259 @@
-#![allow(unused_assignments)]
-use std::collections::{BTreeMap, VecDeque};
 use std::cmp::Ordering;
+use std::fmt;
 
+use crate::account::{Account, AccountBalance, AccountError, AccountId,
+    AccountPosition, Accounts, Currency, Savepoint};
+use crate::decimal::{DecimalError, Precision, Price, Quantity};
+use crate::execution::{ExecId, ExecutionReport, Executions, OrderState};
+use crate::listener::{BookListener, Listeners, Trade};
 use crate::order::*;
+use crate::pnl::{CostMethod, Pnl};
+use crate::side::{Band, BookSide, Orders};
+use crate::snapshot::{Codec, Decoder, Encoder, SnapshotError};
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BookError {
-    OrderNotFound
+    OrderNotFound,
+    DuplicateOrder,
+    InvalidQuantity,
+    AccountNotFound,
+    ShortSaleRestricted,
+    NotionalOverflow,
+    InvalidBand,
+    PriceOutOfBand,
+    AccountsUnavailable,
+    Account(AccountError)
 }
 
-type PriceLabel = OrderPrice;
-type OrderQueue<'a> = VecDeque<&'a mut Order<'a>>;
-pub type Side<'a> = BTreeMap<PriceLabel, OrderQueue<'a>>;
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BookError::OrderNotFound => write!(f, "No such order in book"),
+            BookError::DuplicateOrder =>
+                write!(f, "Order ID has already been used"),
+            BookError::InvalidQuantity => write!(f, "Quantity must be nonzero"),
+            BookError::AccountNotFound => write!(f, "No such account"),
+            BookError::ShortSaleRestricted =>
+                write!(f, "Short sale restriction in force"),
+            BookError::NotionalOverflow =>
+                write!(f, "Price times quantity is too large"),
+            BookError::InvalidBand =>
+                write!(f, "Price band is empty, misaligned or too wide"),
+            BookError::PriceOutOfBand =>
+                write!(f, "Price is outside the book's band or off its tick"),
+            BookError::AccountsUnavailable =>
+                write!(f, "Accounts cannot be reached"),
+            BookError::Account(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl From<AccountError> for BookError {
+    fn from(e: AccountError) -> Self {
+        BookError::Account(e)
+    }
+}
 
 pub type BookId = u128;
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Tick {
+    Up,
+    Down,
+    #[default]
+    Unchanged
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Level {
+    price: OrderPrice,
+    quantity: OrderQuantity,
+    orders: usize
+}
+
+impl Level {
+    pub fn new(price: OrderPrice, quantity: OrderQuantity, orders: usize) ->
+        Self {
+        Level {
+            price,
+            quantity,
+            orders
+        }
+    }
+
+    fn from_queue(price: OrderPrice, orders: Orders) -> Self {
+        let count: usize = orders.len();
+
+        Level::new(price, Book::total_quantity(orders), count)
+    }
+
+    pub fn price(&self) -> OrderPrice {
+        self.price
+    }
+
+    pub fn quantity(&self) -> OrderQuantity {
+        self.quantity
+    }
+
+    pub fn orders(&self) -> usize {
+        self.orders
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderView {
+    id: OrderId,
+    owner: AccountId,
+    order_type: OrderType,
+    price: OrderPrice,
+    quantity: OrderQuantity,
+    position: usize
+}
+
+impl OrderView {
+    fn from_order(order: &Order, position: usize) -> Self {
+        OrderView {
+            id: order.id(),
+            owner: order.owner(),
+            order_type: order.r#type(),
+            price: order.price(),
+            quantity: order.quantity(),
+            position
+        }
+    }
+
+    pub fn id(&self) -> OrderId {
+        self.id
+    }
+
+    pub fn owner(&self) -> AccountId {
+        self.owner
+    }
+
+    pub fn r#type(&self) -> OrderType {
+        self.order_type
+    }
+
+    pub fn price(&self) -> OrderPrice {
+        self.price
+    }
+
+    pub fn quantity(&self) -> OrderQuantity {
+        self.quantity
+    }
+
+    /* place in the queue at this order's price level, zero being the front */
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Quote {
+    Currency(Currency),
+    Asset(String)
+}
+
+impl Default for Quote {
+    fn default() -> Self {
+        Quote::Currency(Currency::new())
+    }
+}
+
+impl Quote {
+    /* the currency or asset quoted in */
+    pub fn symbol(&self) -> String {
+        match self {
+            Quote::Currency(currency) => currency.clone(),
+            Quote::Asset(ticker) => ticker.clone()
+        }
+    }
+
+    pub fn credit(&self, account: &mut Account, amount: AccountBalance) ->
+        Result<(), AccountError> {
+        match self {
+            Quote::Currency(currency) =>
+                account.add_balance(currency.clone(), amount),
+            Quote::Asset(ticker) => account.add_holding(ticker.clone(), amount)
+        }
+    }
+
+    pub fn debit(&self, account: &mut Account, amount: AccountBalance) ->
+        Result<(), AccountError> {
+        match self {
+            Quote::Currency(currency) =>
+                account.take_balance(currency.clone(), amount),
+            Quote::Asset(ticker) => account.take_holding(ticker.clone(), amount)
+        }
+    }
+}
+
+/* what a book checks orders and settles trades against: either accounts of
+ * its own, or accounts shared with books elsewhere, which are then reached
+ * only for each check or trade rather than for a whole request */
+pub trait Ledger {
+    fn with<R>(&mut self, f: impl FnOnce(&mut Accounts) -> R) ->
+        Result<R, BookError>;
+}
+
+impl Ledger for Accounts {
+    fn with<R>(&mut self, f: impl FnOnce(&mut Accounts) -> R) ->
+        Result<R, BookError> {
+        Ok(f(self))
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Default)]
-pub struct Book<'a> {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Book {
     id: BookId,
     name: String,
     ticker: String,
-    bids: Side<'a>,
-    asks: Side<'a>,
+    quote: Quote,
+    precision: Precision,
+    bids: BookSide,
+    asks: BookSide,
     ltp: OrderPrice,
     has_traded: bool,
-    order_ids: Vec<OrderId>
+    tick: Tick,
+    short_sale_restriction: bool,
+    executions: Executions,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    listeners: Listeners
 }
 
-impl<'a> Book<'a> {
-    pub fn new(id: BookId, name: String, ticker: String) -> Self {
+impl Book {
+    pub fn new(id: BookId, name: String, ticker: String, currency: Currency) ->
+        Self {
+        Book::with_quote(id, name, ticker, Quote::Currency(currency))
+    }
+
+    pub fn new_pair(id: BookId, name: String, base: String, quote: String) ->
+        Self {
+        Book::with_quote(id, name, base, Quote::Asset(quote))
+    }
+
+    pub fn with_quote(id: BookId, name: String, ticker: String, quote: Quote) ->
+        Self {
         Book {
             id,
             name,
             ticker,
-            bids: Side::new(),
-            asks: Side::new(),
+            quote,
+            precision: Precision::default(),
+            bids: BookSide::default(),
+            asks: BookSide::default(),
             ltp: 0,
             has_traded: false,
-            order_ids: vec![]
+            tick: Tick::Unchanged,
+            short_sale_restriction: false,
+            executions: Executions::new(),
+            listeners: Listeners::new()
         }
     }
 
@@ -53,6 +269,21 @@ impl<'a> Book<'a> {
         self.ticker.clone()
     }
 
+    pub fn base(&self) -> String {
+        self.ticker.clone()
+    }
+
+    pub fn quote(&self) -> Quote {
+        self.quote.clone()
+    }
+
+    pub fn currency(&self) -> Option<Currency> {
+        match &self.quote {
+            Quote::Currency(currency) => Some(currency.clone()),
+            Quote::Asset(_) => None
+        }
+    }
+
     pub fn ltp(&self) -> Option<OrderPrice> {
         if self.has_traded {
             Some(self.ltp)
@@ -61,278 +292,706 @@ impl<'a> Book<'a> {
         }
     }
 
-    pub fn submit(&mut self, order: &'a mut Order<'a>) ->
-        Result<(), BookError> {
-        let order_id: OrderId = order.id();
-        let price_key: OrderPrice = order.price();
-        let order_quantity: OrderQuantity = order.quantity();
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
 
-        match order.r#type() {
-            OrderType::Bid => {
-                let mut matched: bool = false;
-                
-                if self.top().1.is_some() &&
-                    price_key >= self.top().1.unwrap() {
-                    for curr_queue in self.asks.values_mut() {
-                        for _i in 0..curr_queue.len() {
-                            let counter_order = curr_queue.pop_front().unwrap();
-                            let counter_order_done: bool;
-                            let curr_price: OrderPrice = counter_order.price();
-                    
-                            if curr_price <= price_key {
-                                let counter_quantity: OrderPrice =
-                                                    counter_order.quantity();
-                                match counter_quantity.cmp(&order_quantity) {
-                                    Ordering::Less => {
-                                        Book::payout_order(self.ticker.clone(),
-                                            counter_order, None, None)?;
-                                        Book::payout_order(self.ticker.clone(),
-                                            order, Some(curr_price),
-                                            Some(counter_quantity))?;
-                                    
-                                        /* remove counter order as it is consumed */
-                                        counter_order_done = true;
-                                        Book::remove_id(&mut self.order_ids,
-                                            counter_order.id());
-                                    },
-                                    Ordering::Equal => {
-                                        Book::payout_order(self.ticker.clone(),
-                                            counter_order, None, None)?;
-                                        Book::payout_order(self.ticker.clone(),
-                                            order, Some(curr_price),
-                                            Some(counter_quantity))?;
-                                        
-                                        /* remove counter order as it is consumed */
-                                        counter_order_done = true;
-                                        Book::remove_id(&mut self.order_ids,
-                                            counter_order.id());
-                                        
-                                        self.has_traded = true;
-                                        self.ltp = price_key;
-                                        matched = true;
-                                        break;
-                                    },
-                                    Ordering::Greater => {
-                                        Book::payout_order(self.ticker.clone(),
-                                            counter_order, None, None)?;
-                                        Book::payout_order(self.ticker.clone(),
-                                        order, Some(curr_price),
-                                        Some(counter_quantity))?;
-                                        
-                                        self.has_traded = true;
-                                        self.ltp = price_key;
-                                        matched = true;
-                                        break;
-                                    }
-                                }
-                                
-                                /* restore counter order if not consumed */
-                                if !counter_order_done {     
-                                    curr_queue.push_back(counter_order);
-                                }
-                            } else {
-                                curr_queue.push_back(counter_order);
-                            }
-                        }
-                    }
-                }
-                
-                if !matched {
-                    self.bids.insert(price_key, VecDeque::new());
-                    self.bids.get_mut(&price_key).unwrap().push_back(order);
-                    self.order_ids.push(order_id);
-                }
-            },
-            OrderType::Ask => {
-                let mut matched: bool = false;
-                if self.top().0.is_some() &&
-                    price_key <= self.top().0.unwrap() {
-                    for curr_queue in self.bids.values_mut() {
-                        for _i in 0..curr_queue.len() {
-                            let counter_order = curr_queue.pop_front().unwrap();
-                            let counter_order_done: bool;
-                            let curr_price: OrderPrice = counter_order.price();
-                            
-                            if curr_price >= price_key {
-                                let counter_quantity: OrderPrice =
-                                                    counter_order.quantity();
-                            
-                                match counter_quantity.cmp(&order_quantity) {
-                                    Ordering::Less => {
-                                        Book::payout_order(self.ticker.clone(),
-                                            counter_order, None, None)?;
-                                        Book::payout_order(self.ticker.clone(),
-                                            order, None, Some(counter_quantity))?;
-                                        
-                                        /* remove counter order as it is consumed */
-                                        counter_order_done = true;
-                                        Book::remove_id(&mut self.order_ids,
-                                            counter_order.id());
-                                    }, Ordering::Equal => {
-                                        Book::payout_order(self.ticker.clone(),
-                                            counter_order, None, None)?;
-                                        Book::payout_order(self.ticker.clone(),
-                                            order, None, Some(counter_quantity))?;
-                                        
-                                        /* remove counter order as it is consumed */
-                                        counter_order_done = true;
-                                        Book::remove_id(&mut self.order_ids,
-                                            counter_order.id());
-                                        
-                                        self.has_traded = true;
-                                        self.ltp = price_key;
-                                        matched = true;
-                                        break;
-                                    }, Ordering::Greater => {
-                                        Book::payout_order(self.ticker.clone(),
-                                            counter_order, None, None)?;
-                                        Book::payout_order(self.ticker.clone(),
-                                            order, Some(curr_price),
-                                            Some(counter_quantity))?;
-                                        
-                                        self.has_traded = true;
-                                        self.ltp = price_key;
-                                        matched = true;
-                                        break;
-                                    }
-                                }
-                                
-                                /* restore counter order if not consumed */
-                                if !counter_order_done {     
-                                    curr_queue.push_back(counter_order);
-                                }
-                            } else {
-                                curr_queue.push_back(counter_order);
-                            }
-                        }
-                    }
-                }
-                
-                if !matched {
-                    self.asks.insert(price_key, VecDeque::new());
-                    self.asks.get_mut(&price_key).unwrap().push_back(order);
-                    self.order_ids.push(order_id);
+    pub fn short_sale_restriction(&self) -> bool {
+        self.short_sale_restriction
+    }
+
+    pub fn set_short_sale_restriction(&mut self, restricted: bool) {
+        self.short_sale_restriction = restricted;
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    /* resting orders are not rescaled, so this is for new books only */
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.precision = precision;
+    }
+
+    /* a book with a band keeps its levels in a ladder over the band, and
+     * only lets orders rest at the prices in it; one without keeps them in
+     * a tree, and lets them rest anywhere */
+    pub fn band(&self) -> Option<Band> {
+        self.bids.band()
+    }
+
+    /* the resting orders move across, unless any is outside the new band */
+    pub fn set_band(&mut self, band: Option<Band>) -> Result<(), BookError> {
+        let mut bids: BookSide = BookSide::new(band);
+        let mut asks: BookSide = BookSide::new(band);
+
+        for (to, from) in [(&mut bids, &self.bids), (&mut asks, &self.asks)] {
+            for order in from.orders() {
+                if !to.accepts(order.price()) {
+                    return Err(BookError::PriceOutOfBand);
                 }
+
+                to.push(order.clone());
             }
+        }
+
+        self.bids = bids;
+        self.asks = asks;
+        Ok(())
+    }
+
+    pub fn price(&self, units: OrderPrice) -> Price {
+        Price::new(units, self.precision.price())
+    }
+
+    pub fn quantity(&self, units: OrderQuantity) -> Quantity {
+        Quantity::new(units, self.precision.quantity())
+    }
+
+    pub fn parse_price(&self, text: &str) -> Result<Price, DecimalError> {
+        Price::parse(text, self.precision.price())
+    }
+
+    pub fn parse_quantity(&self, text: &str) ->
+        Result<Quantity, DecimalError> {
+        Quantity::parse(text, self.precision.quantity())
+    }
+
+    pub fn add_listener(&mut self, listener: Box<dyn BookListener + Send>) {
+        self.listeners.add(listener);
+    }
+
+    pub fn submit(&mut self, order: Order, accounts: &mut impl Ledger) ->
+        Result<(), BookError> {
+        self.observed(|book| book.place(order, accounts))
+    }
+
+    /* immediate-or-cancel: whatever does not match straight away is
+     * discarded rather than rested, and the quantity filled is returned */
+    pub fn submit_ioc(&mut self, order: Order, accounts: &mut impl Ledger)
+        -> Result<OrderQuantity, BookError> {
+        self.observed(|book| book.place_ioc(order, accounts))
+    }
+
+    pub fn cancel(&mut self, id: OrderId) -> Result<(), BookError> {
+        self.observed(|book| match book.remove_order(id) {
+            Some(order) => {
+                let report: ExecutionReport = book.executions.cancel(&order);
+                book.listeners.cancel(book.id, &report);
+                Ok(())
+            },
+            None => Err(BookError::OrderNotFound)
+        })
+    } 
+
+    /* reducing an order's quantity keeps its place in the queue; any other
+     * change sends it to the back of the queue at its (new) price, where it
+     * may match straight away */
+    pub fn amend(&mut self, id: OrderId, price: OrderPrice,
+        quantity: OrderQuantity, accounts: &mut impl Ledger) ->
+        Result<(), BookError> {
+        self.observed(|book| book.replace(id, price, quantity, accounts))
+    }
+
+    pub fn expire(&mut self, id: OrderId) -> Result<(), BookError> {
+        self.observed(|book| match book.remove_order(id) {
+            Some(order) => {
+                let report: ExecutionReport = book.executions.expire(&order);
+                book.listeners.cancel(book.id, &report);
+                Ok(())
+            },
+            None => Err(BookError::OrderNotFound)
+        })
+    }
+
+    fn place(&mut self, mut order: Order, accounts: &mut impl Ledger) ->
+        Result<(), BookError> {
+        self.accept(&order, accounts, true)?;
+        self.execute(&mut order, accounts)?;
+        self.rest(order);
+
+        Ok(())
+    }
+
+    fn replace(&mut self, id: OrderId, price: OrderPrice,
+        quantity: OrderQuantity, accounts: &mut impl Ledger) ->
+        Result<(), BookError> {
+        if quantity == 0 {
+            return Err(BookError::InvalidQuantity);
+        }
+
+        let view: OrderView = match self.order(id) {
+            Some(v) => v,
+            None => return Err(BookError::OrderNotFound)
         };
 
-        Book::prune_side(&mut self.bids);
-        Book::prune_side(&mut self.asks);
+        if price == view.price() && quantity <= view.quantity() {
+            let side: &mut BookSide = match view.r#type() {
+                OrderType::Bid => &mut self.bids,
+                OrderType::Ask => &mut self.asks
+            };
+            let order: &mut Order = side.get_mut(id).unwrap();
+
+            order.amend(price, quantity);
+            let report: ExecutionReport = self.executions.replace(order);
+            self.listeners.amended(self.id, &report);
+            self.listeners.touch(view.r#type(), price);
+            return Ok(());
+        }
+
+        let amended: Order = Order::new(id, view.owner(), view.r#type(), price,
+            quantity);
+
+        self.validate(&amended, accounts, true)?;
+
+        let mut order: Order = self.remove_order(id).unwrap();
+        order.amend(price, quantity);
+
+        let report: ExecutionReport = self.executions.replace(&order);
+        self.listeners.amended(self.id, &report);
+
+        self.execute(&mut order, accounts)?;
+        self.rest(order);
 
         Ok(())
     }
 
-    pub fn cancel(&mut self, id: OrderId) -> Result<(), BookError> {
-        if !self.order_ids.contains(&id) {
-            return Err(BookError::OrderNotFound);
+    /* rest whatever is left of the order */
+    fn rest(&mut self, order: Order) {
+        if order.quantity() == 0 {
+            return;
         }
 
-        for (_curr_price, curr_queue) in self.bids.iter_mut() {
-            let mut index: usize = 0;
-            let mut found: bool = false;            
+        self.listeners.touch(order.r#type(), order.price());
+        self.side_mut(order.r#type()).push(order);
+    }
 
-            for curr_order in curr_queue.iter() {
-                if curr_order.id() == id {
-                    found = true;
-                    break;
-                }
+    fn place_ioc(&mut self, mut order: Order, accounts: &mut impl Ledger) ->
+        Result<OrderQuantity, BookError> {
+        let order_quantity: OrderQuantity = order.quantity();
 
-                index += 1;
-            }
+        self.accept(&order, accounts, false)?;
+        self.execute(&mut order, accounts)?;
 
-            if found {
-                curr_queue.remove(index);
-                return Ok(());
-            }
+        if order.quantity() > 0 {
+            let report: ExecutionReport = self.executions.cancel(&order);
+            self.listeners.cancel(self.id, &report);
         }
-        
-        Ok(())
-    } 
+
+        Ok(order_quantity - order.quantity())
+    }
+
+    /* also answers for orders which have since left the book */
+    pub fn order_status(&self, id: OrderId) -> Option<OrderStatus> {
+        self.executions.status(id)
+    }
+
+    pub fn order_state(&self, id: OrderId) -> Option<OrderState> {
+        self.executions.state(id)
+    }
+
+    /* only the latest `REPORT_LIMIT` or so are held, so callers who want
+     * every report should take them after each request */
+    pub fn reports(&self) -> &[ExecutionReport] {
+        self.executions.reports()
+    }
+
+    pub fn take_reports(&mut self) -> Vec<ExecutionReport> {
+        self.executions.take_reports()
+    }
+
+    /* see `Executions::acknowledge` */
+    pub fn acknowledge(&mut self, exec_id: ExecId) {
+        self.executions.acknowledge(exec_id);
+    }
+
+    /* see `Executions::forget` */
+    pub fn forget(&mut self, exec_id: ExecId) {
+        self.executions.forget(exec_id);
+    }
    
     pub fn top(&self) -> (Option<OrderPrice>, Option<OrderPrice>) {
-        let mut best_bid: OrderPrice = 0;        
-        let mut best_ask: OrderPrice = 0;        
+        (self.bids.highest(), self.asks.lowest())
+    }
+
+    /* the best level on each side, with its aggregate quantity */
+    pub fn bbo(&self) -> (Option<Level>, Option<Level>) {
+        (self.bids.levels().next_back()
+            .map(|(price, orders)| Level::from_queue(price, orders)),
+            self.asks.levels().next()
+            .map(|(price, orders)| Level::from_queue(price, orders)))
+    }
+
+    /* aggregated price levels, best first, at most `levels` per side */
+    pub fn depth(&self, levels: usize) -> (Vec<Level>, Vec<Level>) {
+        let bids: Vec<Level> = self.bids.levels().rev()
+            .take(levels)
+            .map(|(price, orders)| Level::from_queue(price, orders))
+            .collect();
+        let asks: Vec<Level> = self.asks.levels()
+            .take(levels)
+            .map(|(price, orders)| Level::from_queue(price, orders))
+            .collect();
 
-        if self.bids.iter().next().is_some() {
-            best_bid = *self.bids.iter().next().unwrap().0;
+        (bids, asks)
+    }
+
+    /* every resting order on one side, in priority order */
+    pub fn orders(&self, r#type: OrderType) ->
+        Box<dyn Iterator<Item = OrderView> + '_> {
+        let levels: Box<dyn Iterator<Item = (OrderPrice, Orders)>> =
+            match r#type {
+                OrderType::Bid => Box::new(self.bids.levels().rev()),
+                OrderType::Ask => self.asks.levels()
+            };
+
+        Box::new(levels.flat_map(|(_, orders)| orders.enumerate()
+            .map(|(position, order)| OrderView::from_order(order, position))))
+    }
+
+    /* the ids of every resting order, lowest first */
+    pub fn order_ids(&self) -> Vec<OrderId> {
+        let mut ids: Vec<OrderId> = self.bids.ids().chain(self.asks.ids())
+            .collect();
+
+        ids.sort_unstable();
+        ids
+    }
+
+    /* finding the order is immediate, but its place in the queue is
+     * counted from the front */
+    pub fn order(&self, id: OrderId) -> Option<OrderView> {
+        let side: &BookSide = [&self.bids, &self.asks].iter().copied()
+            .find(|side| side.contains(id))?;
+
+        Some(OrderView::from_order(side.get(id)?, side.position(id)?))
+    }
+
+    pub fn queue_position(&self, id: OrderId) -> Option<usize> {
+        self.order(id).map(|view| view.position())
+    }
+
+    /* quantity resting ahead of the order at its own price level */
+    pub fn quantity_ahead(&self, id: OrderId) -> Option<OrderQuantity> {
+        let view: OrderView = self.order(id)?;
+        let orders: Orders = self.side(view.r#type()).level(view.price())?;
+
+        Some(Book::total_quantity(orders.take(view.position())))
+    }
+
+    pub fn mid(&self) -> Option<OrderPrice> {
+        match self.top() {
+            (Some(bid), Some(ask)) => {
+                let low: OrderPrice = bid.min(ask);
+                let high: OrderPrice = bid.max(ask);
+                Some(low + (high - low) / 2)
+            },
+            _ => None
         }
-        
-        if self.asks.iter().next().is_some() {
-            best_ask = *self.asks.iter().rev().next().unwrap().0;
+    }
+
+    pub fn mark(&self) -> Option<OrderPrice> {
+        match self.ltp() {
+            Some(price) => Some(price),
+            None => self.mid()
         }
-        
-        let mut bid_top: Option<OrderPrice> = None;
-        let mut ask_top: Option<OrderPrice> = None;
+    }
 
-        if best_bid == 0 {
-            bid_top = None;
-        } else {
-            bid_top = Some(best_bid);
+    pub fn unrealized_pnl(&self, account: &Account, method: CostMethod) ->
+        Option<Pnl> {
+        self.mark().map(|mark| account.unrealized_pnl(self.ticker.clone(),
+            &self.quote, mark, method))
+    }
+ 
+    fn accept(&mut self, order: &Order, accounts: &mut impl Ledger,
+        rests: bool) -> Result<(), BookError> {
+        let checked: Result<(), BookError> =
+            if self.executions.contains(order.id()) {
+                Err(BookError::DuplicateOrder)
+            } else {
+                self.validate(order, accounts, rests)
+            };
+
+        if let Err(e) = checked {
+            let report: ExecutionReport = self.executions.reject(order);
+            self.listeners.rejected(self.id, &report, &e);
+            return Err(e);
         }
 
-        if best_ask == 0 {
-            ask_top = None;
-        } else {
-            ask_top = Some(best_ask);
+        let report: ExecutionReport = self.executions.accept(order);
+        self.listeners.accepted(self.id, &report);
+        Ok(())
+    }
+
+    /* should matching fail part way through, whatever is left of the order
+     * is not rested; having been accepted, it is cancelled rather than
+     * rejected */
+    fn execute(&mut self, order: &mut Order, accounts: &mut impl Ledger) ->
+        Result<(), BookError> {
+        if let Err(e) = self.match_order(order, accounts) {
+            let report: ExecutionReport = self.executions.cancel(order);
+            self.listeners.cancel(self.id, &report);
+            return Err(e);
         }
 
-        (bid_top, ask_top)
+        Ok(())
     }
- 
-    fn payout_order(ticker: String, order: &'a mut Order,
-        price: Option<OrderPrice>, quantity: Option<OrderQuantity>) ->
-        Result <(), BookError> {
-        let actual_price: OrderPrice = match price {
-            Some(p) => p,
-            None => order.price()
-        };
 
-        let actual_quantity: OrderQuantity = match quantity {
-            Some(q) => q,
-            None => order.quantity()
-        };
+    fn validate(&self, order: &Order, accounts: &mut impl Ledger,
+        rests: bool) -> Result<(), BookError> {
+        let (known, restricted): (bool, bool) = accounts.with(|accounts|
+            (accounts.contains_key(&order.owner()),
+                self.is_restricted_short(order, accounts)))?;
+
+        if !known {
+            return Err(BookError::AccountNotFound);
+        }
 
-        let amount: OrderQuantity = actual_price * actual_quantity;
+        if restricted {
+            return Err(BookError::ShortSaleRestricted);
+        }
 
-        match order.r#type() {
-            OrderType::Bid => {
-                order.owner().take_balance(amount).unwrap();
-                order.owner().add_holding(ticker, actual_quantity).unwrap();
-            },
-            OrderType::Ask => {
-                order.owner().add_balance(amount).unwrap();
-                order.owner().take_holding(ticker, actual_quantity).unwrap();
+        if rests && !self.side(order.r#type()).accepts(order.price()) {
+            return Err(BookError::PriceOutOfBand);
+        }
+
+        Book::notional(&self.precision, self.worst_price(order, rests),
+            order.quantity())?;
+
+        Ok(())
+    }
+
+    /* the most the order could ever trade at: a bid pays no more than its
+     * limit, nor, if it cannot rest, than the highest ask; an ask is paid no
+     * more than the best bid, nor its own limit once resting */
+    fn worst_price(&self, order: &Order, rests: bool) -> OrderPrice {
+        let highest_ask: OrderPrice = self.asks.highest().unwrap_or(0);
+        let best_bid: OrderPrice = self.bids.highest().unwrap_or(0);
+
+        match (order.r#type(), rests) {
+            (OrderType::Bid, true) => order.price(),
+            (OrderType::Bid, false) => order.price().min(highest_ask),
+            (OrderType::Ask, true) => order.price().max(best_bid),
+            (OrderType::Ask, false) => best_bid
+        }
+    }
+
+    fn notional(precision: &Precision, price: OrderPrice,
+        quantity: OrderQuantity) -> Result<AccountBalance, BookError> {
+        precision.notional(price, quantity).ok_or(BookError::NotionalOverflow)
+    }
+
+    /* a level's quantity saturates rather than overflows */
+    fn total_quantity<'a>(orders: impl Iterator<Item = &'a Order>) ->
+        OrderQuantity {
+        orders.fold(0, |total: OrderQuantity, order|
+            total.saturating_add(order.quantity()))
+    }
+
+    fn match_order(&mut self, order: &mut Order, accounts: &mut impl Ledger)
+        -> Result<(), BookError> {
+        while order.quantity() > 0 {
+            let best: Option<OrderPrice> = match order.r#type() {
+                OrderType::Bid => self.asks.lowest(),
+                OrderType::Ask => self.bids.highest()
+            };
+
+            let level_price: OrderPrice = match best {
+                Some(p) => p,
+                None => break
+            };
+
+            let crosses: bool = match order.r#type() {
+                OrderType::Bid => level_price <= order.price(),
+                OrderType::Ask => level_price >= order.price()
+            };
+
+            if !crosses {
+                break;
             }
+
+            let side: &mut BookSide = match order.r#type() {
+                OrderType::Bid => &mut self.asks,
+                OrderType::Ask => &mut self.bids
+            };
+            let counter_order: &mut Order = side.front_mut(level_price)
+                .unwrap();
+
+            let quantity: OrderQuantity =
+                order.quantity().min(counter_order.quantity());
+            let (buyer, seller): (AccountId, AccountId) = match order.r#type() {
+                OrderType::Bid => (order.owner(), counter_order.owner()),
+                OrderType::Ask => (counter_order.owner(), order.owner())
+            };
+            let (buy_order, sell_order): (OrderId, OrderId) =
+                match order.r#type() {
+                OrderType::Bid => (order.id(), counter_order.id()),
+                OrderType::Ask => (counter_order.id(), order.id())
+            };
+
+            let amount: AccountBalance = Book::notional(&self.precision,
+                level_price, quantity)?;
+
+            /* a resting order whose owner cannot settle is cancelled, lest
+             * it block its level for good; the incoming order carries on */
+            let (ticker, quote, precision): (&str, &Quote, &Precision) =
+                (&self.ticker, &self.quote, &self.precision);
+
+            if let Err((account, e)) = accounts.with(|accounts| Book::settle(
+                ticker, quote, precision, accounts, buyer, seller, level_price,
+                quantity, amount))? {
+                if account == order.owner() {
+                    return Err(e);
+                }
+
+                let counter_order: Order = side.pop_front(level_price)
+                    .unwrap();
+                let report: ExecutionReport =
+                    self.executions.cancel(&counter_order);
+                self.listeners.cancel(self.id, &report);
+                self.listeners.touch(counter_order.r#type(), level_price);
+                continue;
+            }
+
+            order.fill(quantity);
+            counter_order.fill(quantity);
+            self.executions.fill(order, quantity, level_price);
+            self.executions.fill(counter_order, quantity, level_price);
+            self.listeners.trade(self.id, &Trade::new(level_price, quantity,
+                order.r#type(), buy_order, sell_order, buyer, seller));
+            self.listeners.touch(counter_order.r#type(), level_price);
+
+            /* remove counter order as it is consumed */
+            if counter_order.quantity() == 0 {
+                side.pop_front(level_price);
+            }
+
+            self.tick = Book::next_tick(self.has_traded, self.ltp,
+                level_price, self.tick);
+            self.has_traded = true;
+            self.ltp = level_price;
+        }
+
+        Ok(())
+    }
+
+    /* trades always happen at the resting order's price; should either
+     * party be unable to settle, neither account is changed. A failure names
+     * the account to blame */
+    #[allow(clippy::too_many_arguments)]
+    fn settle(ticker: &str, quote: &Quote, precision: &Precision,
+        accounts: &mut Accounts, buyer: AccountId, seller: AccountId,
+        price: OrderPrice, quantity: OrderQuantity, amount: AccountBalance) ->
+        Result<(), (AccountId, BookError)> {
+        let savepoints: Vec<(AccountId, Savepoint)> = [buyer, seller].iter()
+            .filter_map(|id: &AccountId| accounts.get(id)
+                .map(|account: &Account| (*id, account.savepoint(ticker,
+                    quote))))
+            .collect();
+
+        /* all or nothing: should any leg fail, those before it are undone */
+        let settled: Result<(), (AccountId, BookError)> = Book::transfer(
+            ticker, quote, accounts, buyer, seller, quantity, amount);
+
+        if settled.is_err() {
+            for (id, savepoint) in savepoints.into_iter().rev() {
+                accounts.get_mut(&id).unwrap().restore(savepoint);
+            }
+
+            return settled;
+        }
+
+        accounts.get_mut(&seller).unwrap().record_sale(ticker.to_string(),
+            quote, *precision, price, quantity);
+        accounts.get_mut(&buyer).unwrap().record_purchase(ticker.to_string(),
+            quote, *precision, price, quantity);
+
+        Ok(())
+    }
+
+    fn transfer(ticker: &str, quote: &Quote, accounts: &mut Accounts,
+        buyer: AccountId, seller: AccountId, quantity: OrderQuantity,
+        amount: AccountBalance) -> Result<(), (AccountId, BookError)> {
+        match accounts.get_mut(&buyer) {
+            Some(account) => quote.debit(account, amount)
+                .map_err(|e| (buyer, e.into()))?,
+            None => return Err((buyer, BookError::AccountNotFound))
+        };
+
+        match accounts.get_mut(&seller) {
+            Some(account) => {
+                account.take_holding(ticker.to_string(), quantity)
+                    .map_err(|e| (seller, e.into()))?;
+                quote.credit(account, amount)
+                    .map_err(|e| (seller, e.into()))?;
+            },
+            None => return Err((seller, BookError::AccountNotFound))
         };
 
+        accounts.get_mut(&buyer).unwrap().add_holding(ticker.to_string(),
+            quantity).map_err(|e| (buyer, e.into()))?;
+
         Ok(())
     }
-    
-    fn prune_side(side: &mut Side) {
-        let mut prices_to_prune: Vec<OrderPrice> = vec![];
-        
-        for (price_level, level_orders) in side.iter_mut() {
-            if level_orders.is_empty() {
-                prices_to_prune.push(*price_level);
+    
+    /* while the restriction is in force, short sales may not be priced below
+     * the last traded price if that price was a down-tick */
+    fn is_restricted_short(&self, order: &Order, accounts: &Accounts) ->
+        bool {
+        if !self.short_sale_restriction || self.tick != Tick::Down ||
+            order.r#type() != OrderType::Ask || order.price() >= self.ltp {
+            return false;
+        }
+
+        let position: AccountPosition = match accounts.get(&order.owner()) {
+            Some(account) => account.holding(self.ticker.clone()).unwrap_or(0),
+            None => 0
+        };
+
+        position < 0 || (position as OrderQuantity) < order.quantity()
+    }
+
+    fn next_tick(has_traded: bool, ltp: OrderPrice, price: OrderPrice,
+        tick: Tick) -> Tick {
+        if !has_traded {
+            return Tick::Unchanged;
+        }
+
+        match price.cmp(&ltp) {
+            Ordering::Greater => Tick::Up,
+            Ordering::Less => Tick::Down,
+            Ordering::Equal => tick
+        }
+    }
+
+    fn side(&self, r#type: OrderType) -> &BookSide {
+        match r#type {
+            OrderType::Bid => &self.bids,
+            OrderType::Ask => &self.asks
+        }
+    }
+
+    fn side_mut(&mut self, r#type: OrderType) -> &mut BookSide {
+        match r#type {
+            OrderType::Bid => &mut self.bids,
+            OrderType::Ask => &mut self.asks
+        }
+    }
+
+    /* runs a request against the book, then reports the levels it changed
+     * and any change to the best bid or offer */
+    fn observed<T>(&mut self, request: impl FnOnce(&mut Book) -> T) -> T {
+        if self.listeners.is_empty() {
+            return request(self);
+        }
+
+        let before: (Option<Level>, Option<Level>) = self.bbo();
+        let result: T = request(self);
+
+        for (r#type, price) in self.listeners.take_touched() {
+            let level: Level = match self.side(r#type).level(price) {
+                Some(orders) => Level::from_queue(price, orders),
+                None => Level::new(price, 0, 0)
+            };
+
+            self.listeners.level(self.id, r#type, level);
+        }
+
+        let after: (Option<Level>, Option<Level>) = self.bbo();
+
+        if after != before {
+            self.listeners.bbo(self.id, after.0, after.1);
+        }
+
+        result
+    }
+
+    fn remove_order(&mut self, id: OrderId) -> Option<Order> {
+        let order: Order = self.bids.remove(id)
+            .or_else(|| self.asks.remove(id))?;
+
+        self.listeners.touch(order.r#type(), order.price());
+        Some(order)
+    }
+}
+
+impl Codec for Tick {
+    fn encode(&self, encoder: &mut Encoder) {
+        let tag: u8 = match self {
+            Tick::Up => 0,
+            Tick::Down => 1,
+            Tick::Unchanged => 2
+        };
+
+        tag.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        match u8::decode(decoder)? {
+            0 => Ok(Tick::Up),
+            1 => Ok(Tick::Down),
+            2 => Ok(Tick::Unchanged),
+            _ => Err(SnapshotError::Malformed)
+        }
+    }
+}
+
+impl Codec for Quote {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            Quote::Currency(currency) => {
+                0u8.encode(encoder);
+                currency.encode(encoder);
+            },
+            Quote::Asset(ticker) => {
+                1u8.encode(encoder);
+                ticker.encode(encoder);
             }
         }
+    }
 
-        for price in prices_to_prune.iter() {
-            side.remove(price);
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        match u8::decode(decoder)? {
+            0 => Ok(Quote::Currency(Codec::decode(decoder)?)),
+            1 => Ok(Quote::Asset(Codec::decode(decoder)?)),
+            _ => Err(SnapshotError::Malformed)
         }
     }
+}
 
-    fn remove_id(order_ids: &mut Vec<OrderId>, id: OrderId) {
-        let mut pos: usize = 0;
-
-        for (i, order_id) in order_ids.iter().enumerate() {
-            if *order_id == id {
-                pos = i;
-                break;
-            }
-        }
+/* listeners are not part of a snapshot and must be registered again */
+impl Codec for Book {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.id.encode(encoder);
+        self.name.encode(encoder);
+        self.ticker.encode(encoder);
+        self.quote.encode(encoder);
+        self.precision.encode(encoder);
+        self.bids.encode(encoder);
+        self.asks.encode(encoder);
+        self.ltp.encode(encoder);
+        self.has_traded.encode(encoder);
+        self.tick.encode(encoder);
+        self.short_sale_restriction.encode(encoder);
+        self.executions.encode(encoder);
+    }
 
-        order_ids.remove(pos);
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(Book {
+            id: Codec::decode(decoder)?,
+            name: Codec::decode(decoder)?,
+            ticker: Codec::decode(decoder)?,
+            quote: Codec::decode(decoder)?,
+            precision: Codec::decode(decoder)?,
+            bids: Codec::decode(decoder)?,
+            asks: Codec::decode(decoder)?,
+            ltp: Codec::decode(decoder)?,
+            has_traded: Codec::decode(decoder)?,
+            tick: Codec::decode(decoder)?,
+            short_sale_restriction: Codec::decode(decoder)?,
+            executions: Codec::decode(decoder)?,
+            listeners: Listeners::new()
+        })
     }
 }
 
@@ -340,175 +999,1006 @@ impl<'a> Book<'a> {
 mod tests {
     use super::*;
     use std::collections::HashMap;
-    use std::iter::FromIterator;
-    use crate::account::{Account, AccountHolding};
+    use std::sync::{Arc, Mutex};
+    use crate::account::{Account, AccountBalance, AccountHolding, Currency};
+    use crate::execution::ExecType;
+    use crate::invariant::{self, Totals};
+    use crate::pnl::CostBasis;
+
+    fn usd(amount: AccountBalance) -> HashMap<Currency, AccountBalance> {
+        let mut balances: HashMap<Currency, AccountBalance> = HashMap::new();
+        balances.insert("USD".to_string(), amount);
+        balances
+    }
+
+    /* the executions of a resting bid wholly filled by an incoming ask */
+    fn crossed(mut bid: Order, mut ask: Order, price: OrderPrice) ->
+        Executions {
+        let quantity: OrderQuantity = ask.quantity();
+        let mut executions: Executions = Executions::new();
+
+        executions.accept(&bid);
+        executions.accept(&ask);
+        bid.fill(quantity);
+        ask.fill(quantity);
+        executions.fill(&ask, quantity, price);
+        executions.fill(&bid, quantity, price);
+        executions
+    }
 
     #[test]
     fn test_submit_equal_orders() -> Result<(), BookError> {
-        let mut holdings: HashMap<String, AccountHolding> = HashMap::new();
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
         holdings.insert("VOC".to_string(), 20);
         
-        let mut actual_account1: Account =
-                Account::new(1, "John Doe".to_string(), 2500, HashMap::new());
-        let mut actual_account2: Account =
-                Account::new(2, "Jane Doe".to_string(), 0, holdings.clone());
-        let mut actual_order1: Order = 
-                Order::new(1000, &mut actual_account1, OrderType::Bid, 125, 20);
-        let mut actual_order2: Order =
-                Order::new(1001, &mut actual_account2, OrderType::Ask, 125, 20);
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(2500), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings.clone()));
+        let actual_order1: Order = Order::new(1000, 1, OrderType::Bid, 125, 20);
+        let actual_order2: Order = Order::new(1001, 2, OrderType::Ask, 125, 20);
         
         let mut actual_book: Book = Book::new(1,
-            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string());
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
         
-        actual_book.submit(&mut actual_order1)?;
-        actual_book.submit(&mut actual_order2)?;
+        actual_book.submit(actual_order1.clone(), &mut actual_accounts)?;
+        actual_book.submit(actual_order2.clone(), &mut actual_accounts)?;
         
         let expected_book: Book = Book {
             id: 1,
             name: "Vereenigde Oostindische Compagnie".to_string(),
             ticker: "VOC".to_string(),
-            bids: Side::new(),
-            asks: Side::new(),
+            quote: Quote::Currency("USD".to_string()),
+            precision: Precision::default(),
+            bids: BookSide::default(),
+            asks: BookSide::default(),
             ltp: 125,
             has_traded: true,
-            order_ids: vec![]
+            tick: Tick::Unchanged,
+            short_sale_restriction: false,
+            executions: crossed(actual_order1, actual_order2, 125),
+            listeners: Listeners::new()
         };
 
-        let mut expected_holdings2: HashMap<String, AccountHolding> =
+        let mut expected_holdings2: HashMap<String, AccountPosition> =
             HashMap::new();
         expected_holdings2.insert("VOC".to_string(), 0);
         let mut expected_account1: Account =
-                Account::new(1, "John Doe".to_string(), 0, holdings);
+                Account::new(1, "John Doe".to_string(), usd(0), holdings);
         let mut expected_account2: Account =
-                Account::new(2, "Jane Doe".to_string(), 2500,
-                expected_holdings2);
-        let _expected_order1: Order = 
-                Order::new(1000, &mut expected_account1, OrderType::Bid, 125,
-                20);
-        let _expected_order2: Order =
-                Order::new(1001, &mut expected_account2, OrderType::Ask, 125,
-                20);
+                Account::new(2, "Jane Doe".to_string(), usd(2500),
+                    expected_holdings2);
+        expected_account1.record_purchase("VOC".to_string(),
+            &Quote::Currency("USD".to_string()), Precision::default(), 125,
+            20);
+        expected_account2.record_sale("VOC".to_string(),
+            &Quote::Currency("USD".to_string()), Precision::default(), 125,
+            20);
         
         assert_eq!(actual_book, expected_book);
-        assert_eq!(actual_account1, expected_account1);
-        assert_eq!(actual_account2, expected_account2);
+        assert_eq!(actual_accounts[&1], expected_account1);
+        assert_eq!(actual_accounts[&2], expected_account2);
         
         Ok(())
     }
 
     #[test]
     fn test_submit_price_mismatch_nocross() -> Result<(), BookError> {
-        let mut holdings: HashMap<String, AccountHolding> = HashMap::new();
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
         holdings.insert("MSFT".to_string(), 20);
         
-        let mut actual_account1: Account =
-                Account::new(1, "John Doe".to_string(), 2500, HashMap::new());
-        let mut actual_account2: Account =
-                Account::new(2, "Jane Doe".to_string(), 0, holdings.clone());
-        let mut actual_order1: Order = 
-                Order::new(1000, &mut actual_account1, OrderType::Bid, 125, 20);
-        let mut actual_order2: Order =
-                Order::new(1001, &mut actual_account2, OrderType::Ask, 130, 20);
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(2500), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings.clone()));
+        let actual_order1: Order = Order::new(1000, 1, OrderType::Bid, 125, 20);
+        let actual_order2: Order = Order::new(1001, 2, OrderType::Ask, 130, 20);
         
         let mut actual_book: Book = Book::new(1,
-            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string());
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
         
-        actual_book.submit(&mut actual_order1)?;
-        actual_book.submit(&mut actual_order2)?;
+        actual_book.submit(actual_order1.clone(), &mut actual_accounts)?;
+        actual_book.submit(actual_order2.clone(), &mut actual_accounts)?;
         
-        let mut expected_account1: Account =
-                Account::new(1, "John Doe".to_string(), 2500, HashMap::new());
-        let mut expected_account2: Account =
-                Account::new(2, "Jane Doe".to_string(), 0, holdings.clone());
-        let mut expected_order1: Order = 
-                Order::new(1000, &mut expected_account1, OrderType::Bid, 125,
-                    20);
-        let mut expected_order2: Order =
-                Order::new(1001, &mut expected_account2, OrderType::Ask, 130,
-                    20);
+        let expected_account1: Account =
+                Account::new(1, "John Doe".to_string(), usd(2500),
+                    HashMap::new());
+        let expected_account2: Account =
+                Account::new(2, "Jane Doe".to_string(), usd(0),
+                    holdings.clone());
         
-        let mut expected_bids: Side = Side::new();
-        expected_bids.insert(125, VecDeque::from_iter(
-                                    vec![&mut expected_order1]));
+        let mut expected_executions: Executions = Executions::new();
+        expected_executions.accept(&actual_order1);
+        expected_executions.accept(&actual_order2);
+
+        let mut expected_bids: BookSide = BookSide::default();
+        expected_bids.push(actual_order1);
         
-        let mut expected_asks: Side = Side::new();
-        expected_asks.insert(130, VecDeque::from_iter(
-                                    vec![&mut expected_order2]));
+        let mut expected_asks: BookSide = BookSide::default();
+        expected_asks.push(actual_order2);
         
         let expected_book: Book = Book {
             id: 1,
             name: "Vereenigde Oostindische Compagnie".to_string(),
             ticker: "VOC".to_string(),
+            quote: Quote::Currency("USD".to_string()),
+            precision: Precision::default(),
             bids: expected_bids,
             asks: expected_asks,
             ltp: 0,
             has_traded: false,
-            order_ids: vec![1000, 1001]
+            tick: Tick::Unchanged,
+            short_sale_restriction: false,
+            executions: expected_executions,
+            listeners: Listeners::new()
         };
         
         assert_eq!(actual_book, expected_book);
-        assert_eq!(actual_account1, expected_account1);
-        assert_eq!(actual_account2, expected_account2);
+        assert_eq!(actual_accounts[&1], expected_account1);
+        assert_eq!(actual_accounts[&2], expected_account2);
         
         Ok(())
     }
 
-    #[allow(unused_variables)]
     #[test]
     fn test_submit_price_mismatch_cross() -> Result<(), BookError> {
-        let mut holdings: HashMap<String, AccountHolding> = HashMap::new();
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
         holdings.insert("VOC".to_string(), 20);
         
-        let mut actual_account1: Account =
-                Account::new(1, "John Doe".to_string(), 4000, HashMap::new());
-        let mut actual_account2: Account =
-                Account::new(2, "Jane Doe".to_string(), 0, holdings.clone());
-        let mut actual_order1: Order = 
-                Order::new(1000, &mut actual_account1, OrderType::Bid, 200, 20);
-        let mut actual_order2: Order =
-                Order::new(1001, &mut actual_account2, OrderType::Ask, 140, 20);
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(4000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings.clone()));
+        let actual_order1: Order = Order::new(1000, 1, OrderType::Bid, 200, 20);
+        let actual_order2: Order = Order::new(1001, 2, OrderType::Ask, 140, 20);
         
         let mut actual_book: Book = Book::new(1,
-            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string());
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
         
-        actual_book.submit(&mut actual_order1)?;
-        actual_book.submit(&mut actual_order2)?;
+        actual_book.submit(actual_order1.clone(), &mut actual_accounts)?;
+        actual_book.submit(actual_order2.clone(), &mut actual_accounts)?;
         
-        let mut expected_holdings2: HashMap<String, AccountHolding> =
+        let mut expected_holdings2: HashMap<String, AccountPosition> =
             HashMap::new();
         expected_holdings2.insert("VOC".to_string(), 0);
 
+        /* the trade happens at the resting bid's price */
         let mut expected_account1: Account =
-                Account::new(1, "John Doe".to_string(), 0, holdings.clone());
+                Account::new(1, "John Doe".to_string(), usd(0),
+                    holdings.clone());
         let mut expected_account2: Account =
-                Account::new(2, "Jane Doe".to_string(), 2800,
-                expected_holdings2);
-        let expected_order1: Order = 
-                Order::new(1000, &mut expected_account1, OrderType::Bid, 200,
-                    20);
-        let expected_order2: Order =
-                Order::new(1001, &mut expected_account2, OrderType::Ask, 140,
-                    20);
+                Account::new(2, "Jane Doe".to_string(), usd(4000),
+                    expected_holdings2);
+        expected_account1.record_purchase("VOC".to_string(),
+            &Quote::Currency("USD".to_string()), Precision::default(), 200,
+            20);
+        expected_account2.record_sale("VOC".to_string(),
+            &Quote::Currency("USD".to_string()), Precision::default(), 200,
+            20);
         
-        let expected_bids: Side = Side::new();
-        let expected_asks: Side = Side::new();
+        let expected_bids: BookSide = BookSide::default();
+        let expected_asks: BookSide = BookSide::default();
         
         let expected_book: Book = Book {
             id: 1,
             name: "Vereenigde Oostindische Compagnie".to_string(),
             ticker: "VOC".to_string(),
+            quote: Quote::Currency("USD".to_string()),
+            precision: Precision::default(),
             bids: expected_bids,
             asks: expected_asks,
-            ltp: 140,
+            ltp: 200,
             has_traded: true,
-            order_ids: vec![]
+            tick: Tick::Unchanged,
+            short_sale_restriction: false,
+            executions: crossed(actual_order1, actual_order2, 200),
+            listeners: Listeners::new()
         };
         
         assert_eq!(actual_book, expected_book);
-        assert_eq!(actual_account1, expected_account1);
-        assert_eq!(actual_account2, expected_account2);
+        assert_eq!(actual_accounts[&1], expected_account1);
+        assert_eq!(actual_accounts[&2], expected_account2);
         
         Ok(())
     }
-}
 
+    #[test]
+    fn test_submit_partial_fill_sweep() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 20);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(5000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings.clone()));
+        actual_accounts.insert(3, Account::new(3, "Joe Bloggs".to_string(),
+            usd(0), holdings));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        actual_book.submit(Order::new(1000, 2, OrderType::Ask, 100, 20),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1001, 3, OrderType::Ask, 101, 20),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1002, 1, OrderType::Bid, 105, 30),
+            &mut actual_accounts)?;
+
+        let mut expected_asks: BookSide = BookSide::default();
+        expected_asks.push(Order::new(1001, 3, OrderType::Ask, 101, 10));
+
+        assert_eq!(actual_book.asks, expected_asks);
+        assert_eq!(actual_book.bids, BookSide::default());
+        assert_eq!(actual_book.order_ids(), vec![1001]);
+        assert_eq!(actual_book.ltp(), Some(101));
+        assert_eq!(actual_accounts[&1].balance("USD".to_string()),
+            Some(5000 - 2000 - 1010));
+        assert_eq!(actual_accounts[&1].holding("VOC".to_string()), Some(30));
+        assert_eq!(actual_accounts[&3].balance("USD".to_string()), Some(1010));
+        assert_eq!(actual_accounts[&3].holding("VOC".to_string()), Some(10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_ioc_discards_remainder() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 50);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(1000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        actual_book.submit(Order::new(1000, 1, OrderType::Bid, 100, 5),
+            &mut actual_accounts)?;
+
+        let filled: OrderQuantity = actual_book.submit_ioc(
+            Order::market(1001, 2, OrderType::Ask, 50), &mut actual_accounts)?;
+
+        assert_eq!(filled, 5);
+        assert_eq!(actual_book.top(), (None, None));
+        assert!(actual_book.order_ids().is_empty());
+        assert_eq!(actual_accounts[&2].balance("USD".to_string()), Some(500));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unrealized_pnl_mid() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 20);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(2500), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        actual_book.submit(Order::new(1000, 1, OrderType::Bid, 120, 20),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1001, 2, OrderType::Ask, 130, 20),
+            &mut actual_accounts)?;
+
+        let mut position: Account =
+                Account::new(3, "Joe Bloggs".to_string(), usd(0),
+                    HashMap::new());
+        position.record_purchase("VOC".to_string(),
+            &Quote::Currency("USD".to_string()), Precision::default(), 100,
+            10);
+
+        assert_eq!(actual_book.ltp(), None);
+        assert_eq!(actual_book.mark(), Some(125));
+        assert_eq!(actual_book.unrealized_pnl(&position, CostMethod::Average),
+            Some(250));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_settlement_is_atomic() -> Result<(), BookError> {
+        let mut short_seller: Account = Account::new(2, "Jane Doe".to_string(),
+            HashMap::new(), HashMap::new());
+        short_seller.set_short_selling(true);
+        short_seller.add_borrow("VOC".to_string(), 10).unwrap();
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(1000), HashMap::new()));
+        actual_accounts.insert(2, short_seller);
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+        actual_book.submit(Order::new(1000, 1, OrderType::Bid, 20, 10),
+            &mut actual_accounts)?;
+
+        /* the buyer can pay and the seller can deliver, but by the time the
+         * bid is hit the buyer cannot hold any more, which is the last leg
+         * to be found out */
+        actual_accounts.get_mut(&1).unwrap().add_holding("VOC".to_string(),
+            AccountPosition::MAX as AccountHolding - 5).unwrap();
+        let expected_accounts: Accounts = actual_accounts.clone();
+        let before: Totals = Totals::of(&actual_accounts);
+
+        actual_book.submit(Order::new(1001, 2, OrderType::Ask, 20, 10),
+            &mut actual_accounts)?;
+
+        /* the bid is at fault, so it is the one cancelled */
+        assert_eq!(actual_accounts, expected_accounts);
+        assert_eq!(actual_book.order_status(1000),
+            Some(OrderStatus::Cancelled));
+        assert_eq!(actual_book.order_status(1001), Some(OrderStatus::New));
+        assert_eq!(invariant::check(&actual_book, &actual_accounts, &before),
+            Ok(()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_settles_in_book_currency() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("ASML".to_string(), 10);
+
+        let mut balances1: HashMap<Currency, AccountBalance> = usd(500);
+        balances1.insert("EUR".to_string(), 1000);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            balances1, HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            HashMap::new(), holdings));
+
+        let mut actual_book: Book = Book::new(2, "ASML Holding".to_string(),
+            "ASML".to_string(), "EUR".to_string());
+
+        actual_book.submit(Order::new(1000, 1, OrderType::Bid, 80, 10),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1001, 2, OrderType::Ask, 80, 10),
+            &mut actual_accounts)?;
+
+        assert_eq!(actual_accounts[&1].balance("EUR".to_string()), Some(200));
+        assert_eq!(actual_accounts[&1].balance("USD".to_string()), Some(500));
+        assert_eq!(actual_accounts[&2].balance("EUR".to_string()), Some(800));
+        assert_eq!(actual_accounts[&2].balance("USD".to_string()), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_pair_chain() -> Result<(), BookError> {
+        let mut holdings1: HashMap<String, AccountPosition> = HashMap::new();
+        holdings1.insert("ETH".to_string(), 10);
+        let mut holdings2: HashMap<String, AccountPosition> = HashMap::new();
+        holdings2.insert("BTC".to_string(), 1);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(3000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            HashMap::new(), holdings1));
+        actual_accounts.insert(3, Account::new(3, "Joe Bloggs".to_string(),
+            HashMap::new(), holdings2));
+
+        let mut actual_book1: Book = Book::new(1, "Ether".to_string(),
+            "ETH".to_string(), "USD".to_string());
+        let mut actual_book2: Book = Book::new_pair(2,
+            "Bitcoin/Ether".to_string(), "BTC".to_string(), "ETH".to_string());
+
+        assert_eq!(actual_book2.currency(), None);
+
+        /* John buys ETH with USD... */
+        actual_book1.submit(Order::new(1000, 1, OrderType::Bid, 300, 10),
+            &mut actual_accounts)?;
+        actual_book1.submit(Order::new(1001, 2, OrderType::Ask, 300, 10),
+            &mut actual_accounts)?;
+
+        /* ...then spends that ETH on BTC */
+        actual_book2.submit(Order::new(1002, 1, OrderType::Bid, 10, 1),
+            &mut actual_accounts)?;
+        actual_book2.submit(Order::new(1003, 3, OrderType::Ask, 10, 1),
+            &mut actual_accounts)?;
+
+        assert_eq!(actual_accounts[&1].balance("USD".to_string()), Some(0));
+        assert_eq!(actual_accounts[&1].holding("ETH".to_string()), Some(0));
+        assert_eq!(actual_accounts[&1].holding("BTC".to_string()), Some(1));
+        assert_eq!(actual_accounts[&2].balance("USD".to_string()), Some(3000));
+        assert_eq!(actual_accounts[&3].holding("ETH".to_string()), Some(10));
+        assert_eq!(actual_accounts[&3].holding("BTC".to_string()), Some(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_short_sale_restriction() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 20);
+
+        let mut short_seller: Account =
+                Account::new(3, "Joe Bloggs".to_string(), usd(0),
+                    HashMap::new());
+        short_seller.set_short_selling(true);
+        short_seller.add_borrow("VOC".to_string(), 10).unwrap();
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(2450), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+        actual_accounts.insert(3, short_seller);
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+        actual_book.set_short_sale_restriction(true);
+
+        actual_book.submit(Order::new(1000, 1, OrderType::Bid, 125, 10),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1001, 2, OrderType::Ask, 125, 10),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1002, 1, OrderType::Bid, 120, 10),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1003, 2, OrderType::Ask, 120, 10),
+            &mut actual_accounts)?;
+
+        assert_eq!(actual_book.ltp(), Some(120));
+        assert_eq!(actual_book.tick(), Tick::Down);
+        assert!(matches!(actual_book.submit(
+            Order::new(1004, 3, OrderType::Ask, 110, 10),
+            &mut actual_accounts), Err(BookError::ShortSaleRestricted)));
+
+        actual_book.submit(Order::new(1005, 3, OrderType::Ask, 130, 10),
+            &mut actual_accounts)?;
+        assert_eq!(actual_book.order_ids(), vec![1005]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_notional_overflow() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 50);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(1000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        /* a resting market bid could be hit at any price at all */
+        assert!(matches!(actual_book.submit(
+            Order::market(1000, 1, OrderType::Bid, 2), &mut actual_accounts),
+            Err(BookError::NotionalOverflow)));
+        assert_eq!(actual_book.order_status(1000), Some(OrderStatus::Rejected));
+
+        actual_book.submit(Order::new(1001, 1, OrderType::Bid, 100, 5),
+            &mut actual_accounts)?;
+
+        /* an ask is paid the best bid, even when it asks for less */
+        assert!(matches!(actual_book.submit(
+            Order::new(1002, 2, OrderType::Ask, 1, OrderQuantity::MAX / 50),
+            &mut actual_accounts), Err(BookError::NotionalOverflow)));
+        assert!(matches!(actual_book.amend(1001, OrderPrice::MAX, 5,
+            &mut actual_accounts), Err(BookError::NotionalOverflow)));
+
+        /* whereas a market IOC bid pays no more than the highest ask */
+        actual_book.submit(Order::new(1003, 2, OrderType::Ask, 150, 5),
+            &mut actual_accounts)?;
+        assert_eq!(actual_book.submit_ioc(
+            Order::market(1004, 1, OrderType::Bid, 5), &mut actual_accounts)?,
+            5);
+        assert_eq!(actual_accounts[&2].balance("USD".to_string()), Some(750));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_settles_at_precision() -> Result<(), DecimalError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 50);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(10000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        /* prices in cents, quantities in tenths of a share */
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+        actual_book.set_precision(Precision::new(2, 1)?);
+
+        let price: Price = actual_book.parse_price("12.34")?;
+        let quantity: Quantity = actual_book.parse_quantity("1.5")?;
+
+        actual_book.submit(Order::new(1000, 2, OrderType::Ask, price.units(),
+            20), &mut actual_accounts).unwrap();
+        actual_book.submit(Order::new(1001, 1, OrderType::Bid, price.units(),
+            quantity.units()), &mut actual_accounts).unwrap();
+
+        assert_eq!(actual_book.price(actual_book.ltp().unwrap()).to_string(),
+            "12.34");
+        assert_eq!(actual_book.quantity(actual_book.depth(1).1[0].quantity())
+            .to_string(), "0.5");
+        assert_eq!(actual_accounts[&1].balance("USD".to_string()),
+            Some(10000 - 1851));
+        assert_eq!(actual_accounts[&2].balance("USD".to_string()), Some(1851));
+        assert_eq!(actual_accounts[&1].holding("VOC".to_string()), Some(15));
+
+        /* and the cost basis agrees with what was paid */
+        let basis: &CostBasis = actual_accounts[&1].cost_basis(
+            "VOC".to_string(), &actual_book.quote()).unwrap();
+        assert_eq!(basis.total_cost(), 1851);
+        assert_eq!(basis.average_cost(), Some(1234));
+        assert_eq!(actual_book.unrealized_pnl(&actual_accounts[&1],
+            CostMethod::Fifo), Some(0));
+        assert_eq!(actual_book.parse_price("12.345"),
+            Err(DecimalError::TooPrecise(2)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_depth_best_first() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 100);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(10000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        actual_book.submit(Order::new(1000, 1, OrderType::Bid, 120, 10),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1001, 1, OrderType::Bid, 122, 5),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1002, 1, OrderType::Bid, 122, 7),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1003, 1, OrderType::Bid, 118, 1),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1004, 2, OrderType::Ask, 130, 3),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1005, 2, OrderType::Ask, 125, 4),
+            &mut actual_accounts)?;
+
+        let expected_bids: Vec<Level> = vec![Level::new(122, 12, 2),
+            Level::new(120, 10, 1)];
+        let expected_asks: Vec<Level> = vec![Level::new(125, 4, 1),
+            Level::new(130, 3, 1)];
+
+        assert_eq!(actual_book.top(), (Some(122), Some(125)));
+        assert_eq!(actual_book.depth(2), (expected_bids, expected_asks));
+        assert_eq!(actual_book.depth(0), (vec![], vec![]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_band() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 100);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(10000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        actual_book.submit(Order::new(1000, 1, OrderType::Bid, 120, 10),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1001, 2, OrderType::Ask, 125, 4),
+            &mut actual_accounts)?;
+
+        /* the ask would be left outside the band */
+        assert!(matches!(actual_book.set_band(Some(Band::new(100, 120, 5)?)),
+            Err(BookError::PriceOutOfBand)));
+        assert_eq!(actual_book.band(), None);
+
+        actual_book.set_band(Some(Band::new(100, 200, 5)?))?;
+        assert_eq!(actual_book.band(), Some(Band::new(100, 200, 5)?));
+        assert_eq!(actual_book.depth(usize::MAX), (vec![Level::new(120, 10, 1)],
+            vec![Level::new(125, 4, 1)]));
+
+        /* orders may only rest on the band's ticks, but may take from
+         * anywhere */
+        assert!(matches!(actual_book.submit(Order::new(1002, 1,
+            OrderType::Bid, 121, 1), &mut actual_accounts),
+            Err(BookError::PriceOutOfBand)));
+        assert!(matches!(actual_book.submit(Order::new(1003, 1,
+            OrderType::Bid, 205, 1), &mut actual_accounts),
+            Err(BookError::PriceOutOfBand)));
+        assert!(matches!(actual_book.amend(1000, 95, 10,
+            &mut actual_accounts), Err(BookError::PriceOutOfBand)));
+        assert_eq!(actual_book.submit_ioc(Order::new(1004, 1, OrderType::Bid,
+            205, 4), &mut actual_accounts)?, 4);
+        assert_eq!(actual_book.top(), (Some(120), None));
+
+        actual_book.cancel(1000)?;
+        assert_eq!(actual_book.top(), (None, None));
+
+        actual_book.set_band(None)?;
+        assert_eq!(actual_book.band(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_orders_priority_and_queue() -> Result<(), BookError> {
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(10000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(10000), HashMap::new()));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        actual_book.submit(Order::new(1000, 1, OrderType::Bid, 120, 10),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1001, 2, OrderType::Bid, 122, 5),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1002, 1, OrderType::Bid, 122, 7),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1003, 2, OrderType::Bid, 122, 2),
+            &mut actual_accounts)?;
+
+        let actual_ids: Vec<OrderId> = actual_book.orders(OrderType::Bid)
+            .map(|view| view.id())
+            .collect();
+        let actual_positions: Vec<usize> = actual_book.orders(OrderType::Bid)
+            .map(|view| view.position())
+            .collect();
+
+        assert_eq!(actual_ids, vec![1001, 1002, 1003, 1000]);
+        assert_eq!(actual_positions, vec![0, 1, 2, 0]);
+        assert_eq!(actual_book.orders(OrderType::Ask).count(), 0);
+        assert_eq!(actual_book.order(1002).unwrap().owner(), 1);
+        assert_eq!(actual_book.queue_position(1003), Some(2));
+        assert_eq!(actual_book.quantity_ahead(1003), Some(12));
+        assert_eq!(actual_book.quantity_ahead(1000), Some(0));
+        assert_eq!(actual_book.quantity_ahead(9999), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_status_lifecycle() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 20);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(5000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        actual_book.submit(Order::new(1000, 2, OrderType::Ask, 100, 20),
+            &mut actual_accounts)?;
+        actual_book.submit_ioc(Order::new(1001, 1, OrderType::Bid, 100, 5),
+            &mut actual_accounts)?;
+        actual_book.submit_ioc(Order::new(1002, 1, OrderType::Bid, 90, 5),
+            &mut actual_accounts)?;
+
+        assert_eq!(actual_book.order_status(1000),
+            Some(OrderStatus::PartiallyFilled));
+        assert_eq!(actual_book.order_status(1001), Some(OrderStatus::Filled));
+        assert_eq!(actual_book.order_status(1002),
+            Some(OrderStatus::Cancelled));
+
+        assert!(matches!(actual_book.submit(
+            Order::new(1003, 9, OrderType::Bid, 100, 5), &mut actual_accounts),
+            Err(BookError::AccountNotFound)));
+        assert!(matches!(actual_book.submit(
+            Order::new(1000, 1, OrderType::Bid, 100, 5), &mut actual_accounts),
+            Err(BookError::DuplicateOrder)));
+        assert_eq!(actual_book.order_status(1003),
+            Some(OrderStatus::Rejected));
+
+        actual_book.cancel(1000)?;
+
+        assert!(matches!(actual_book.cancel(1000),
+            Err(BookError::OrderNotFound)));
+        assert_eq!(actual_book.order_status(1000),
+            Some(OrderStatus::Cancelled));
+        assert_eq!(actual_book.order_state(1000),
+            Some(OrderState::new(OrderStatus::Cancelled, 5, 0)));
+        assert_eq!(actual_book.top(), (None, None));
+        assert_eq!(actual_book.order_status(9999), None);
+
+        let actual_types: Vec<(OrderId, ExecType)> = actual_book.take_reports()
+            .iter()
+            .map(|report| (report.order_id(), report.exec_type()))
+            .collect();
+
+        assert_eq!(actual_types, vec![(1000, ExecType::New),
+            (1001, ExecType::New), (1001, ExecType::Trade),
+            (1000, ExecType::Trade), (1002, ExecType::New),
+            (1002, ExecType::Cancelled), (1003, ExecType::Rejected),
+            (1000, ExecType::Rejected), (1000, ExecType::Cancelled)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expire_ask() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 20);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        actual_book.submit(Order::new(1000, 2, OrderType::Ask, 100, 20),
+            &mut actual_accounts)?;
+        actual_book.expire(1000)?;
+
+        assert_eq!(actual_book.order_status(1000), Some(OrderStatus::Expired));
+        assert_eq!(actual_book.orders(OrderType::Ask).count(), 0);
+        assert!(actual_book.depth(1).1.is_empty());
+
+        Ok(())
+    }
+
+    /* an order taken from the middle of its queue leaves the others where
+     * they were, and the queue still matches front first */
+    #[test]
+    fn test_cancel_middle_of_queue() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 30);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(5000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        for id in 1000..1003 {
+            actual_book.submit(Order::new(id, 2, OrderType::Ask, 100, 10),
+                &mut actual_accounts)?;
+        }
+
+        actual_book.cancel(1001)?;
+
+        assert!(matches!(actual_book.cancel(1001),
+            Err(BookError::OrderNotFound)));
+        assert_eq!(actual_book.order_ids(), vec![1000, 1002]);
+        assert_eq!(actual_book.queue_position(1002), Some(1));
+        assert_eq!(actual_book.depth(1).1, vec![Level::new(100, 20, 2)]);
+
+        actual_book.submit(Order::new(1003, 1, OrderType::Bid, 100, 15),
+            &mut actual_accounts)?;
+
+        assert_eq!(actual_book.order_status(1000), Some(OrderStatus::Filled));
+        assert_eq!(actual_book.order_state(1002),
+            Some(OrderState::new(OrderStatus::PartiallyFilled, 5, 5)));
+        assert_eq!(actual_book.queue_position(1002), Some(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_amend_priority() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 20);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(5000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        actual_book.submit(Order::new(1000, 1, OrderType::Bid, 90, 10),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1001, 1, OrderType::Bid, 90, 10),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1002, 2, OrderType::Ask, 100, 20),
+            &mut actual_accounts)?;
+
+        /* reducing quantity keeps priority, increasing it does not */
+        actual_book.amend(1000, 90, 5, &mut actual_accounts)?;
+        assert_eq!(actual_book.queue_position(1000), Some(0));
+        actual_book.amend(1000, 90, 8, &mut actual_accounts)?;
+        assert_eq!(actual_book.queue_position(1000), Some(1));
+
+        /* a new price may cross the spread */
+        actual_book.amend(1001, 100, 4, &mut actual_accounts)?;
+
+        assert!(matches!(actual_book.amend(1001, 100, 4,
+            &mut actual_accounts), Err(BookError::OrderNotFound)));
+        assert!(matches!(actual_book.amend(1000, 90, 0,
+            &mut actual_accounts), Err(BookError::InvalidQuantity)));
+        assert_eq!(actual_book.order_status(1001), Some(OrderStatus::Filled));
+        assert_eq!(actual_book.order_state(1000),
+            Some(OrderState::new(OrderStatus::New, 0, 8)));
+        assert_eq!(actual_book.depth(1), (vec![Level::new(90, 8, 1)],
+            vec![Level::new(100, 16, 1)]));
+        assert_eq!(actual_book.ltp(), Some(100));
+
+        Ok(())
+    }
+
+    /* records every event as a line of text */
+    struct Recorder {
+        events: Arc<Mutex<Vec<String>>>
+    }
+
+    impl BookListener for Recorder {
+        fn on_accepted(&mut self, _book: BookId, report: &ExecutionReport) {
+            self.events.lock().unwrap()
+                .push(format!("accepted {}", report.order_id()));
+        }
+
+        fn on_rejected(&mut self, _book: BookId, report: &ExecutionReport,
+            _error: &BookError) {
+            self.events.lock().unwrap()
+                .push(format!("rejected {}", report.order_id()));
+        }
+
+        fn on_trade(&mut self, _book: BookId, trade: &Trade) {
+            self.events.lock().unwrap().push(format!("trade {} {} {}@{}",
+                trade.buy_order(), trade.sell_order(), trade.quantity(),
+                trade.price()));
+        }
+
+        fn on_cancel(&mut self, _book: BookId, report: &ExecutionReport) {
+            self.events.lock().unwrap()
+                .push(format!("cancel {}", report.order_id()));
+        }
+
+        fn on_bbo(&mut self, _book: BookId, bid: Option<Level>,
+            ask: Option<Level>) {
+            let describe = |level: Option<Level>| match level {
+                Some(l) => format!("{}x{}", l.quantity(), l.price()),
+                None => "-".to_string()
+            };
+
+            self.events.lock().unwrap().push(format!("bbo {} {}",
+                describe(bid), describe(ask)));
+        }
+
+        fn on_level(&mut self, _book: BookId, side: OrderType, level: Level) {
+            self.events.lock().unwrap().push(format!("level {} {}x{}", side,
+                level.quantity(), level.price()));
+        }
+    }
+
+    #[test]
+    fn test_add_listener_events() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 20);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(5000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        let events1: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
+        let events2: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+        actual_book.add_listener(Box::new(Recorder {
+            events: events1.clone()
+        }));
+        actual_book.add_listener(Box::new(Recorder {
+            events: events2.clone()
+        }));
+
+        actual_book.submit(Order::new(1000, 2, OrderType::Ask, 100, 20),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1001, 1, OrderType::Bid, 100, 5),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1002, 1, OrderType::Bid, 90, 5),
+            &mut actual_accounts)?;
+        assert!(actual_book.submit(Order::new(1003, 9, OrderType::Bid, 90, 5),
+            &mut actual_accounts).is_err());
+        assert!(matches!(actual_book.submit(Order::new(1002, 1, OrderType::Bid,
+            95, 5), &mut actual_accounts), Err(BookError::DuplicateOrder)));
+        actual_book.cancel(1000)?;
+
+        let expected_events: Vec<String> = vec![
+            "accepted 1000", "level ASK 20x100", "bbo - 20x100",
+            "accepted 1001", "trade 1001 1000 5@100", "level ASK 15x100",
+            "bbo - 15x100",
+            "accepted 1002", "level BID 5x90", "bbo 5x90 15x100",
+            "rejected 1003", "rejected 1002",
+            "cancel 1000", "level ASK 0x100", "bbo 5x90 -"
+        ].into_iter().map(String::from).collect();
+
+        assert_eq!(*events1.lock().unwrap(), expected_events);
+        assert_eq!(*events2.lock().unwrap(), expected_events);
+
+        /* the duplicate leaves the original order as it was */
+        assert_eq!(actual_book.order_status(1002), Some(OrderStatus::New));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize_roundtrip() -> Result<(), BookError> {
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), 20);
+
+        let mut actual_accounts: Accounts = Accounts::new();
+        actual_accounts.insert(1, Account::new(1, "John Doe".to_string(),
+            usd(5000), HashMap::new()));
+        actual_accounts.insert(2, Account::new(2, "Jane Doe".to_string(),
+            usd(0), holdings));
+
+        let mut actual_book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+
+        actual_book.submit(Order::new(1000, 2, OrderType::Ask, 100, 10),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1001, 2, OrderType::Ask, 100, 10),
+            &mut actual_accounts)?;
+        actual_book.submit(Order::new(1002, 1, OrderType::Bid, 100, 15),
+            &mut actual_accounts)?;
+
+        let book_json: String = serde_json::to_string(&actual_book).unwrap();
+        let accounts_json: String =
+            serde_json::to_string(&actual_accounts).unwrap();
+        let error_json: String = serde_json::to_string(
+            &BookError::Account(AccountError::InsufficientFunds)).unwrap();
+
+        assert_eq!(serde_json::from_str::<Book>(&book_json).unwrap(),
+            actual_book);
+        assert_eq!(serde_json::from_str::<Accounts>(&accounts_json).unwrap(),
+            actual_accounts);
+        assert_eq!(error_json, "{\"Account\":\"InsufficientFunds\"}");
+
+        Ok(())
+    }
+}
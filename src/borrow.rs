@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::account::{Account, AccountBalance, AccountError, AccountHolding,
+    Currency};
+use crate::snapshot::{Codec, Decoder, Encoder, SnapshotError};
+
+#[derive(Copy, Clone, Debug, Error)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BorrowError {
+    NotLendable,
+    InsufficientInventory,
+    Account(AccountError)
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BorrowError::NotLendable => write!(f, "Asset cannot be borrowed"),
+            BorrowError::InsufficientInventory =>
+                write!(f, "Insufficient lendable inventory"),
+            BorrowError::Account(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl From<AccountError> for BorrowError {
+    fn from(e: AccountError) -> Self {
+        BorrowError::Account(e)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lendable {
+    available: AccountHolding,
+    fee: AccountBalance,
+    currency: Currency
+}
+
+impl Lendable {
+    pub fn new(available: AccountHolding, fee: AccountBalance,
+        currency: Currency) -> Self {
+        Lendable {
+            available,
+            fee,
+            currency
+        }
+    }
+
+    pub fn available(&self) -> AccountHolding {
+        self.available
+    }
+
+    pub fn fee(&self) -> AccountBalance {
+        self.fee
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency.clone()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BorrowRegistry {
+    lendables: HashMap<String, Lendable>
+}
+
+impl BorrowRegistry {
+    pub fn new() -> Self {
+        BorrowRegistry::default()
+    }
+
+    pub fn lendable(&self, ticker: String) -> Option<&Lendable> {
+        self.lendables.get(&ticker)
+    }
+
+    /* fee is charged per unit borrowed, per session */
+    pub fn set_lendable(&mut self, ticker: String, lendable: Lendable) {
+        self.lendables.insert(ticker, lendable);
+    }
+
+    pub fn locate(&mut self, account: &mut Account, ticker: String,
+        quantity: AccountHolding) -> Result<(), BorrowError> {
+        let lendable: &mut Lendable = match self.lendables.get_mut(&ticker) {
+            Some(l) => l,
+            None => return Err(BorrowError::NotLendable)
+        };
+
+        if quantity > lendable.available {
+            return Err(BorrowError::InsufficientInventory);
+        }
+
+        account.add_borrow(ticker, quantity)?;
+        lendable.available -= quantity;
+        Ok(())
+    }
+
+    pub fn release(&mut self, account: &mut Account, ticker: String,
+        quantity: AccountHolding) -> Result<(), BorrowError> {
+        let lendable: &mut Lendable = match self.lendables.get_mut(&ticker) {
+            Some(l) => l,
+            None => return Err(BorrowError::NotLendable)
+        };
+
+        account.take_borrow(ticker, quantity)?;
+        lendable.available += quantity;
+        Ok(())
+    }
+
+    /* charges one session's worth of fees on everything the account has
+     * borrowed; nothing is charged unless the account can pay in full */
+    pub fn accrue_fees(&self, account: &mut Account) ->
+        Result<HashMap<Currency, AccountBalance>, BorrowError> {
+        let mut fees: HashMap<Currency, AccountBalance> = HashMap::new();
+
+        for (ticker, quantity) in account.borrows().iter() {
+            if let Some(lendable) = self.lendables.get(ticker) {
+                let fee: AccountBalance = match lendable.fee
+                    .checked_mul(*quantity) {
+                    Some(f) => f,
+                    None => return Err(AccountError::BalanceOutOfBounds.into())
+                };
+
+                let total: &mut AccountBalance =
+                    fees.entry(lendable.currency()).or_insert(0);
+
+                *total = match total.checked_add(fee) {
+                    Some(t) => t,
+                    None => return Err(AccountError::BalanceOutOfBounds.into())
+                };
+            }
+        }
+
+        for (currency, fee) in fees.iter() {
+            match account.balance(currency.clone()) {
+                Some(balance) if balance >= *fee => {},
+                Some(_) => return Err(AccountError::InsufficientFunds.into()),
+                None if *fee == 0 => {},
+                None => return Err(AccountError::CurrencyNotFound.into())
+            }
+        }
+
+        for (currency, fee) in fees.iter() {
+            if *fee > 0 {
+                account.take_balance(currency.clone(), *fee)?;
+            }
+        }
+
+        Ok(fees)
+    }
+}
+
+impl Codec for Lendable {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.available.encode(encoder);
+        self.fee.encode(encoder);
+        self.currency.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(Lendable {
+            available: Codec::decode(decoder)?,
+            fee: Codec::decode(decoder)?,
+            currency: Codec::decode(decoder)?
+        })
+    }
+}
+
+impl Codec for BorrowRegistry {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.lendables.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(BorrowRegistry {
+            lendables: Codec::decode(decoder)?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate_and_accrue_fees() -> Result<(), BorrowError> {
+        let mut balances: HashMap<Currency, AccountBalance> = HashMap::new();
+        balances.insert("USD".to_string(), 150);
+
+        let mut actual_account: Account = Account::new(1,
+            "John Doe".to_string(), balances, HashMap::new());
+        let mut actual_registry: BorrowRegistry = BorrowRegistry::new();
+        actual_registry.set_lendable("VOC".to_string(),
+            Lendable::new(50, 2, "USD".to_string()));
+
+        assert!(matches!(actual_registry.locate(&mut actual_account,
+            "VOC".to_string(), 60), Err(BorrowError::InsufficientInventory)));
+        assert!(matches!(actual_registry.locate(&mut actual_account,
+            "MSFT".to_string(), 1), Err(BorrowError::NotLendable)));
+
+        actual_registry.locate(&mut actual_account, "VOC".to_string(), 30)?;
+        actual_registry.accrue_fees(&mut actual_account)?;
+        actual_registry.accrue_fees(&mut actual_account)?;
+        actual_registry.release(&mut actual_account, "VOC".to_string(), 10)?;
+
+        assert!(matches!(actual_registry.accrue_fees(&mut actual_account),
+            Err(BorrowError::Account(AccountError::InsufficientFunds))));
+
+        assert_eq!(actual_account.balance("USD".to_string()), Some(30));
+        assert_eq!(actual_account.borrowed("VOC".to_string()), 20);
+        assert_eq!(actual_registry.lendable("VOC".to_string()).unwrap()
+            .available(), 30);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,384 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::account::AccountBalance;
+use crate::order::{OrderPrice, OrderQuantity};
+use crate::snapshot::{Codec, Decoder, Encoder, SnapshotError};
+
+/* a number of decimal places */
+pub type Scale = u32;
+
+/* the most decimal places a balance can carry a whole unit to */
+pub const MAX_SCALE: Scale = AccountBalance::MAX.ilog10();
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DecimalError {
+    Malformed(String),
+    TooPrecise(Scale),
+    OutOfRange,
+    ScaleMismatch(Scale, Scale)
+}
+
+impl fmt::Display for DecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecimalError::Malformed(text) =>
+                write!(f, "'{}' is not a decimal number", text),
+            DecimalError::TooPrecise(scale) =>
+                write!(f, "More than {} decimal places", scale),
+            DecimalError::OutOfRange => write!(f, "Number out of range"),
+            DecimalError::ScaleMismatch(left, right) =>
+                write!(f, "Cannot combine {} and {} decimal places", left,
+                    right)
+        }
+    }
+}
+
+fn unit(scale: Scale) -> Result<AccountBalance, DecimalError> {
+    (10 as AccountBalance).checked_pow(scale)
+        .ok_or(DecimalError::TooPrecise(MAX_SCALE))
+}
+
+/* the number of smallest units in `text`, a plain decimal such as "12.34";
+ * there may be fewer decimal places than `scale`, but not more */
+fn parse(text: &str, scale: Scale) -> Result<AccountBalance, DecimalError> {
+    let malformed = || DecimalError::Malformed(text.to_string());
+    let (whole, fraction): (&str, &str) = match text.split_once('.') {
+        Some((whole, fraction)) if !fraction.is_empty() => (whole, fraction),
+        Some(_) => return Err(malformed()),
+        None => (text, "")
+    };
+
+    if whole.is_empty() || !whole.bytes().chain(fraction.bytes())
+        .all(|b| b.is_ascii_digit()) {
+        return Err(malformed());
+    }
+
+    if fraction.len() as u64 > scale as u64 {
+        return Err(DecimalError::TooPrecise(scale));
+    }
+
+    let padding: Scale = scale - fraction.len() as Scale;
+    let whole: AccountBalance = whole.parse()
+        .map_err(|_| DecimalError::OutOfRange)?;
+    let fraction: AccountBalance = match fraction.is_empty() {
+        true => 0,
+        false => fraction.parse().map_err(|_| DecimalError::OutOfRange)?
+    };
+
+    whole.checked_mul(unit(scale)?)
+        .and_then(|units| units.checked_add(fraction * unit(padding).ok()?))
+        .ok_or(DecimalError::OutOfRange)
+}
+
+fn format(units: AccountBalance, scale: Scale, f: &mut fmt::Formatter) ->
+    fmt::Result {
+    match unit(scale) {
+        Ok(1) | Err(_) => write!(f, "{}", units),
+        Ok(unit) => write!(f, "{}.{:0width$}", units / unit, units % unit,
+            width = scale as usize)
+    }
+}
+
+/* the decimal places an instrument's prices and quantities are given to;
+ * prices are also what balances in the quote are counted in, so an
+ * exchange refuses books which disagree over a currency's scale */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "PrecisionIn"))]
+pub struct Precision {
+    price: Scale,
+    quantity: Scale
+}
+
+/* what is read back goes through `Precision::new` */
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct PrecisionIn {
+    price: Scale,
+    quantity: Scale
+}
+
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<PrecisionIn> for Precision {
+    type Error = DecimalError;
+
+    fn try_from(input: PrecisionIn) -> Result<Self, Self::Error> {
+        Precision::new(input.price, input.quantity)
+    }
+}
+
+impl Precision {
+    pub fn new(price: Scale, quantity: Scale) -> Result<Self, DecimalError> {
+        match price.max(quantity) {
+            scale if scale > MAX_SCALE =>
+                Err(DecimalError::TooPrecise(MAX_SCALE)),
+            _ => Ok(Precision {
+                price,
+                quantity
+            })
+        }
+    }
+
+    pub fn price(&self) -> Scale {
+        self.price
+    }
+
+    pub fn quantity(&self) -> Scale {
+        self.quantity
+    }
+
+    /* the units of a price and quantity given to exactly these places */
+    pub fn units(&self, price: Price, quantity: Quantity) ->
+        Result<(OrderPrice, OrderQuantity), DecimalError> {
+        match (price.scale(), quantity.scale()) {
+            (scale, _) if scale != self.price =>
+                Err(DecimalError::ScaleMismatch(self.price, scale)),
+            (_, scale) if scale != self.quantity =>
+                Err(DecimalError::ScaleMismatch(self.quantity, scale)),
+            _ => Ok((price.units(), quantity.units()))
+        }
+    }
+
+    /* what `quantity` at `price` comes to in units of the quote, with any
+     * fraction of a unit rounded up against the buyer */
+    pub fn notional(&self, price: OrderPrice, quantity: OrderQuantity) ->
+        Option<AccountBalance> {
+        let product: AccountBalance = price.checked_mul(quantity)?;
+        let unit: AccountBalance = unit(self.quantity).ok()?;
+
+        Some(product.div_ceil(unit))
+    }
+
+    /* the price at which `quantity` comes to `notional`, rounded down */
+    pub fn price_for(&self, quantity: OrderQuantity, notional: AccountBalance)
+        -> Option<OrderPrice> {
+        notional.checked_mul(unit(self.quantity).ok()?)?.checked_div(quantity)
+    }
+
+    /* the least quantity which at `price` comes to at least `notional` */
+    pub fn quantity_for(&self, price: OrderPrice, notional: AccountBalance) ->
+        Option<OrderQuantity> {
+        let units: AccountBalance =
+            notional.checked_mul(unit(self.quantity).ok()?)?;
+
+        units.checked_div(price).map(|_| units.div_ceil(price))
+    }
+}
+
+macro_rules! decimal {
+    ($name:ident, $units:ty) => {
+        /* a whole number of the smallest units at some scale; only values
+         * at the same scale can be compared or combined */
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+        #[cfg_attr(feature = "serde",
+            derive(serde::Serialize, serde::Deserialize))]
+        pub struct $name {
+            units: $units,
+            scale: Scale
+        }
+
+        impl $name {
+            pub fn new(units: $units, scale: Scale) -> Self {
+                $name {
+                    units,
+                    scale
+                }
+            }
+
+            pub fn parse(text: &str, scale: Scale) ->
+                Result<Self, DecimalError> {
+                Ok($name::new(parse(text, scale)?, scale))
+            }
+
+            pub fn units(&self) -> $units {
+                self.units
+            }
+
+            pub fn scale(&self) -> Scale {
+                self.scale
+            }
+
+            /* exact only: adding places always works, but removing them
+             * fails if any of the removed digits are nonzero */
+            pub fn rescale(&self, scale: Scale) ->
+                Result<Self, DecimalError> {
+                let units: $units = if scale >= self.scale {
+                    self.units.checked_mul(unit(scale - self.scale)?)
+                        .ok_or(DecimalError::OutOfRange)?
+                } else {
+                    let divisor: AccountBalance = unit(self.scale - scale)?;
+
+                    if !self.units.is_multiple_of(divisor) {
+                        return Err(DecimalError::TooPrecise(scale));
+                    }
+
+                    self.units / divisor
+                };
+
+                Ok($name::new(units, scale))
+            }
+
+            pub fn checked_add(&self, other: $name) ->
+                Result<Self, DecimalError> {
+                self.same_scale(&other)?;
+                self.units.checked_add(other.units)
+                    .map(|units| $name::new(units, self.scale))
+                    .ok_or(DecimalError::OutOfRange)
+            }
+
+            pub fn checked_sub(&self, other: $name) ->
+                Result<Self, DecimalError> {
+                self.same_scale(&other)?;
+                self.units.checked_sub(other.units)
+                    .map(|units| $name::new(units, self.scale))
+                    .ok_or(DecimalError::OutOfRange)
+            }
+
+            fn same_scale(&self, other: &$name) -> Result<(), DecimalError> {
+                match self.scale == other.scale {
+                    true => Ok(()),
+                    false => Err(DecimalError::ScaleMismatch(self.scale,
+                        other.scale))
+                }
+            }
+        }
+
+        impl PartialOrd for $name {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                self.same_scale(other).ok()?;
+                self.units.partial_cmp(&other.units)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                format(self.units, self.scale, f)
+            }
+        }
+    };
+}
+
+decimal!(Price, OrderPrice);
+decimal!(Quantity, OrderQuantity);
+
+impl Price {
+    /* what `quantity` comes to at this price, in the quote and to this
+     * price's scale */
+    pub fn notional(&self, quantity: Quantity) -> Result<Price, DecimalError> {
+        Precision::new(self.scale, quantity.scale)?
+            .notional(self.units, quantity.units)
+            .map(|units| Price::new(units, self.scale))
+            .ok_or(DecimalError::OutOfRange)
+    }
+}
+
+impl Codec for Precision {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.price.encode(encoder);
+        self.quantity.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Precision::new(Codec::decode(decoder)?, Codec::decode(decoder)?)
+            .map_err(|_| SnapshotError::Malformed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() -> Result<(), DecimalError> {
+        assert_eq!(Price::parse("12.34", 2)?, Price::new(1234, 2));
+        assert_eq!(Price::parse("12.3", 2)?, Price::new(1230, 2));
+        assert_eq!(Price::parse("12", 2)?, Price::new(1200, 2));
+        assert_eq!(Quantity::parse("0.00000001", 8)?, Quantity::new(1, 8));
+        assert_eq!(Quantity::parse("7", 0)?, Quantity::new(7, 0));
+
+        assert_eq!(Price::new(1234, 2).to_string(), "12.34");
+        assert_eq!(Price::new(5, 2).to_string(), "0.05");
+        assert_eq!(Quantity::new(150_000_000, 8).to_string(), "1.50000000");
+        assert_eq!(Quantity::new(42, 0).to_string(), "42");
+
+        assert_eq!(Price::parse("12.345", 2), Err(DecimalError::TooPrecise(2)));
+        assert_eq!(Price::parse("1", MAX_SCALE + 1),
+            Err(DecimalError::TooPrecise(MAX_SCALE)));
+        assert_eq!(Price::parse(&format!("{}0", OrderPrice::MAX), 0),
+            Err(DecimalError::OutOfRange));
+
+        for text in ["", ".5", "1.", "-1", "1.2.3", "1e3", " 1"] {
+            assert_eq!(Price::parse(text, 2),
+                Err(DecimalError::Malformed(text.to_string())));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checked_arithmetic() -> Result<(), DecimalError> {
+        let cents: Price = Price::new(1234, 2);
+
+        assert_eq!(cents.checked_add(Price::new(66, 2))?, Price::new(1300, 2));
+        assert_eq!(cents.checked_sub(Price::new(1234, 2))?, Price::new(0, 2));
+        assert_eq!(cents.checked_sub(Price::new(1235, 2)),
+            Err(DecimalError::OutOfRange));
+        assert_eq!(Price::new(OrderPrice::MAX, 0).checked_add(Price::new(1, 0)),
+            Err(DecimalError::OutOfRange));
+
+        /* units never mix silently */
+        assert_eq!(cents.checked_add(Price::new(1234, 4)),
+            Err(DecimalError::ScaleMismatch(2, 4)));
+        assert_eq!(cents.partial_cmp(&Price::new(1, 4)), None);
+        assert!(cents < Price::new(1235, 2));
+
+        assert_eq!(cents.rescale(4)?, Price::new(123_400, 4));
+        assert_eq!(Price::new(123_400, 4).rescale(2)?, cents);
+        assert_eq!(cents.rescale(1), Err(DecimalError::TooPrecise(1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_notional() -> Result<(), DecimalError> {
+        /* 1.5 shares at $12.34 is $18.51 */
+        assert_eq!(Price::new(1234, 2).notional(Quantity::new(15, 1))?,
+            Price::new(1851, 2));
+        /* a satoshi at a cent is a cent, rounded up */
+        assert_eq!(Price::new(1, 2).notional(Quantity::new(1, 8))?,
+            Price::new(1, 2));
+        assert_eq!(Precision::new(0, 0)?.notional(100, 5), Some(500));
+        assert_eq!(Precision::new(0, 0)?.notional(OrderPrice::MAX, 2), None);
+        assert_eq!(Precision::new(0, MAX_SCALE + 1),
+            Err(DecimalError::TooPrecise(MAX_SCALE)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_units() -> Result<(), DecimalError> {
+        let precision: Precision = Precision::new(2, 8)?;
+
+        assert_eq!(precision.units(Price::new(1234, 2),
+            Quantity::new(1, 8)), Ok((1234, 1)));
+        assert_eq!(precision.units(Price::new(1234, 0), Quantity::new(1, 8)),
+            Err(DecimalError::ScaleMismatch(2, 0)));
+        assert_eq!(precision.units(Price::new(1234, 2), Quantity::new(1, 2)),
+            Err(DecimalError::ScaleMismatch(8, 2)));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_validates() {
+        assert_eq!(serde_json::from_str::<Precision>(
+            "{\"price\":2,\"quantity\":8}").unwrap(), Precision::new(2, 8)
+            .unwrap());
+        assert!(serde_json::from_str::<Precision>(&format!(
+            "{{\"price\":2,\"quantity\":{}}}", MAX_SCALE + 1)).is_err());
+    }
+}
@@ -0,0 +1,626 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use thiserror::Error;
+
+use crate::account::{Account, AccountId, Accounts};
+use crate::book::{Book, BookError, Ledger, Level};
+use crate::execution::{ExecutionReport, OrderState};
+use crate::order::{Order, OrderId, OrderPrice, OrderQuantity};
+
+#[derive(Debug, Error)]
+pub enum EngineError {
+    BookNotFound,
+    DuplicateBook,
+    Stopped,
+    Poisoned,
+    ShardPanicked,
+    Book(BookError)
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::BookNotFound => write!(f, "No such book"),
+            EngineError::DuplicateBook =>
+                write!(f, "Ticker already has a book"),
+            EngineError::Stopped => write!(f, "Engine has stopped"),
+            EngineError::Poisoned =>
+                write!(f, "Accounts were left part way through a request"),
+            EngineError::ShardPanicked => write!(f, "A shard has panicked"),
+            EngineError::Book(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl From<BookError> for EngineError {
+    fn from(e: BookError) -> Self {
+        match e {
+            BookError::AccountsUnavailable => EngineError::Poisoned,
+            e => EngineError::Book(e)
+        }
+    }
+}
+
+/* the accounts every shard settles against, locked afresh for each check
+ * and trade */
+struct Shared<'a>(&'a Mutex<Accounts>);
+
+impl Ledger for Shared<'_> {
+    fn with<R>(&mut self, f: impl FnOnce(&mut Accounts) -> R) ->
+        Result<R, BookError> {
+        let mut accounts: MutexGuard<Accounts> = self.0.lock()
+            .map_err(|_| BookError::AccountsUnavailable)?;
+
+        Ok(f(&mut accounts))
+    }
+}
+
+/* what the book for a ticker can be asked to do */
+#[derive(Debug)]
+pub enum Request {
+    AddBook(Box<Book>),
+    Submit(Order),
+    SubmitIoc(Order),
+    Cancel(OrderId),
+    Amend {
+        id: OrderId,
+        price: OrderPrice,
+        quantity: OrderQuantity
+    },
+    Depth(usize),
+    Status(OrderId)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    /* the execution reports the request gave rise to, in order */
+    Reports(Vec<ExecutionReport>),
+    Depth(Vec<Level>, Vec<Level>),
+    /* where an order stands, even after it has left the book */
+    Status(Option<OrderState>)
+}
+
+pub type Outcome = Result<Response, EngineError>;
+
+/* where the outcome of a request arrives once it has been carried out */
+#[derive(Debug)]
+pub struct Reply(Receiver<Outcome>);
+
+impl Reply {
+    pub fn wait(self) -> Outcome {
+        self.0.recv().unwrap_or(Err(EngineError::Stopped))
+    }
+}
+
+enum Message {
+    Request(String, Request, SyncSender<Outcome>),
+    Stop
+}
+
+/* sends requests to an engine's books, from whichever thread holds it;
+ * requests for the same book are carried out in the order they were sent,
+ * so any one sender sees its own requests take effect in order */
+#[derive(Clone, Debug)]
+pub struct Handle {
+    shards: Vec<SyncSender<Message>>
+}
+
+impl Handle {
+    /* queues the request without waiting for it to be carried out, unless
+     * the book's shard already has as many queued as it will take */
+    pub fn send(&self, ticker: &str, request: Request) ->
+        Result<Reply, EngineError> {
+        let (reply, receiver): (SyncSender<Outcome>, Receiver<Outcome>) =
+            mpsc::sync_channel(1);
+
+        self.shards[Engine::shard(ticker, self.shards.len())]
+            .send(Message::Request(ticker.to_string(), request, reply))
+            .map_err(|_| EngineError::Stopped)?;
+        Ok(Reply(receiver))
+    }
+
+    pub fn add_book(&self, book: Book) -> Result<Reply, EngineError> {
+        let ticker: String = book.ticker();
+
+        self.send(&ticker, Request::AddBook(Box::new(book)))
+    }
+
+    pub fn submit(&self, ticker: &str, order: Order) ->
+        Result<Reply, EngineError> {
+        self.send(ticker, Request::Submit(order))
+    }
+
+    pub fn submit_ioc(&self, ticker: &str, order: Order) ->
+        Result<Reply, EngineError> {
+        self.send(ticker, Request::SubmitIoc(order))
+    }
+
+    pub fn cancel(&self, ticker: &str, id: OrderId) ->
+        Result<Reply, EngineError> {
+        self.send(ticker, Request::Cancel(id))
+    }
+
+    pub fn amend(&self, ticker: &str, id: OrderId, price: OrderPrice,
+        quantity: OrderQuantity) -> Result<Reply, EngineError> {
+        self.send(ticker, Request::Amend {
+            id,
+            price,
+            quantity
+        })
+    }
+
+    pub fn depth(&self, ticker: &str, levels: usize) ->
+        Result<Reply, EngineError> {
+        self.send(ticker, Request::Depth(levels))
+    }
+
+    pub fn status(&self, ticker: &str, id: OrderId) ->
+        Result<Reply, EngineError> {
+        self.send(ticker, Request::Status(id))
+    }
+}
+
+/* books run on threads of their own, each thread (or shard) owning the books
+ * for some share of the tickers and working through the requests for them
+ * one at a time. The accounts are shared between the shards behind a single
+ * lock, so that trading in any book settles against the same balances; a
+ * shard takes it only to check an order or settle a trade, so shards match
+ * in parallel and wait on one another only to settle. A shard which panics
+ * while settling leaves the accounts poisoned, after which every request
+ * needing them fails. Unlike an `Exchange`, an
+ * engine neither journals requests nor checks margin, nor that its books
+ * agree on the places of the currencies they share */
+#[derive(Debug)]
+pub struct Engine {
+    handle: Handle,
+    accounts: Arc<Mutex<Accounts>>,
+    threads: Vec<JoinHandle<Vec<Book>>>
+}
+
+impl Engine {
+    /* each shard queues at most `capacity` requests before senders wait */
+    pub fn new(accounts: Accounts, shards: usize, capacity: usize) -> Self {
+        let accounts: Arc<Mutex<Accounts>> = Arc::new(Mutex::new(accounts));
+        let mut senders: Vec<SyncSender<Message>> = vec![];
+        let mut threads: Vec<JoinHandle<Vec<Book>>> = vec![];
+
+        for _ in 0..shards.max(1) {
+            let (sender, receiver): (SyncSender<Message>, Receiver<Message>) =
+                mpsc::sync_channel(capacity);
+            let shared: Arc<Mutex<Accounts>> = Arc::clone(&accounts);
+
+            senders.push(sender);
+            threads.push(thread::spawn(move || Engine::run(receiver,
+                shared)));
+        }
+
+        Engine {
+            handle: Handle {
+                shards: senders
+            },
+            accounts,
+            threads
+        }
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    pub fn shards(&self) -> usize {
+        self.threads.len()
+    }
+
+    /* the accounts as they stand between requests */
+    pub fn accounts(&self) -> Result<Accounts, EngineError> {
+        Ok(Engine::lock(&self.accounts)?.clone())
+    }
+
+    pub fn account(&self, id: AccountId) ->
+        Result<Option<Account>, EngineError> {
+        Ok(Engine::lock(&self.accounts)?.get(&id).cloned())
+    }
+
+    /* carries out every request already queued, then stops the shards and
+     * hands back the books, by ticker, and the accounts; requests sent
+     * afterwards through any handle fail with `EngineError::Stopped`. Should
+     * any shard have panicked, its books are lost and nothing is handed
+     * back */
+    pub fn shutdown(self) ->
+        Result<(BTreeMap<String, Book>, Accounts), EngineError> {
+        for shard in self.handle.shards.iter() {
+            let _ = shard.send(Message::Stop);
+        }
+
+        let mut books: BTreeMap<String, Book> = BTreeMap::new();
+        let mut panicked: bool = false;
+
+        /* every shard is joined, even after one is found to have panicked */
+        for thread in self.threads.into_iter() {
+            match thread.join() {
+                Ok(shard) => books.extend(shard.into_iter()
+                    .map(|book| (book.ticker(), book))),
+                Err(_) => panicked = true
+            }
+        }
+
+        if panicked {
+            return Err(EngineError::ShardPanicked);
+        }
+
+        let accounts: Accounts = Engine::lock(&self.accounts)?.clone();
+        Ok((books, accounts))
+    }
+
+    fn lock(accounts: &Mutex<Accounts>) ->
+        Result<MutexGuard<'_, Accounts>, EngineError> {
+        accounts.lock().map_err(|_| EngineError::Poisoned)
+    }
+
+    /* the same ticker goes to the same shard every time */
+    fn shard(ticker: &str, shards: usize) -> usize {
+        let mut hasher: DefaultHasher = DefaultHasher::new();
+
+        ticker.hash(&mut hasher);
+        (hasher.finish() % shards as u64) as usize
+    }
+
+    /* a requester which has gone away without waiting for its reply does
+     * not stop the shard */
+    fn run(receiver: Receiver<Message>, accounts: Arc<Mutex<Accounts>>) ->
+        Vec<Book> {
+        let mut books: HashMap<String, Book> = HashMap::new();
+
+        while let Ok(Message::Request(ticker, request, reply)) =
+            receiver.recv() {
+            let _ = reply.send(Engine::carry_out(&mut books, &accounts,
+                ticker, request));
+        }
+
+        books.into_values().collect()
+    }
+
+    fn carry_out(books: &mut HashMap<String, Book>,
+        accounts: &Mutex<Accounts>, ticker: String, request: Request) ->
+        Outcome {
+        if let Request::AddBook(book) = request {
+            if books.contains_key(&ticker) {
+                return Err(EngineError::DuplicateBook);
+            }
+
+            books.insert(ticker, *book);
+            return Ok(Response::Reports(vec![]));
+        }
+
+        let book: &mut Book = books.get_mut(&ticker)
+            .ok_or(EngineError::BookNotFound)?;
+
+        let result: Result<(), BookError> = match request {
+            Request::AddBook(_) => unreachable!(),
+            Request::Submit(order) => book.submit(order, &mut Shared(accounts)),
+            Request::SubmitIoc(order) =>
+                book.submit_ioc(order, &mut Shared(accounts)).map(|_| ()),
+            Request::Cancel(id) => book.cancel(id),
+            Request::Amend { id, price, quantity } => book.amend(id, price,
+                quantity, &mut Shared(accounts)),
+            Request::Depth(levels) => {
+                let (bids, asks): (Vec<Level>, Vec<Level>) =
+                    book.depth(levels);
+                return Ok(Response::Depth(bids, asks));
+            },
+            Request::Status(id) =>
+                return Ok(Response::Status(book.order_state(id)))
+        };
+
+        /* the reports are handed back, so the book need not keep them, but
+         * the states of the orders they report on are kept */
+        let reports: Vec<ExecutionReport> = book.take_reports();
+
+        result?;
+        Ok(Response::Reports(reports))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{AccountBalance, Currency};
+    use crate::book::BookId;
+    use crate::execution::ExecType;
+    use std::sync::mpsc::Sender;
+    use std::sync::{Condvar, WaitTimeoutResult};
+    use std::time::Duration;
+    use crate::listener::{BookListener, Trade};
+    use crate::order::{OrderStatus, OrderType};
+
+    const TICKERS: [&str; 4] = ["VOC", "WIC", "EIC", "GWC"];
+
+    fn accounts() -> Accounts {
+        let mut accounts: Accounts = Accounts::new();
+
+        for id in 1..3 {
+            let mut balances: HashMap<Currency, AccountBalance> =
+                HashMap::new();
+            balances.insert("USD".to_string(), 1_000_000_000);
+
+            let mut account: Account = Account::new(id, format!("{}", id),
+                balances, HashMap::new());
+
+            for ticker in TICKERS.iter() {
+                account.add_holding(ticker.to_string(), 1_000_000).unwrap();
+            }
+
+            accounts.insert(id, account);
+        }
+
+        accounts
+    }
+
+    fn book(ticker: &str) -> Book {
+        Book::new(1, ticker.to_string(), ticker.to_string(), "USD".to_string())
+    }
+
+    /* bids and asks around 100, such that some cross and some rest */
+    fn orders(seed: OrderId) -> Vec<Order> {
+        (0..200).map(|i| {
+            let id: OrderId = seed * 1000 + i;
+            let (owner, r#type): (AccountId, OrderType) = match i % 2 {
+                0 => (1, OrderType::Bid),
+                _ => (2, OrderType::Ask)
+            };
+
+            Order::new(id, owner, r#type, 95 + (id * 7) % 11, 1 + id % 5)
+        }).collect()
+    }
+
+    /* requests sent without waiting are carried out in the order sent */
+    #[test]
+    fn test_send_in_order() -> Result<(), EngineError> {
+        let engine: Engine = Engine::new(accounts(), 2, 4);
+        let handle: Handle = engine.handle();
+
+        handle.add_book(book("VOC"))?.wait()?;
+
+        let replies: Vec<Reply> = (1..=50)
+            .map(|id| handle.submit("VOC", Order::new(id, 2, OrderType::Ask,
+                100, 1)))
+            .collect::<Result<Vec<Reply>, EngineError>>()?;
+
+        for reply in replies.into_iter() {
+            reply.wait()?;
+        }
+
+        let response: Response = handle.submit("VOC", Order::new(51, 1,
+            OrderType::Bid, 100, 2))?.wait()?;
+        let (books, _): (BTreeMap<String, Book>, Accounts) =
+            engine.shutdown()?;
+
+        match response {
+            Response::Reports(reports) => {
+                let filled: Vec<OrderId> = reports.iter()
+                    .filter(|report| report.exec_type() == ExecType::Trade &&
+                        report.order_id() != 51)
+                    .map(|report| report.order_id())
+                    .collect();
+
+                assert_eq!(filled, vec![1, 2]);
+            },
+            _ => panic!("expected reports")
+        };
+
+        let ids: Vec<OrderId> = books["VOC"].orders(OrderType::Ask)
+            .map(|view| view.id())
+            .collect();
+
+        assert_eq!(ids, (3..=50).collect::<Vec<OrderId>>());
+
+        Ok(())
+    }
+
+    /* books fed by threads of their own end up as if each had been fed
+     * alone, however many shards they are spread over */
+    #[test]
+    fn test_send_concurrently() -> Result<(), EngineError> {
+        let mut expected: BTreeMap<String, Book> = BTreeMap::new();
+        let mut expected_accounts: Accounts = accounts();
+
+        for (seed, ticker) in TICKERS.iter().enumerate() {
+            let mut book: Book = book(ticker);
+
+            for order in orders(seed as OrderId).into_iter() {
+                book.submit(order, &mut expected_accounts)?;
+            }
+
+            book.take_reports();
+            expected.insert(ticker.to_string(), book);
+        }
+
+        for shards in [1, 3] {
+            let engine: Engine = Engine::new(accounts(), shards, 8);
+
+            thread::scope(|scope| {
+                for (seed, ticker) in TICKERS.iter().enumerate() {
+                    let handle: Handle = engine.handle();
+
+                    scope.spawn(move || {
+                        handle.add_book(book(ticker)).unwrap().wait()
+                            .unwrap();
+
+                        for order in orders(seed as OrderId).into_iter() {
+                            handle.submit(ticker, order).unwrap().wait()
+                                .unwrap();
+                        }
+                    });
+                }
+            });
+
+            let (books, accounts): (BTreeMap<String, Book>, Accounts) =
+                engine.shutdown()?;
+
+            assert_eq!(books, expected);
+            assert_eq!(accounts, expected_accounts);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_errors() -> Result<(), EngineError> {
+        let engine: Engine = Engine::new(accounts(), 2, 4);
+        let handle: Handle = engine.handle();
+
+        handle.add_book(book("VOC"))?.wait()?;
+
+        assert!(matches!(handle.add_book(book("VOC"))?.wait(),
+            Err(EngineError::DuplicateBook)));
+        assert!(matches!(handle.cancel("WIC", 1)?.wait(),
+            Err(EngineError::BookNotFound)));
+        assert!(matches!(handle.cancel("VOC", 1)?.wait(),
+            Err(EngineError::Book(BookError::OrderNotFound))));
+        assert_eq!(handle.depth("VOC", 1)?.wait()?,
+            Response::Depth(vec![], vec![]));
+
+        engine.shutdown()?;
+
+        assert!(matches!(handle.depth("VOC", 1),
+            Err(EngineError::Stopped)));
+
+        Ok(())
+    }
+
+    /* orders are remembered once they have left the book */
+    #[test]
+    fn test_send_status() -> Result<(), EngineError> {
+        let engine: Engine = Engine::new(accounts(), 2, 4);
+        let handle: Handle = engine.handle();
+
+        handle.add_book(book("VOC"))?.wait()?;
+        handle.submit("VOC", Order::new(1, 2, OrderType::Ask, 100, 2))?
+            .wait()?;
+        handle.submit("VOC", Order::new(2, 1, OrderType::Bid, 100, 2))?
+            .wait()?;
+
+        assert_eq!(handle.status("VOC", 1)?.wait()?, Response::Status(Some(
+            OrderState::new(OrderStatus::Filled, 2, 0))));
+        assert_eq!(handle.status("VOC", 3)?.wait()?, Response::Status(None));
+
+        /* and their ids are not taken again */
+        assert!(matches!(handle.submit("VOC", Order::new(1, 2, OrderType::Ask,
+            100, 2))?.wait(),
+            Err(EngineError::Book(BookError::DuplicateOrder))));
+
+        let (books, _): (BTreeMap<String, Book>, Accounts) =
+            engine.shutdown()?;
+
+        assert_eq!(books["VOC"].order_status(2), Some(OrderStatus::Filled));
+        assert!(books["VOC"].reports().is_empty());
+
+        Ok(())
+    }
+
+    struct Panicker;
+
+    impl BookListener for Panicker {
+        fn on_trade(&mut self, _book: BookId, _trade: &Trade) {
+            panic!("listener panicked");
+        }
+    }
+
+    /* a shard panicking outside settlement loses its books, but leaves the
+     * accounts as they were after the trade */
+    #[test]
+    fn test_send_after_panic() -> Result<(), EngineError> {
+        let engine: Engine = Engine::new(accounts(), 1, 4);
+        let handle: Handle = engine.handle();
+        let mut panicking: Book = book("VOC");
+        panicking.add_listener(Box::new(Panicker));
+
+        handle.add_book(panicking)?.wait()?;
+        handle.submit("VOC", Order::new(1, 2, OrderType::Ask, 100, 1))?
+            .wait()?;
+
+        /* the shard dies with the reply unsent */
+        assert!(matches!(handle.submit("VOC", Order::new(2, 1,
+            OrderType::Bid, 100, 1))?.wait(), Err(EngineError::Stopped)));
+        assert_eq!(engine.account(1)?.unwrap().holding("VOC".to_string()),
+            Some(1_000_001));
+        assert!(matches!(engine.shutdown(),
+            Err(EngineError::ShardPanicked)));
+
+        Ok(())
+    }
+
+    /* holds up each trade until one is under way in every book at once,
+     * reporting whether they all met before the timeout */
+    struct Rendezvous {
+        arrived: Arc<(Mutex<usize>, Condvar)>,
+        books: usize,
+        met: Sender<bool>
+    }
+
+    impl BookListener for Rendezvous {
+        fn on_trade(&mut self, _book: BookId, _trade: &Trade) {
+            let (count, all): &(Mutex<usize>, Condvar) = &self.arrived;
+            let mut arrived: MutexGuard<usize> = count.lock().unwrap();
+
+            *arrived += 1;
+            all.notify_all();
+
+            let (arrived, timeout): (MutexGuard<usize>, WaitTimeoutResult) =
+                all.wait_timeout_while(arrived, Duration::from_secs(5),
+                    |arrived| *arrived < self.books).unwrap();
+            drop(arrived);
+            self.met.send(!timeout.timed_out()).unwrap();
+        }
+    }
+
+    /* books on different shards are matching at the same time */
+    #[test]
+    fn test_send_in_parallel() -> Result<(), EngineError> {
+        let engine: Engine = Engine::new(accounts(), 2, 4);
+        let handle: Handle = engine.handle();
+        let arrived: Arc<(Mutex<usize>, Condvar)> =
+            Arc::new((Mutex::new(0), Condvar::new()));
+        let (met, meetings): (Sender<bool>, Receiver<bool>) = mpsc::channel();
+
+        /* one ticker for each shard */
+        let tickers: Vec<&str> = [0, 1].iter()
+            .map(|shard| *TICKERS.iter()
+                .find(|ticker| Engine::shard(ticker, 2) == *shard).unwrap())
+            .collect();
+
+        for ticker in tickers.iter() {
+            let mut book: Book = book(ticker);
+            book.add_listener(Box::new(Rendezvous {
+                arrived: Arc::clone(&arrived),
+                books: tickers.len(),
+                met: met.clone()
+            }));
+
+            handle.add_book(book)?.wait()?;
+            handle.submit(ticker, Order::new(1, 2, OrderType::Ask, 100, 1))?
+                .wait()?;
+        }
+
+        let replies: Vec<Reply> = tickers.iter()
+            .map(|ticker| handle.submit(ticker, Order::new(2, 1,
+                OrderType::Bid, 100, 1)))
+            .collect::<Result<Vec<Reply>, EngineError>>()?;
+
+        for reply in replies.into_iter() {
+            reply.wait()?;
+        }
+
+        assert_eq!(meetings.try_iter().collect::<Vec<bool>>(),
+            vec![true, true]);
+        engine.shutdown()?;
+
+        Ok(())
+    }
+}
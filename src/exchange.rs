@@ -0,0 +1,569 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::account::{Account, AccountBalance, AccountError, AccountHolding,
+    AccountId, Accounts, Currency};
+use crate::book::{Book, BookError, BookId, OrderView};
+use crate::borrow::{BorrowError, BorrowRegistry, Lendable};
+use crate::decimal::Scale;
+use crate::execution::ExecId;
+use crate::journal::{Command, Journal, Record};
+use crate::margin;
+use crate::order::{Order, OrderId, OrderPrice, OrderQuantity};
+use crate::snapshot::{Codec, Decoder, Encoder, SnapshotError};
+use crate::wal::{Wal, WalError};
+
+#[derive(Debug)]
+pub enum ExchangeError {
+    AccountNotFound,
+    BookNotFound,
+    DuplicateAccount,
+    DuplicateBook,
+    InsufficientBuyingPower,
+    ScaleMismatch(String, Scale, Scale),
+    Account(AccountError),
+    Book(BookError),
+    Borrow(BorrowError),
+    Wal(WalError)
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExchangeError::AccountNotFound => write!(f, "No such account"),
+            ExchangeError::BookNotFound => write!(f, "No such book"),
+            ExchangeError::DuplicateAccount =>
+                write!(f, "Account ID has already been used"),
+            ExchangeError::DuplicateBook =>
+                write!(f, "Book ID has already been used"),
+            ExchangeError::InsufficientBuyingPower =>
+                write!(f, "Insufficient buying power"),
+            ExchangeError::ScaleMismatch(symbol, scale, places) =>
+                write!(f, "{} is counted to {} decimal places, not {}", symbol,
+                    scale, places),
+            ExchangeError::Account(e) => write!(f, "{}", e),
+            ExchangeError::Book(e) => write!(f, "{}", e),
+            ExchangeError::Borrow(e) => write!(f, "{}", e),
+            ExchangeError::Wal(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl From<AccountError> for ExchangeError {
+    fn from(e: AccountError) -> Self {
+        ExchangeError::Account(e)
+    }
+}
+
+impl From<BookError> for ExchangeError {
+    fn from(e: BookError) -> Self {
+        ExchangeError::Book(e)
+    }
+}
+
+impl From<BorrowError> for ExchangeError {
+    fn from(e: BorrowError) -> Self {
+        ExchangeError::Borrow(e)
+    }
+}
+
+impl From<WalError> for ExchangeError {
+    fn from(e: WalError) -> Self {
+        ExchangeError::Wal(e)
+    }
+}
+
+/* every change made through an exchange's own methods is journalled; those
+ * made through `account_mut` or `book_mut` are not */
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Exchange {
+    accounts: Accounts,
+    books: BTreeMap<BookId, Book>,
+    borrows: BorrowRegistry,
+    next_order_id: OrderId,
+    journal: Journal,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    wal: Option<Wal>
+}
+
+impl Exchange {
+    pub fn new() -> Self {
+        Exchange::default()
+    }
+
+    pub fn accounts(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    pub fn account(&self, id: AccountId) -> Option<&Account> {
+        self.accounts.get(&id)
+    }
+
+    pub fn account_mut(&mut self, id: AccountId) -> Option<&mut Account> {
+        self.accounts.get_mut(&id)
+    }
+
+    pub fn add_account(&mut self, account: Account) ->
+        Result<(), ExchangeError> {
+        self.apply(Command::AddAccount(Box::new(account)))
+    }
+
+    pub fn books(&self) -> &BTreeMap<BookId, Book> {
+        &self.books
+    }
+
+    pub fn book(&self, id: BookId) -> Option<&Book> {
+        self.books.get(&id)
+    }
+
+    pub fn book_mut(&mut self, id: BookId) -> Option<&mut Book> {
+        self.books.get_mut(&id)
+    }
+
+    /* the decimal places amounts of a symbol are counted to, set by the
+     * first book to trade or quote it: a ticker is counted in the book's
+     * quantities, and a quote in its prices */
+    pub fn scale(&self, symbol: &str) -> Option<Scale> {
+        self.books.values().find_map(|book| Exchange::scale_in(book, symbol))
+    }
+
+    fn scale_in(book: &Book, symbol: &str) -> Option<Scale> {
+        if book.ticker() == symbol {
+            Some(book.precision().quantity())
+        } else if book.quote().symbol() == symbol {
+            Some(book.precision().price())
+        } else {
+            None
+        }
+    }
+
+    /* only the book's definition is journalled, so books are expected to be
+     * empty when they are added. Balances carry no scale of their own, so
+     * a book is refused unless it counts its ticker and quote to the same
+     * places as every book already trading or quoting them */
+    pub fn add_book(&mut self, book: Book) -> Result<(), ExchangeError> {
+        self.record(Command::AddBook {
+            id: book.id(),
+            name: book.name(),
+            ticker: book.ticker(),
+            quote: book.quote(),
+            precision: book.precision(),
+            band: book.band()
+        })?;
+
+        self.insert_book(book)
+    }
+
+    pub fn borrows(&self) -> &BorrowRegistry {
+        &self.borrows
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub fn wal(&self) -> Option<&Wal> {
+        self.wal.as_ref()
+    }
+
+    /* from then on, every command is written to the log before it is
+     * carried out */
+    pub fn set_wal(&mut self, wal: Option<Wal>) -> Option<Wal> {
+        std::mem::replace(&mut self.wal, wal)
+    }
+
+    /* an order id that has not been used by any order submitted so far */
+    pub fn next_order_id(&mut self) -> OrderId {
+        let id: OrderId = self.next_order_id;
+        self.next_order_id += 1;
+        id
+    }
+
+    pub fn deposit(&mut self, account: AccountId, currency: Currency,
+        amount: AccountBalance) -> Result<(), ExchangeError> {
+        self.apply(Command::Deposit {
+            account,
+            currency,
+            amount
+        })
+    }
+
+    pub fn deposit_holding(&mut self, account: AccountId, ticker: String,
+        quantity: AccountHolding) -> Result<(), ExchangeError> {
+        self.apply(Command::DepositHolding {
+            account,
+            ticker,
+            quantity
+        })
+    }
+
+    pub fn submit(&mut self, book: BookId, order: Order) ->
+        Result<(), ExchangeError> {
+        self.apply(Command::Submit {
+            book,
+            order
+        })
+    }
+
+    pub fn submit_ioc(&mut self, book: BookId, order: Order) ->
+        Result<(), ExchangeError> {
+        self.apply(Command::SubmitIoc {
+            book,
+            order
+        })
+    }
+
+    pub fn cancel(&mut self, book: BookId, id: OrderId) ->
+        Result<(), ExchangeError> {
+        self.apply(Command::Cancel {
+            book,
+            id
+        })
+    }
+
+    pub fn amend(&mut self, book: BookId, id: OrderId, price: OrderPrice,
+        quantity: OrderQuantity) -> Result<(), ExchangeError> {
+        self.apply(Command::Amend {
+            book,
+            id,
+            price,
+            quantity
+        })
+    }
+
+    pub fn set_short_sale_restriction(&mut self, book: BookId,
+        restricted: bool) -> Result<(), ExchangeError> {
+        self.apply(Command::SetShortSaleRestriction {
+            book,
+            restricted
+        })
+    }
+
+    pub fn set_lendable(&mut self, ticker: String, lendable: Lendable) ->
+        Result<(), ExchangeError> {
+        self.apply(Command::SetLendable {
+            ticker,
+            lendable
+        })
+    }
+
+    pub fn locate(&mut self, account: AccountId, ticker: String,
+        quantity: AccountHolding) -> Result<(), ExchangeError> {
+        self.apply(Command::Locate {
+            account,
+            ticker,
+            quantity
+        })
+    }
+
+    pub fn release(&mut self, account: AccountId, ticker: String,
+        quantity: AccountHolding) -> Result<(), ExchangeError> {
+        self.apply(Command::Release {
+            account,
+            ticker,
+            quantity
+        })
+    }
+
+    /* charges one session's borrow fees to the account */
+    pub fn accrue_fees(&mut self, account: AccountId) ->
+        Result<(), ExchangeError> {
+        self.apply(Command::AccrueFees {
+            account
+        })
+    }
+
+    /* see `Executions::acknowledge` */
+    pub fn acknowledge(&mut self, book: BookId, exec_id: ExecId) ->
+        Result<(), ExchangeError> {
+        self.apply(Command::Acknowledge {
+            book,
+            exec_id
+        })
+    }
+
+    /* see `Executions::forget` */
+    pub fn forget(&mut self, book: BookId, exec_id: ExecId) ->
+        Result<(), ExchangeError> {
+        self.apply(Command::Forget {
+            book,
+            exec_id
+        })
+    }
+
+    /* the id which `next_order_id` will hand out next */
+    pub fn peek_order_id(&self) -> OrderId {
+        self.next_order_id
+    }
+
+    /* submits straight into the book, bypassing pre-trade margin checks, for
+     * the orders a liquidation works out for itself */
+    pub(crate) fn force_submit_ioc(&mut self, book: BookId, order: Order) ->
+        Result<(), ExchangeError> {
+        self.see_order_id(order.id());
+
+        match self.books.get_mut(&book) {
+            Some(b) => {
+                b.submit_ioc(order, &mut self.accounts)?;
+                Ok(())
+            },
+            None => Err(ExchangeError::BookNotFound)
+        }
+    }
+
+    /* journals the command and then carries it out */
+    pub fn apply(&mut self, command: Command) -> Result<(), ExchangeError> {
+        self.record(command.clone())?;
+
+        match command {
+            Command::AddAccount(account) => {
+                if self.accounts.contains_key(&account.id()) {
+                    return Err(ExchangeError::DuplicateAccount);
+                }
+
+                self.accounts.insert(account.id(), *account);
+                Ok(())
+            },
+            Command::AddBook { id, name, ticker, quote, precision, band } => {
+                let mut book: Book = Book::with_quote(id, name, ticker, quote);
+                book.set_precision(precision);
+                book.set_band(band)?;
+                self.insert_book(book)
+            },
+            Command::Deposit { account, currency, amount } =>
+                match self.accounts.get_mut(&account) {
+                    Some(a) => Ok(a.add_balance(currency, amount)?),
+                    None => Err(ExchangeError::AccountNotFound)
+                },
+            Command::DepositHolding { account, ticker, quantity } =>
+                match self.accounts.get_mut(&account) {
+                    Some(a) => Ok(a.add_holding(ticker, quantity)?),
+                    None => Err(ExchangeError::AccountNotFound)
+                },
+            Command::Submit { book, order } => {
+                if !self.books.contains_key(&book) {
+                    return Err(ExchangeError::BookNotFound);
+                }
+
+                margin::check_order(self, book, &order)?;
+                self.see_order_id(order.id());
+
+                self.books.get_mut(&book).unwrap().submit(order,
+                    &mut self.accounts)?;
+                Ok(())
+            },
+            Command::SubmitIoc { book, order } => {
+                if !self.books.contains_key(&book) {
+                    return Err(ExchangeError::BookNotFound);
+                }
+
+                margin::check_order(self, book, &order)?;
+                self.see_order_id(order.id());
+
+                self.books.get_mut(&book).unwrap().submit_ioc(order,
+                    &mut self.accounts)?;
+                Ok(())
+            },
+            Command::Liquidate { account } => margin::sell_down(self, account),
+            Command::Cancel { book, id } => match self.books.get_mut(&book) {
+                Some(b) => Ok(b.cancel(id)?),
+                None => Err(ExchangeError::BookNotFound)
+            },
+            Command::Amend { book, id, price, quantity } => {
+                let view: Option<OrderView> = match self.books.get(&book) {
+                    Some(b) => b.order(id),
+                    None => return Err(ExchangeError::BookNotFound)
+                };
+
+                /* the book itself rejects amending an unknown order */
+                if let Some(view) = view {
+                    margin::check_order(self, book, &Order::new(id,
+                        view.owner(), view.r#type(), price, quantity))?;
+                }
+
+                Ok(self.books.get_mut(&book).unwrap().amend(id, price,
+                    quantity, &mut self.accounts)?)
+            },
+            Command::Acknowledge { book, exec_id } =>
+                match self.books.get_mut(&book) {
+                    Some(b) => {
+                        b.acknowledge(exec_id);
+                        Ok(())
+                    },
+                    None => Err(ExchangeError::BookNotFound)
+                },
+            Command::Forget { book, exec_id } =>
+                match self.books.get_mut(&book) {
+                    Some(b) => {
+                        b.forget(exec_id);
+                        Ok(())
+                    },
+                    None => Err(ExchangeError::BookNotFound)
+                },
+            Command::SetShortSaleRestriction { book, restricted } =>
+                match self.books.get_mut(&book) {
+                    Some(b) => {
+                        b.set_short_sale_restriction(restricted);
+                        Ok(())
+                    },
+                    None => Err(ExchangeError::BookNotFound)
+                },
+            Command::SetLendable { ticker, lendable } => {
+                self.borrows.set_lendable(ticker, lendable);
+                Ok(())
+            },
+            Command::Locate { account, ticker, quantity } =>
+                match self.accounts.get_mut(&account) {
+                    Some(a) => Ok(self.borrows.locate(a, ticker, quantity)?),
+                    None => Err(ExchangeError::AccountNotFound)
+                },
+            Command::Release { account, ticker, quantity } =>
+                match self.accounts.get_mut(&account) {
+                    Some(a) => Ok(self.borrows.release(a, ticker, quantity)?),
+                    None => Err(ExchangeError::AccountNotFound)
+                },
+            Command::AccrueFees { account } =>
+                match self.accounts.get_mut(&account) {
+                    Some(a) => {
+                        self.borrows.accrue_fees(a)?;
+                        Ok(())
+                    },
+                    None => Err(ExchangeError::AccountNotFound)
+                }
+        }
+    }
+
+    /* nothing is journalled unless it has first made it into the log */
+    fn record(&mut self, command: Command) -> Result<(), ExchangeError> {
+        if let Some(wal) = self.wal.as_mut() {
+            wal.append(&Record::new(self.journal.next_sequence(),
+                command.clone()))?;
+        }
+
+        self.journal.append(command);
+        Ok(())
+    }
+
+    fn check_scales(&self, book: &Book) -> Result<(), ExchangeError> {
+        for symbol in [book.ticker(), book.quote().symbol()] {
+            let places: Option<Scale> = Exchange::scale_in(book, &symbol);
+
+            match (self.scale(&symbol), places) {
+                (Some(scale), Some(places)) if scale != places =>
+                    return Err(ExchangeError::ScaleMismatch(symbol, scale,
+                        places)),
+                _ => ()
+            }
+        }
+
+        Ok(())
+    }
+
+    fn insert_book(&mut self, book: Book) -> Result<(), ExchangeError> {
+        if self.books.contains_key(&book.id()) {
+            return Err(ExchangeError::DuplicateBook);
+        }
+
+        self.check_scales(&book)?;
+
+        self.books.insert(book.id(), book);
+        Ok(())
+    }
+
+    fn see_order_id(&mut self, id: OrderId) {
+        if id >= self.next_order_id {
+            self.next_order_id = id.saturating_add(1);
+        }
+    }
+}
+/* where (or whether) an exchange is logging to plays no part in comparing
+ * exchanges */
+impl PartialEq for Exchange {
+    fn eq(&self, other: &Self) -> bool {
+        self.accounts == other.accounts && self.books == other.books &&
+            self.borrows == other.borrows &&
+            self.next_order_id == other.next_order_id &&
+            self.journal == other.journal
+    }
+}
+
+impl Eq for Exchange {}
+
+/* the journal itself is not part of a snapshot, only how far into it the
+ * snapshot reaches */
+impl Codec for Exchange {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.accounts.encode(encoder);
+        self.books.encode(encoder);
+        self.borrows.encode(encoder);
+        self.next_order_id.encode(encoder);
+        self.journal.next_sequence().encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(Exchange {
+            accounts: Codec::decode(decoder)?,
+            books: Codec::decode(decoder)?,
+            borrows: Codec::decode(decoder)?,
+            next_order_id: Codec::decode(decoder)?,
+            journal: Journal::starting_at(Codec::decode(decoder)?),
+            wal: None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::book::Quote;
+    use crate::decimal::Precision;
+    use crate::order::OrderType;
+
+    #[test]
+    fn test_submit_book_not_found() {
+        let mut actual_exchange: Exchange = Exchange::new();
+        actual_exchange.add_account(Account::new(1, "John Doe".to_string(),
+            HashMap::new(), HashMap::new())).unwrap();
+
+        assert!(matches!(actual_exchange.add_account(Account::new(1,
+            "John Doe".to_string(), HashMap::new(), HashMap::new())),
+            Err(ExchangeError::DuplicateAccount)));
+        assert!(matches!(actual_exchange.submit(1,
+            Order::new(1000, 1, OrderType::Bid, 125, 20)),
+            Err(ExchangeError::BookNotFound)));
+        assert_eq!(actual_exchange.next_order_id(), 0);
+    }
+
+    #[test]
+    fn test_add_book_scale_mismatch() -> Result<(), ExchangeError> {
+        let book = |id: BookId, ticker: &str, quote: Quote, price: Scale,
+            quantity: Scale| {
+            let mut book: Book = Book::with_quote(id, ticker.to_string(),
+                ticker.to_string(), quote);
+            book.set_precision(Precision::new(price, quantity).unwrap());
+            book
+        };
+        let usd: Quote = Quote::Currency("USD".to_string());
+        let btc: Quote = Quote::Asset("BTC".to_string());
+
+        let mut actual_exchange: Exchange = Exchange::new();
+        actual_exchange.add_book(book(1, "BTC", usd.clone(), 2, 8))?;
+
+        /* dollars are counted in cents, and bitcoin in satoshis */
+        assert!(matches!(actual_exchange.add_book(book(2, "VOC", usd.clone(),
+            4, 0)), Err(ExchangeError::ScaleMismatch(_, 2, 4))));
+        assert!(matches!(actual_exchange.add_book(book(3, "ETH", btc.clone(),
+            6, 4)), Err(ExchangeError::ScaleMismatch(_, 8, 6))));
+        actual_exchange.add_book(book(4, "VOC", usd, 2, 0))?;
+        actual_exchange.add_book(book(5, "ETH", btc, 8, 4))?;
+
+        assert_eq!(actual_exchange.scale("USD"), Some(2));
+        assert_eq!(actual_exchange.scale("BTC"), Some(8));
+        assert_eq!(actual_exchange.scale("ETH"), Some(4));
+        assert_eq!(actual_exchange.scale("EUR"), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,495 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::account::AccountId;
+use crate::order::{Order, OrderId, OrderPrice, OrderQuantity, OrderStatus,
+    OrderType};
+use crate::snapshot::{Codec, Decoder, Encoder, SnapshotError};
+
+pub type ExecId = u128;
+
+/* the most reports a book holds on to; once there are this many, the older
+ * half are dropped to make room */
+pub const REPORT_LIMIT: usize = 1 << 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExecType {
+    New,
+    Trade,
+    Replaced,
+    Cancelled,
+    Rejected,
+    Expired
+}
+
+impl fmt::Display for ExecType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecType::New => write!(f, "NEW"),
+            ExecType::Trade => write!(f, "TRADE"),
+            ExecType::Replaced => write!(f, "REPLACED"),
+            ExecType::Cancelled => write!(f, "CANCELLED"),
+            ExecType::Rejected => write!(f, "REJECTED"),
+            ExecType::Expired => write!(f, "EXPIRED")
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderState {
+    status: OrderStatus,
+    filled: OrderQuantity,
+    leaves: OrderQuantity
+}
+
+impl OrderState {
+    pub fn new(status: OrderStatus, filled: OrderQuantity,
+        leaves: OrderQuantity) -> Self {
+        OrderState {
+            status,
+            filled,
+            leaves
+        }
+    }
+
+    pub fn status(&self) -> OrderStatus {
+        self.status
+    }
+
+    pub fn filled(&self) -> OrderQuantity {
+        self.filled
+    }
+
+    pub fn leaves(&self) -> OrderQuantity {
+        self.leaves
+    }
+}
+
+/* modelled on the FIX ExecutionReport message */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExecutionReport {
+    exec_id: ExecId,
+    order_id: OrderId,
+    owner: AccountId,
+    side: OrderType,
+    price: OrderPrice,
+    exec_type: ExecType,
+    status: OrderStatus,
+    last_quantity: OrderQuantity,
+    last_price: OrderPrice,
+    cumulative_quantity: OrderQuantity,
+    leaves_quantity: OrderQuantity
+}
+
+impl ExecutionReport {
+    pub fn new(exec_id: ExecId, order: &Order, exec_type: ExecType,
+        state: OrderState, last_quantity: OrderQuantity,
+        last_price: OrderPrice) -> Self {
+        ExecutionReport {
+            exec_id,
+            order_id: order.id(),
+            owner: order.owner(),
+            side: order.r#type(),
+            price: order.price(),
+            exec_type,
+            status: state.status(),
+            last_quantity,
+            last_price,
+            cumulative_quantity: state.filled(),
+            leaves_quantity: state.leaves()
+        }
+    }
+
+    pub fn exec_id(&self) -> ExecId {
+        self.exec_id
+    }
+
+    pub fn order_id(&self) -> OrderId {
+        self.order_id
+    }
+
+    pub fn owner(&self) -> AccountId {
+        self.owner
+    }
+
+    pub fn side(&self) -> OrderType {
+        self.side
+    }
+
+    pub fn price(&self) -> OrderPrice {
+        self.price
+    }
+
+    pub fn exec_type(&self) -> ExecType {
+        self.exec_type
+    }
+
+    pub fn status(&self) -> OrderStatus {
+        self.status
+    }
+
+    pub fn last_quantity(&self) -> OrderQuantity {
+        self.last_quantity
+    }
+
+    pub fn last_price(&self) -> OrderPrice {
+        self.last_price
+    }
+
+    pub fn cumulative_quantity(&self) -> OrderQuantity {
+        self.cumulative_quantity
+    }
+
+    pub fn leaves_quantity(&self) -> OrderQuantity {
+        self.leaves_quantity
+    }
+}
+
+impl fmt::Display for ExecutionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} {} {} {} @ {} (last {} @ {}, cum {}, leaves {})",
+            self.exec_id, self.order_id, self.exec_type, self.status,
+            self.side, self.price, self.last_quantity, self.last_price,
+            self.cumulative_quantity, self.leaves_quantity)
+    }
+}
+
+/* the lifecycle of every order a book has seen, including those which have
+ * since left it, along with the reports emitted on each transition. Reports
+ * are kept until taken or acknowledged, though never more than
+ * `REPORT_LIMIT` of them, so callers wanting every report must take them as
+ * they go. The states of finished orders are kept until they are forgotten,
+ * quite apart from their reports */
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Executions {
+    states: HashMap<OrderId, OrderState>,
+    reports: Vec<ExecutionReport>,
+    next_exec_id: ExecId,
+    finished: BTreeMap<ExecId, OrderId>
+}
+
+impl Executions {
+    pub fn new() -> Self {
+        Executions::default()
+    }
+
+    pub fn state(&self, id: OrderId) -> Option<OrderState> {
+        self.states.get(&id).copied()
+    }
+
+    pub fn status(&self, id: OrderId) -> Option<OrderStatus> {
+        self.state(id).map(|state| state.status())
+    }
+
+    pub fn contains(&self, id: OrderId) -> bool {
+        self.states.contains_key(&id)
+    }
+
+    pub fn reports(&self) -> &[ExecutionReport] {
+        &self.reports
+    }
+
+    pub fn take_reports(&mut self) -> Vec<ExecutionReport> {
+        std::mem::take(&mut self.reports)
+    }
+
+    /* drops the reports up to and including `exec_id` */
+    pub fn acknowledge(&mut self, exec_id: ExecId) {
+        self.reports.retain(|report| report.exec_id() > exec_id);
+    }
+
+    /* forgets the orders which had finished by `exec_id`, whose ids may then
+     * be used again */
+    pub fn forget(&mut self, exec_id: ExecId) {
+        let pending: BTreeMap<ExecId, OrderId> =
+            match exec_id.checked_add(1) {
+                Some(next) => self.finished.split_off(&next),
+                None => BTreeMap::new()
+            };
+
+        for id in std::mem::replace(&mut self.finished, pending).values() {
+            self.states.remove(id);
+        }
+    }
+
+    pub fn accept(&mut self, order: &Order) -> ExecutionReport {
+        let state: OrderState =
+            OrderState::new(OrderStatus::New, 0, order.quantity());
+        self.transition(order, ExecType::New, state, 0, 0)
+    }
+
+    /* an order reusing the id of one already seen is rejected without
+     * disturbing the state of the first */
+    pub fn reject(&mut self, order: &Order) -> ExecutionReport {
+        let state: OrderState = OrderState::new(OrderStatus::Rejected, 0, 0);
+
+        if self.contains(order.id()) {
+            return self.report(order, ExecType::Rejected, state, 0, 0);
+        }
+
+        self.transition(order, ExecType::Rejected, state, 0, 0)
+    }
+
+    /* `order` is expected to have already had the fill taken off it */
+    pub fn fill(&mut self, order: &Order, quantity: OrderQuantity,
+        price: OrderPrice) -> ExecutionReport {
+        let status: OrderStatus = if order.quantity() == 0 {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+        let state: OrderState = OrderState::new(status,
+            self.filled(order.id()) + quantity, order.quantity());
+
+        self.transition(order, ExecType::Trade, state, quantity, price)
+    }
+
+    /* `order` is expected to already carry its new price and quantity */
+    pub fn replace(&mut self, order: &Order) -> ExecutionReport {
+        let filled: OrderQuantity = self.filled(order.id());
+        let status: OrderStatus = if filled > 0 {
+            OrderStatus::PartiallyFilled
+        } else {
+            OrderStatus::New
+        };
+        let state: OrderState = OrderState::new(status, filled,
+            order.quantity());
+
+        self.transition(order, ExecType::Replaced, state, 0, 0)
+    }
+
+    pub fn cancel(&mut self, order: &Order) -> ExecutionReport {
+        let state: OrderState = OrderState::new(OrderStatus::Cancelled,
+            self.filled(order.id()), 0);
+        self.transition(order, ExecType::Cancelled, state, 0, 0)
+    }
+
+    pub fn expire(&mut self, order: &Order) -> ExecutionReport {
+        let state: OrderState = OrderState::new(OrderStatus::Expired,
+            self.filled(order.id()), 0);
+        self.transition(order, ExecType::Expired, state, 0, 0)
+    }
+
+    fn filled(&self, id: OrderId) -> OrderQuantity {
+        self.state(id).map(|state| state.filled()).unwrap_or(0)
+    }
+
+    fn transition(&mut self, order: &Order, exec_type: ExecType,
+        state: OrderState, last_quantity: OrderQuantity,
+        last_price: OrderPrice) -> ExecutionReport {
+        let report: ExecutionReport = self.report(order, exec_type, state,
+            last_quantity, last_price);
+
+        if state.status().is_terminal() {
+            self.finished.insert(report.exec_id(), order.id());
+        }
+
+        self.states.insert(order.id(), state);
+        report
+    }
+
+    fn report(&mut self, order: &Order, exec_type: ExecType,
+        state: OrderState, last_quantity: OrderQuantity,
+        last_price: OrderPrice) -> ExecutionReport {
+        let exec_id: ExecId = self.next_exec_id;
+        self.next_exec_id += 1;
+
+        let report: ExecutionReport = ExecutionReport::new(exec_id, order,
+            exec_type, state, last_quantity, last_price);
+
+        if self.reports.len() >= REPORT_LIMIT {
+            self.reports.drain(..REPORT_LIMIT / 2);
+        }
+
+        self.reports.push(report);
+        report
+    }
+}
+
+impl Codec for ExecType {
+    fn encode(&self, encoder: &mut Encoder) {
+        let tag: u8 = match self {
+            ExecType::New => 0,
+            ExecType::Trade => 1,
+            ExecType::Replaced => 2,
+            ExecType::Cancelled => 3,
+            ExecType::Rejected => 4,
+            ExecType::Expired => 5
+        };
+
+        tag.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        match u8::decode(decoder)? {
+            0 => Ok(ExecType::New),
+            1 => Ok(ExecType::Trade),
+            2 => Ok(ExecType::Replaced),
+            3 => Ok(ExecType::Cancelled),
+            4 => Ok(ExecType::Rejected),
+            5 => Ok(ExecType::Expired),
+            _ => Err(SnapshotError::Malformed)
+        }
+    }
+}
+
+impl Codec for OrderState {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.status.encode(encoder);
+        self.filled.encode(encoder);
+        self.leaves.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(OrderState {
+            status: Codec::decode(decoder)?,
+            filled: Codec::decode(decoder)?,
+            leaves: Codec::decode(decoder)?
+        })
+    }
+}
+
+impl Codec for ExecutionReport {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.exec_id.encode(encoder);
+        self.order_id.encode(encoder);
+        self.owner.encode(encoder);
+        self.side.encode(encoder);
+        self.price.encode(encoder);
+        self.exec_type.encode(encoder);
+        self.status.encode(encoder);
+        self.last_quantity.encode(encoder);
+        self.last_price.encode(encoder);
+        self.cumulative_quantity.encode(encoder);
+        self.leaves_quantity.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(ExecutionReport {
+            exec_id: Codec::decode(decoder)?,
+            order_id: Codec::decode(decoder)?,
+            owner: Codec::decode(decoder)?,
+            side: Codec::decode(decoder)?,
+            price: Codec::decode(decoder)?,
+            exec_type: Codec::decode(decoder)?,
+            status: Codec::decode(decoder)?,
+            last_quantity: Codec::decode(decoder)?,
+            last_price: Codec::decode(decoder)?,
+            cumulative_quantity: Codec::decode(decoder)?,
+            leaves_quantity: Codec::decode(decoder)?
+        })
+    }
+}
+
+impl Codec for Executions {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.states.encode(encoder);
+        self.reports.encode(encoder);
+        self.next_exec_id.encode(encoder);
+        self.finished.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(Executions {
+            states: Codec::decode(decoder)?,
+            reports: Codec::decode(decoder)?,
+            next_exec_id: Codec::decode(decoder)?,
+            finished: Codec::decode(decoder)?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_partial_then_cancel() {
+        let mut actual_order: Order = Order::new(1000, 1, OrderType::Bid, 125,
+            20);
+        let mut actual_executions: Executions = Executions::new();
+
+        actual_executions.accept(&actual_order);
+        actual_order.fill(5);
+        actual_executions.fill(&actual_order, 5, 120);
+
+        assert_eq!(actual_executions.state(1000),
+            Some(OrderState::new(OrderStatus::PartiallyFilled, 5, 15)));
+
+        actual_executions.cancel(&actual_order);
+
+        let actual_reports: Vec<ExecutionReport> =
+            actual_executions.take_reports();
+        let actual_types: Vec<ExecType> = actual_reports.iter()
+            .map(|report| report.exec_type())
+            .collect();
+
+        assert_eq!(actual_types, vec![ExecType::New, ExecType::Trade,
+            ExecType::Cancelled]);
+        assert_eq!(actual_reports[1].last_price(), 120);
+        assert_eq!(actual_reports[2].exec_id(), 2);
+        assert_eq!(actual_executions.state(1000),
+            Some(OrderState::new(OrderStatus::Cancelled, 5, 0)));
+        assert!(actual_executions.reports().is_empty());
+    }
+
+    #[test]
+    fn test_forget_finished() {
+        let mut actual_order: Order = Order::new(1000, 1, OrderType::Bid, 125,
+            20);
+        let resting: Order = Order::new(1001, 1, OrderType::Bid, 120, 20);
+        let mut actual_executions: Executions = Executions::new();
+
+        actual_executions.accept(&actual_order);
+        actual_executions.accept(&resting);
+        actual_order.fill(20);
+        let filled: ExecutionReport =
+            actual_executions.fill(&actual_order, 20, 125);
+        actual_executions.cancel(&resting);
+
+        /* acknowledging the reports leaves the states be */
+        actual_executions.acknowledge(filled.exec_id());
+
+        assert_eq!(actual_executions.reports().len(), 1);
+        assert_eq!(actual_executions.status(1000), Some(OrderStatus::Filled));
+
+        /* the cancel came after the forgotten report */
+        actual_executions.forget(filled.exec_id());
+
+        assert!(!actual_executions.contains(1000));
+        assert_eq!(actual_executions.status(1001),
+            Some(OrderStatus::Cancelled));
+
+        actual_executions.acknowledge(ExecId::MAX);
+        actual_executions.forget(ExecId::MAX);
+
+        assert!(actual_executions.reports().is_empty());
+        assert!(!actual_executions.contains(1001));
+        assert_eq!(actual_executions, Executions {
+            next_exec_id: 4,
+            ..Executions::new()
+        });
+    }
+
+    #[test]
+    fn test_report_limit() {
+        let mut actual_executions: Executions = Executions::new();
+
+        for id in 0..REPORT_LIMIT as OrderId + 1 {
+            actual_executions.accept(&Order::new(id, 1, OrderType::Bid, 125,
+                20));
+        }
+
+        /* the older half made way for the last */
+        let reports: &[ExecutionReport] = actual_executions.reports();
+        assert_eq!(reports.len(), REPORT_LIMIT / 2 + 1);
+        assert_eq!(reports[0].order_id(), REPORT_LIMIT as OrderId / 2);
+        assert_eq!(reports.last().unwrap().order_id(),
+            REPORT_LIMIT as OrderId);
+        assert!(actual_executions.contains(0));
+    }
+}
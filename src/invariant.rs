@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::account::{AccountPosition, Accounts, Currency};
+use crate::book::{Book, Level};
+use crate::order::{OrderId, OrderPrice, OrderType};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Violation {
+    CashNotConserved(Currency, i128, i128),
+    HoldingsNotConserved(String, AccountPosition, AccountPosition),
+    Crossed(OrderPrice, OrderPrice),
+    NotResting(OrderId),
+    Untracked(OrderId),
+    EmptyLevel(OrderType, OrderPrice)
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::CashNotConserved(currency, before, after) =>
+                write!(f, "{} {} in total, but there was {}", after,
+                    currency, before),
+            Violation::HoldingsNotConserved(ticker, before, after) =>
+                write!(f, "{} {} held in total, but there was {}", after,
+                    ticker, before),
+            Violation::Crossed(bid, ask) =>
+                write!(f, "Best bid {} is not below best ask {}", bid, ask),
+            Violation::NotResting(id) =>
+                write!(f, "Order {} is tracked but not resting", id),
+            Violation::Untracked(id) =>
+                write!(f, "Order {} is resting but not tracked", id),
+            Violation::EmptyLevel(side, price) =>
+                write!(f, "Empty {} level at {}", side, price)
+        }
+    }
+}
+
+/* how much of everything there is across a set of accounts; cash is net of
+ * margin loans and holdings are net of short positions, so trading moves
+ * both between accounts but never changes either total */
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct Totals {
+    cash: BTreeMap<Currency, i128>,
+    holdings: BTreeMap<String, AccountPosition>
+}
+
+impl Totals {
+    pub fn of(accounts: &Accounts) -> Self {
+        let mut totals: Totals = Totals::default();
+
+        for account in accounts.values() {
+            for (currency, balance) in account.balances().iter() {
+                *totals.cash.entry(currency.clone()).or_insert(0) +=
+                    *balance as i128;
+            }
+
+            for (currency, loan) in account.loans().iter() {
+                *totals.cash.entry(currency.clone()).or_insert(0) -=
+                    *loan as i128;
+            }
+
+            for (ticker, position) in account.holdings().iter() {
+                *totals.holdings.entry(ticker.clone()).or_insert(0) +=
+                    *position;
+            }
+        }
+
+        /* an account which has emptied still lists what it has none of */
+        totals.cash.retain(|_, total| *total != 0);
+        totals.holdings.retain(|_, total| *total != 0);
+        totals
+    }
+
+    pub fn cash(&self, currency: &str) -> i128 {
+        self.cash.get(currency).copied().unwrap_or(0)
+    }
+
+    pub fn holding(&self, ticker: &str) -> AccountPosition {
+        self.holdings.get(ticker).copied().unwrap_or(0)
+    }
+
+    /* the first difference between these totals and some earlier ones */
+    pub fn compare(&self, before: &Totals) -> Result<(), Violation> {
+        for currency in before.cash.keys().chain(self.cash.keys()) {
+            if self.cash(currency) != before.cash(currency) {
+                return Err(Violation::CashNotConserved(currency.clone(),
+                    before.cash(currency), self.cash(currency)));
+            }
+        }
+
+        for ticker in before.holdings.keys().chain(self.holdings.keys()) {
+            if self.holding(ticker) != before.holding(ticker) {
+                return Err(Violation::HoldingsNotConserved(ticker.clone(),
+                    before.holding(ticker), self.holding(ticker)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/* checks the rules which must hold between any two requests to a book:
+ * nothing is created or destroyed by trading, so the accounts hold what
+ * they held `before`; the book is not crossed; every tracked order is
+ * resting, and vice versa; and no price level is empty */
+pub fn check(book: &Book, accounts: &Accounts, before: &Totals) ->
+    Result<(), Violation> {
+    Totals::of(accounts).compare(before)?;
+
+    if let (Some(bid), Some(ask)) = book.top() {
+        if bid >= ask {
+            return Err(Violation::Crossed(bid, ask));
+        }
+    }
+
+    let (bids, asks): (Vec<Level>, Vec<Level>) = book.depth(usize::MAX);
+
+    for (side, levels) in [(OrderType::Bid, bids), (OrderType::Ask, asks)] {
+        if let Some(level) = levels.iter()
+            .find(|level| level.orders() == 0 || level.quantity() == 0) {
+            return Err(Violation::EmptyLevel(side, level.price()));
+        }
+    }
+
+    let tracked: HashSet<OrderId> = book.order_ids().into_iter().collect();
+
+    if let Some(id) = tracked.iter().find(|id| book.order(**id).is_none()) {
+        return Err(Violation::NotResting(*id));
+    }
+
+    for side in [OrderType::Bid, OrderType::Ask] {
+        if let Some(view) = book.orders(side)
+            .find(|view| !tracked.contains(&view.id())) {
+            return Err(Violation::Untracked(view.id()));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::account::{Account, AccountBalance, AccountHolding, AccountId};
+    use crate::margin::MarginTerms;
+    use crate::order::{Order, OrderQuantity};
+    use crate::side::Band;
+
+    /* xorshift64*, so that a failing seed can be replayed */
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn below(&mut self, bound: u64) -> u64 {
+            self.next() % bound
+        }
+
+        fn between(&mut self, low: OrderQuantity, high: OrderQuantity) ->
+            OrderQuantity {
+            low + self.next() as OrderQuantity % (high - low + 1)
+        }
+    }
+
+    const SEEDS: u64 = 100;
+    const STEPS: usize = 300;
+    const ACCOUNTS: AccountId = 6;
+
+    /* some accounts are rich, some poor, one trades on margin and another
+     * sells short; account 0 does not exist */
+    fn accounts() -> Accounts {
+        let mut accounts: Accounts = HashMap::new();
+
+        for id in 1..ACCOUNTS {
+            let mut account: Account = Account::new(id, format!("{}", id),
+                HashMap::new(), HashMap::new());
+            let (cash, shares): (AccountBalance, AccountHolding) = match id {
+                1 => (1_000_000, 1_000),
+                2 => (500, 5),
+                _ => (20_000, 200)
+            };
+
+            account.add_balance("USD".to_string(), cash).unwrap();
+            account.add_holding("VOC".to_string(), shares).unwrap();
+
+            match id {
+                4 => account.set_margin(Some(MarginTerms::new(
+                    "USD".to_string(), 5000, 2500))),
+                5 => account.set_short_selling(true),
+                _ => {}
+            }
+
+            accounts.insert(id, account);
+        }
+
+        accounts
+    }
+
+    /* market orders are only ever sent IOC, as a resting one would be
+     * priced out of all proportion */
+    fn order(random: &mut Random, id: OrderId, ioc: bool) -> Order {
+        let r#type: OrderType = match random.below(2) {
+            0 => OrderType::Bid,
+            _ => OrderType::Ask
+        };
+        let owner: AccountId = random.between(0, ACCOUNTS - 1);
+        let quantity: OrderQuantity = random.between(1, 40);
+
+        match random.below(4) {
+            0 if ioc => Order::market(id, owner, r#type, quantity),
+            _ => Order::new(id, owner, r#type, random.between(90, 110),
+                quantity)
+        }
+    }
+
+    /* drives a random flow of requests through a book, checking the
+     * invariants after every one */
+    fn run(seed: u64, band: Option<Band>) ->
+        Result<(Book, Accounts), (usize, Violation)> {
+        let mut random: Random = Random(seed.wrapping_mul(2654435761) + 1);
+        let mut book: Book = Book::new(1, "Vereenigde Oostindische Compagnie"
+            .to_string(), "VOC".to_string(), "USD".to_string());
+        book.set_band(band).unwrap();
+        let mut accounts: Accounts = accounts();
+        let before: Totals = Totals::of(&accounts);
+
+        for step in 0..STEPS {
+            let id: OrderId = step as OrderId + 1;
+
+            /* most requests name a recent order, some an unknown one */
+            let target: OrderId = id.saturating_sub(random.between(1,
+                20));
+
+            let _ = match random.below(10) {
+                0..=4 => book.submit(order(&mut random, id, false),
+                    &mut accounts),
+                5 | 6 => book.submit_ioc(order(&mut random, id, true),
+                    &mut accounts).map(|_| ()),
+                7 => book.cancel(target),
+                _ => book.amend(target, random.between(90, 110),
+                    random.between(0, 40), &mut accounts)
+            };
+
+            check(&book, &accounts, &before).map_err(|v| (step, v))?;
+        }
+
+        Ok((book, accounts))
+    }
+
+    /* a ladder must behave exactly as a tree would, so each flow is run
+     * through both and the outcomes compared */
+    #[test]
+    fn test_check_random_flows() {
+        let band: Band = Band::new(80, 120, 1).unwrap();
+
+        for seed in 0..SEEDS {
+            let (tree, ladder): ((Book, Accounts), (Book, Accounts)) =
+                match (run(seed, None), run(seed, Some(band))) {
+                    (Ok(tree), Ok(ladder)) => (tree, ladder),
+                    (Err((step, v)), _) | (_, Err((step, v))) =>
+                        panic!("seed {}, step {}: {}", seed, step, v)
+                };
+
+            assert_eq!(tree.0.depth(usize::MAX), ladder.0.depth(usize::MAX),
+                "seed {}", seed);
+            assert_eq!(tree.0.reports(), ladder.0.reports(), "seed {}", seed);
+            assert_eq!(tree.1, ladder.1, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_check_violations() {
+        let mut accounts: Accounts = accounts();
+        let before: Totals = Totals::of(&accounts);
+        let book: Book = Book::new(1, "Vereenigde Oostindische Compagnie"
+            .to_string(), "VOC".to_string(), "USD".to_string());
+
+        assert_eq!(check(&book, &accounts, &before), Ok(()));
+
+        accounts.get_mut(&1).unwrap().add_balance("USD".to_string(), 1)
+            .unwrap();
+        assert_eq!(check(&book, &accounts, &before),
+            Err(Violation::CashNotConserved("USD".to_string(), 1_060_500,
+                1_060_501)));
+
+        accounts.get_mut(&1).unwrap().take_balance("USD".to_string(), 1)
+            .unwrap();
+        accounts.get_mut(&2).unwrap().take_holding("VOC".to_string(), 5)
+            .unwrap();
+        assert_eq!(check(&book, &accounts, &before),
+            Err(Violation::HoldingsNotConserved("VOC".to_string(), 1605,
+                1600)));
+    }
+}
@@ -0,0 +1,494 @@
+use std::fmt;
+
+use crate::account::{Account, AccountBalance, AccountHolding, AccountId,
+    Currency};
+use crate::book::{BookId, Quote};
+use crate::borrow::Lendable;
+use crate::decimal::Precision;
+use crate::exchange::Exchange;
+use crate::execution::ExecId;
+use crate::order::{Order, OrderId, OrderPrice, OrderQuantity};
+use crate::side::Band;
+use crate::snapshot::{Codec, Decoder, Encoder, SnapshotError};
+
+pub type Sequence = u64;
+
+/* everything which changes an exchange's state, in the form it was asked
+ * for; commands which were refused are journalled too, since a refusal can
+ * itself leave a trace (such as a rejected order's status) */
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Command {
+    AddAccount(Box<Account>),
+    AddBook {
+        id: BookId,
+        name: String,
+        ticker: String,
+        quote: Quote,
+        precision: Precision,
+        band: Option<Band>
+    },
+    Deposit {
+        account: AccountId,
+        currency: Currency,
+        amount: AccountBalance
+    },
+    DepositHolding {
+        account: AccountId,
+        ticker: String,
+        quantity: AccountHolding
+    },
+    Submit {
+        book: BookId,
+        order: Order
+    },
+    SubmitIoc {
+        book: BookId,
+        order: Order
+    },
+    /* sells down a margin account which is below maintenance; the orders
+     * are worked out by the exchange rather than given */
+    Liquidate {
+        account: AccountId
+    },
+    Cancel {
+        book: BookId,
+        id: OrderId
+    },
+    Amend {
+        book: BookId,
+        id: OrderId,
+        price: OrderPrice,
+        quantity: OrderQuantity
+    },
+    Acknowledge {
+        book: BookId,
+        exec_id: ExecId
+    },
+    SetShortSaleRestriction {
+        book: BookId,
+        restricted: bool
+    },
+    SetLendable {
+        ticker: String,
+        lendable: Lendable
+    },
+    Locate {
+        account: AccountId,
+        ticker: String,
+        quantity: AccountHolding
+    },
+    Release {
+        account: AccountId,
+        ticker: String,
+        quantity: AccountHolding
+    },
+    AccrueFees {
+        account: AccountId
+    },
+    Forget {
+        book: BookId,
+        exec_id: ExecId
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::AddAccount(account) =>
+                write!(f, "ADD_ACCOUNT {} {}", account.id(), account.name()),
+            Command::AddBook { id, ticker, .. } =>
+                write!(f, "ADD_BOOK {} {}", id, ticker),
+            Command::Deposit { account, currency, amount } =>
+                write!(f, "DEPOSIT {} {} {}", account, amount, currency),
+            Command::DepositHolding { account, ticker, quantity } =>
+                write!(f, "DEPOSIT {} {} {}", account, quantity, ticker),
+            Command::Submit { book, order } =>
+                write!(f, "SUBMIT {} {}", book, order),
+            Command::SubmitIoc { book, order } =>
+                write!(f, "SUBMIT_IOC {} {}", book, order),
+            Command::Liquidate { account } => write!(f, "LIQUIDATE {}", account),
+            Command::Cancel { book, id } => write!(f, "CANCEL {} {}", book, id),
+            Command::Amend { book, id, price, quantity } =>
+                write!(f, "AMEND {} {} @ {} for {}", book, id, price,
+                    quantity),
+            Command::Acknowledge { book, exec_id } =>
+                write!(f, "ACKNOWLEDGE {} {}", book, exec_id),
+            Command::SetShortSaleRestriction { book, restricted } =>
+                write!(f, "SET_SHORT_SALE_RESTRICTION {} {}", book,
+                    restricted),
+            Command::SetLendable { ticker, lendable } =>
+                write!(f, "SET_LENDABLE {} {} @ {} {}", ticker,
+                    lendable.available(), lendable.fee(),
+                    lendable.currency()),
+            Command::Locate { account, ticker, quantity } =>
+                write!(f, "LOCATE {} {} {}", account, quantity, ticker),
+            Command::Release { account, ticker, quantity } =>
+                write!(f, "RELEASE {} {} {}", account, quantity, ticker),
+            Command::AccrueFees { account } =>
+                write!(f, "ACCRUE_FEES {}", account),
+            Command::Forget { book, exec_id } =>
+                write!(f, "FORGET {} {}", book, exec_id)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Record {
+    sequence: Sequence,
+    command: Command
+}
+
+impl Record {
+    pub fn new(sequence: Sequence, command: Command) -> Self {
+        Record {
+            sequence,
+            command
+        }
+    }
+
+    pub fn sequence(&self) -> Sequence {
+        self.sequence
+    }
+
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+}
+
+/* append-only; sequence numbers have no gaps, and start at zero unless the
+ * journal carries on from a snapshot */
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Journal {
+    base: Sequence,
+    records: Vec<Record>
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Journal::default()
+    }
+
+    pub fn starting_at(sequence: Sequence) -> Self {
+        Journal {
+            base: sequence,
+            records: vec![]
+        }
+    }
+
+    /* the sequence number of the first record held */
+    pub fn base(&self) -> Sequence {
+        self.base
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn next_sequence(&self) -> Sequence {
+        self.base + self.records.len() as Sequence
+    }
+
+    pub fn append(&mut self, command: Command) -> Sequence {
+        let sequence: Sequence = self.next_sequence();
+        self.records.push(Record::new(sequence, command));
+        sequence
+    }
+
+    /* the records from `sequence` onwards, unless the journal only starts
+     * after it and so is missing some of them */
+    pub fn since(&self, sequence: Sequence) -> Option<&[Record]> {
+        let start: Sequence = sequence.checked_sub(self.base)?;
+        let start: usize = (start as usize).min(self.records.len());
+
+        Some(&self.records[start..])
+    }
+}
+
+impl Codec for Command {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            Command::AddAccount(account) => {
+                0u8.encode(encoder);
+                account.encode(encoder);
+            },
+            Command::AddBook { id, name, ticker, quote, precision, band } => {
+                1u8.encode(encoder);
+                id.encode(encoder);
+                name.encode(encoder);
+                ticker.encode(encoder);
+                quote.encode(encoder);
+                precision.encode(encoder);
+                band.encode(encoder);
+            },
+            Command::Deposit { account, currency, amount } => {
+                2u8.encode(encoder);
+                account.encode(encoder);
+                currency.encode(encoder);
+                amount.encode(encoder);
+            },
+            Command::DepositHolding { account, ticker, quantity } => {
+                3u8.encode(encoder);
+                account.encode(encoder);
+                ticker.encode(encoder);
+                quantity.encode(encoder);
+            },
+            Command::Submit { book, order } => {
+                4u8.encode(encoder);
+                book.encode(encoder);
+                order.encode(encoder);
+            },
+            Command::SubmitIoc { book, order } => {
+                5u8.encode(encoder);
+                book.encode(encoder);
+                order.encode(encoder);
+            },
+            Command::Liquidate { account } => {
+                6u8.encode(encoder);
+                account.encode(encoder);
+            },
+            Command::Cancel { book, id } => {
+                7u8.encode(encoder);
+                book.encode(encoder);
+                id.encode(encoder);
+            },
+            Command::Amend { book, id, price, quantity } => {
+                8u8.encode(encoder);
+                book.encode(encoder);
+                id.encode(encoder);
+                price.encode(encoder);
+                quantity.encode(encoder);
+            },
+            Command::Acknowledge { book, exec_id } => {
+                9u8.encode(encoder);
+                book.encode(encoder);
+                exec_id.encode(encoder);
+            },
+            Command::SetShortSaleRestriction { book, restricted } => {
+                10u8.encode(encoder);
+                book.encode(encoder);
+                restricted.encode(encoder);
+            },
+            Command::SetLendable { ticker, lendable } => {
+                11u8.encode(encoder);
+                ticker.encode(encoder);
+                lendable.encode(encoder);
+            },
+            Command::Locate { account, ticker, quantity } => {
+                12u8.encode(encoder);
+                account.encode(encoder);
+                ticker.encode(encoder);
+                quantity.encode(encoder);
+            },
+            Command::Release { account, ticker, quantity } => {
+                13u8.encode(encoder);
+                account.encode(encoder);
+                ticker.encode(encoder);
+                quantity.encode(encoder);
+            },
+            Command::AccrueFees { account } => {
+                14u8.encode(encoder);
+                account.encode(encoder);
+            },
+            Command::Forget { book, exec_id } => {
+                15u8.encode(encoder);
+                book.encode(encoder);
+                exec_id.encode(encoder);
+            }
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        match u8::decode(decoder)? {
+            0 => Ok(Command::AddAccount(Codec::decode(decoder)?)),
+            1 => Ok(Command::AddBook {
+                id: Codec::decode(decoder)?,
+                name: Codec::decode(decoder)?,
+                ticker: Codec::decode(decoder)?,
+                quote: Codec::decode(decoder)?,
+                precision: Codec::decode(decoder)?,
+                band: Codec::decode(decoder)?
+            }),
+            2 => Ok(Command::Deposit {
+                account: Codec::decode(decoder)?,
+                currency: Codec::decode(decoder)?,
+                amount: Codec::decode(decoder)?
+            }),
+            3 => Ok(Command::DepositHolding {
+                account: Codec::decode(decoder)?,
+                ticker: Codec::decode(decoder)?,
+                quantity: Codec::decode(decoder)?
+            }),
+            4 => Ok(Command::Submit {
+                book: Codec::decode(decoder)?,
+                order: Codec::decode(decoder)?
+            }),
+            5 => Ok(Command::SubmitIoc {
+                book: Codec::decode(decoder)?,
+                order: Codec::decode(decoder)?
+            }),
+            6 => Ok(Command::Liquidate {
+                account: Codec::decode(decoder)?
+            }),
+            7 => Ok(Command::Cancel {
+                book: Codec::decode(decoder)?,
+                id: Codec::decode(decoder)?
+            }),
+            8 => Ok(Command::Amend {
+                book: Codec::decode(decoder)?,
+                id: Codec::decode(decoder)?,
+                price: Codec::decode(decoder)?,
+                quantity: Codec::decode(decoder)?
+            }),
+            9 => Ok(Command::Acknowledge {
+                book: Codec::decode(decoder)?,
+                exec_id: Codec::decode(decoder)?
+            }),
+            10 => Ok(Command::SetShortSaleRestriction {
+                book: Codec::decode(decoder)?,
+                restricted: Codec::decode(decoder)?
+            }),
+            11 => Ok(Command::SetLendable {
+                ticker: Codec::decode(decoder)?,
+                lendable: Codec::decode(decoder)?
+            }),
+            12 => Ok(Command::Locate {
+                account: Codec::decode(decoder)?,
+                ticker: Codec::decode(decoder)?,
+                quantity: Codec::decode(decoder)?
+            }),
+            13 => Ok(Command::Release {
+                account: Codec::decode(decoder)?,
+                ticker: Codec::decode(decoder)?,
+                quantity: Codec::decode(decoder)?
+            }),
+            14 => Ok(Command::AccrueFees {
+                account: Codec::decode(decoder)?
+            }),
+            15 => Ok(Command::Forget {
+                book: Codec::decode(decoder)?,
+                exec_id: Codec::decode(decoder)?
+            }),
+            _ => Err(SnapshotError::Malformed)
+        }
+    }
+}
+
+impl Codec for Record {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.sequence.encode(encoder);
+        self.command.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(Record {
+            sequence: Codec::decode(decoder)?,
+            command: Codec::decode(decoder)?
+        })
+    }
+}
+
+/* rebuilds an exchange by applying each record in turn to a fresh one;
+ * commands which failed originally fail again in exactly the same way */
+pub fn replay(journal: &Journal) -> Exchange {
+    let mut exchange: Exchange = Exchange::new();
+    replay_onto(&mut exchange, journal.records());
+    exchange
+}
+
+pub fn replay_onto(exchange: &mut Exchange, records: &[Record]) {
+    for record in records.iter() {
+        let _ = exchange.apply(record.command().clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::book::Book;
+    use crate::exchange::ExchangeError;
+    use crate::order::OrderType;
+
+    #[test]
+    fn test_replay_identical() -> Result<(), ExchangeError> {
+        let mut actual_exchange: Exchange = Exchange::new();
+        actual_exchange.add_account(Account::new(1, "John Doe".to_string(),
+            HashMap::new(), HashMap::new()))?;
+        actual_exchange.add_account(Account::new(2, "Jane Doe".to_string(),
+            HashMap::new(), HashMap::new()))?;
+        actual_exchange.add_book(Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string()))?;
+        actual_exchange.deposit(1, "USD".to_string(), 5000)?;
+        actual_exchange.deposit_holding(2, "VOC".to_string(), 30)?;
+
+        actual_exchange.submit(1, Order::new(1, 2, OrderType::Ask, 100, 20))?;
+        actual_exchange.submit(1, Order::new(2, 2, OrderType::Ask, 101, 10))?;
+        actual_exchange.submit(1, Order::new(3, 1, OrderType::Bid, 100, 5))?;
+        actual_exchange.amend(1, 2, 100, 10)?;
+        actual_exchange.submit_ioc(1,
+            Order::new(4, 1, OrderType::Bid, 100, 20))?;
+        actual_exchange.cancel(1, 2)?;
+        assert!(actual_exchange.submit(1,
+            Order::new(5, 9, OrderType::Bid, 100, 5)).is_err());
+        assert!(actual_exchange.cancel(2, 1).is_err());
+
+        let replayed: Exchange = replay(actual_exchange.journal());
+
+        assert_eq!(actual_exchange.journal().len(), 13);
+        assert_eq!(actual_exchange.journal().since(11).unwrap()[0]
+            .sequence(), 11);
+        assert_eq!(replayed, actual_exchange);
+        assert_eq!(replayed.book(1).unwrap().reports(),
+            actual_exchange.book(1).unwrap().reports());
+        assert_eq!(replayed.account(1).unwrap().balance("USD".to_string()),
+            Some(2500));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_short_selling() -> Result<(), ExchangeError> {
+        let mut short_seller: Account = Account::new(1, "John Doe".to_string(),
+            HashMap::new(), HashMap::new());
+        short_seller.set_short_selling(true);
+
+        let mut actual_exchange: Exchange = Exchange::new();
+        actual_exchange.add_account(short_seller)?;
+        actual_exchange.add_book(Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string()))?;
+        actual_exchange.deposit(1, "USD".to_string(), 100)?;
+        actual_exchange.set_lendable("VOC".to_string(),
+            Lendable::new(50, 2, "USD".to_string()))?;
+        actual_exchange.locate(1, "VOC".to_string(), 30)?;
+        actual_exchange.release(1, "VOC".to_string(), 10)?;
+        actual_exchange.accrue_fees(1)?;
+        actual_exchange.set_short_sale_restriction(1, true)?;
+        assert!(actual_exchange.locate(1, "VOC".to_string(), 40).is_err());
+
+        let replayed: Exchange = replay(actual_exchange.journal());
+
+        assert_eq!(replayed, actual_exchange);
+        assert!(replayed.book(1).unwrap().short_sale_restriction());
+        assert_eq!(replayed.account(1).unwrap().borrowed("VOC".to_string()),
+            20);
+        assert_eq!(replayed.account(1).unwrap().balance("USD".to_string()),
+            Some(60));
+        assert_eq!(replayed.borrows().lendable("VOC".to_string()).unwrap()
+            .available(), 30);
+
+        Ok(())
+    }
+}
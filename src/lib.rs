@@ -0,0 +1,20 @@
+pub mod account;
+pub mod order;
+pub mod book;
+pub mod pnl;
+pub mod borrow;
+pub mod decimal;
+pub mod engine;
+pub mod exchange;
+pub mod execution;
+pub mod invariant;
+pub mod journal;
+pub mod listener;
+pub mod margin;
+pub mod replay;
+pub mod scenario;
+pub mod shell;
+pub mod side;
+pub mod slab;
+pub mod snapshot;
+pub mod wal;
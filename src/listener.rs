@@ -0,0 +1,189 @@
+use std::fmt;
+
+use crate::account::AccountId;
+use crate::book::{BookError, BookId, Level};
+use crate::execution::ExecutionReport;
+use crate::order::{OrderId, OrderPrice, OrderQuantity, OrderType};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trade {
+    price: OrderPrice,
+    quantity: OrderQuantity,
+    aggressor: OrderType,
+    buy_order: OrderId,
+    sell_order: OrderId,
+    buyer: AccountId,
+    seller: AccountId
+}
+
+impl Trade {
+    pub fn new(price: OrderPrice, quantity: OrderQuantity,
+        aggressor: OrderType, buy_order: OrderId, sell_order: OrderId,
+        buyer: AccountId, seller: AccountId) -> Self {
+        Trade {
+            price,
+            quantity,
+            aggressor,
+            buy_order,
+            sell_order,
+            buyer,
+            seller
+        }
+    }
+
+    pub fn price(&self) -> OrderPrice {
+        self.price
+    }
+
+    pub fn quantity(&self) -> OrderQuantity {
+        self.quantity
+    }
+
+    /* the side of the incoming order which caused the trade */
+    pub fn aggressor(&self) -> OrderType {
+        self.aggressor
+    }
+
+    pub fn buy_order(&self) -> OrderId {
+        self.buy_order
+    }
+
+    pub fn sell_order(&self) -> OrderId {
+        self.sell_order
+    }
+
+    pub fn buyer(&self) -> AccountId {
+        self.buyer
+    }
+
+    pub fn seller(&self) -> AccountId {
+        self.seller
+    }
+}
+
+impl fmt::Display for Trade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}) buys from {} ({}): {} @ {}", self.buyer,
+            self.buy_order, self.seller, self.sell_order, self.quantity,
+            self.price)
+    }
+}
+
+/* invoked synchronously by a book as events happen; every method does
+ * nothing by default so listeners need only implement what they observe */
+pub trait BookListener {
+    fn on_accepted(&mut self, _book: BookId, _report: &ExecutionReport) {}
+
+    fn on_rejected(&mut self, _book: BookId, _report: &ExecutionReport,
+        _error: &BookError) {}
+
+    fn on_trade(&mut self, _book: BookId, _trade: &Trade) {}
+
+    fn on_amended(&mut self, _book: BookId, _report: &ExecutionReport) {}
+
+    /* also invoked for expiries and discarded IOC remainders */
+    fn on_cancel(&mut self, _book: BookId, _report: &ExecutionReport) {}
+
+    fn on_bbo(&mut self, _book: BookId, _bid: Option<Level>,
+        _ask: Option<Level>) {}
+
+    /* a level which has emptied is reported with zero quantity and orders */
+    fn on_level(&mut self, _book: BookId, _side: OrderType, _level: Level) {}
+}
+
+#[derive(Default)]
+pub struct Listeners {
+    listeners: Vec<Box<dyn BookListener + Send>>,
+    touched: Vec<(OrderType, OrderPrice)>
+}
+
+impl Listeners {
+    pub fn new() -> Self {
+        Listeners::default()
+    }
+
+    pub fn add(&mut self, listener: Box<dyn BookListener + Send>) {
+        self.listeners.push(listener);
+    }
+
+    pub fn len(&self) -> usize {
+        self.listeners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    pub fn accepted(&mut self, book: BookId, report: &ExecutionReport) {
+        for listener in self.listeners.iter_mut() {
+            listener.on_accepted(book, report);
+        }
+    }
+
+    pub fn rejected(&mut self, book: BookId, report: &ExecutionReport,
+        error: &BookError) {
+        for listener in self.listeners.iter_mut() {
+            listener.on_rejected(book, report, error);
+        }
+    }
+
+    pub fn trade(&mut self, book: BookId, trade: &Trade) {
+        for listener in self.listeners.iter_mut() {
+            listener.on_trade(book, trade);
+        }
+    }
+
+    pub fn amended(&mut self, book: BookId, report: &ExecutionReport) {
+        for listener in self.listeners.iter_mut() {
+            listener.on_amended(book, report);
+        }
+    }
+
+    pub fn cancel(&mut self, book: BookId, report: &ExecutionReport) {
+        for listener in self.listeners.iter_mut() {
+            listener.on_cancel(book, report);
+        }
+    }
+
+    pub fn bbo(&mut self, book: BookId, bid: Option<Level>,
+        ask: Option<Level>) {
+        for listener in self.listeners.iter_mut() {
+            listener.on_bbo(book, bid, ask);
+        }
+    }
+
+    pub fn level(&mut self, book: BookId, side: OrderType, level: Level) {
+        for listener in self.listeners.iter_mut() {
+            listener.on_level(book, side, level);
+        }
+    }
+
+    /* remembers a level as changed so it can be reported once the book has
+     * finished processing the current request */
+    pub fn touch(&mut self, side: OrderType, price: OrderPrice) {
+        if !self.listeners.is_empty() &&
+            !self.touched.contains(&(side, price)) {
+            self.touched.push((side, price));
+        }
+    }
+
+    pub fn take_touched(&mut self) -> Vec<(OrderType, OrderPrice)> {
+        std::mem::take(&mut self.touched)
+    }
+}
+
+impl fmt::Debug for Listeners {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Listeners({})", self.listeners.len())
+    }
+}
+
+/* listeners only observe a book, so they play no part in comparing them */
+impl PartialEq for Listeners {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Listeners {}
@@ -1,7 +1,120 @@
-pub mod account;
-pub mod order;
-pub mod book;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use calob::decimal::{Precision, Scale};
+use calob::replay::{self, Format, Replay, ReplayError, Step};
+use calob::shell::Shell;
+
+const USAGE: &str = "\
+usage: calob                     start an interactive shell
+       calob replay <input> <output> [--ticker <ticker>] [--currency <currency>]
+                    [--places <price> <quantity>]
+                                 run a CSV or JSON Lines file of orders
+                                 through a book, writing trades, rejections
+                                 and the final book into <output>; prices
+                                 and quantities are to so many decimal
+                                 places (none by default)";
+
+const DEFAULT_TICKER: &str = "ASSET";
+const DEFAULT_CURRENCY: &str = "USD";
+
+fn prompt() {
+    print!("calob> ");
+    io::stdout().flush().ok();
+}
+
+fn interactive() {
+    let mut shell: Shell = Shell::new();
+    let stdin: io::Stdin = io::stdin();
+
+    prompt();
+
+    for line in stdin.lock().lines() {
+        let line: String = match line {
+            Ok(l) => l,
+            Err(_) => break
+        };
+
+        match shell.execute(&line) {
+            Ok(output) if !output.is_empty() => println!("{}", output),
+            Ok(_) => {},
+            Err(e) => eprintln!("error: {}", e)
+        }
+
+        if shell.is_finished() {
+            break;
+        }
+
+        prompt();
+    }
+}
+
+fn batch(input: &Path, output: &Path, ticker: String, currency: String,
+    precision: Precision) -> Result<(), ReplayError> {
+    let text: String = fs::read_to_string(input)?;
+    let mut replay: Replay = Replay::with_precision(ticker, currency,
+        precision);
+    let steps: Vec<Step> = replay::parse(&text, Format::from_path(input),
+        replay.book())?;
+
+    replay.run(&steps);
+    replay.write_to(output)?;
+
+    println!("{} steps, {} trades, {} rejections", steps.len(),
+        replay.trades().len(), replay.rejections().len());
+    Ok(())
+}
+
+fn places(arg: Option<&String>) -> Scale {
+    arg.and_then(|a| a.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
 
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.split_first() {
+        None => interactive(),
+        Some((command, args)) if command == "replay" => {
+            let mut paths: Vec<PathBuf> = vec![];
+            let mut ticker: String = DEFAULT_TICKER.to_string();
+            let mut currency: String = DEFAULT_CURRENCY.to_string();
+            let mut precision: Precision = Precision::default();
+            let mut args = args.iter();
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--ticker" => ticker = args.next()
+                        .unwrap_or_else(|| usage()).clone(),
+                    "--currency" => currency = args.next()
+                        .unwrap_or_else(|| usage()).clone(),
+                    "--places" => {
+                        let price: Scale = places(args.next());
+                        let quantity: Scale = places(args.next());
+                        precision = Precision::new(price, quantity)
+                            .unwrap_or_else(|_| usage());
+                    },
+                    _ => paths.push(PathBuf::from(arg))
+                }
+            }
+
+            if paths.len() != 2 {
+                usage();
+            }
+
+            if let Err(e) = batch(&paths[0], &paths[1], ticker, currency,
+                precision) {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        },
+        Some(_) => usage()
+    }
 }
@@ -0,0 +1,601 @@
+use std::convert::TryFrom;
+use std::iter;
+
+use crate::account::{Account, AccountBalance, AccountId, AccountPosition,
+    Currency};
+use crate::book::{Book, BookId, OrderView, Quote};
+use crate::decimal::Precision;
+use crate::exchange::{Exchange, ExchangeError};
+use crate::journal::Command;
+use crate::order::{Order, OrderId, OrderPrice, OrderQuantity, OrderType};
+use crate::snapshot::{Codec, Decoder, Encoder, SnapshotError};
+
+pub type Equity = i128;
+
+pub const BASIS_POINTS: AccountBalance = 10000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MarginTerms {
+    currency: Currency,
+    leverage: AccountBalance,
+    maintenance: AccountBalance
+}
+
+impl MarginTerms {
+    /* maintenance is expressed in basis points of gross exposure */
+    pub fn new(currency: Currency, leverage: AccountBalance,
+        maintenance: AccountBalance) -> Self {
+        MarginTerms {
+            currency,
+            leverage,
+            maintenance
+        }
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency.clone()
+    }
+
+    pub fn leverage(&self) -> AccountBalance {
+        self.leverage
+    }
+
+    pub fn maintenance(&self) -> AccountBalance {
+        self.maintenance
+    }
+}
+
+impl Codec for MarginTerms {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.currency.encode(encoder);
+        self.leverage.encode(encoder);
+        self.maintenance.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(MarginTerms {
+            currency: Codec::decode(decoder)?,
+            leverage: Codec::decode(decoder)?,
+            maintenance: Codec::decode(decoder)?
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MarginStatus {
+    account: AccountId,
+    equity: Equity,
+    exposure: AccountBalance,
+    buying_power: AccountBalance,
+    requirement: AccountBalance
+}
+
+impl MarginStatus {
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+
+    pub fn equity(&self) -> Equity {
+        self.equity
+    }
+
+    pub fn exposure(&self) -> AccountBalance {
+        self.exposure
+    }
+
+    pub fn buying_power(&self) -> AccountBalance {
+        self.buying_power
+    }
+
+    pub fn requirement(&self) -> AccountBalance {
+        self.requirement
+    }
+
+    pub fn is_compliant(&self) -> bool {
+        match to_equity(self.requirement) {
+            Some(requirement) => self.equity >= requirement,
+            None => false
+        }
+    }
+
+    /* an account whose figures overflow is reported as having no buying
+     * power and as being below maintenance */
+    fn unrepresentable(account: AccountId) -> Self {
+        MarginStatus {
+            account,
+            equity: 0,
+            exposure: AccountBalance::MAX,
+            buying_power: 0,
+            requirement: AccountBalance::MAX
+        }
+    }
+}
+
+/* an account's position in a book, valued at that book's mark and in the
+ * book's precision */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Position {
+    book: BookId,
+    quantity: AccountPosition,
+    mark: OrderPrice,
+    precision: Precision
+}
+
+impl Position {
+    fn value(&self) -> Option<Equity> {
+        let value: Equity = to_equity(self.exposure()?)?;
+
+        match self.quantity < 0 {
+            true => Some(-value),
+            false => Some(value)
+        }
+    }
+
+    fn exposure(&self) -> Option<AccountBalance> {
+        self.precision.notional(self.mark, self.quantity.unsigned_abs())
+    }
+}
+
+pub fn status(exchange: &Exchange, account: AccountId) ->
+    Option<MarginStatus> {
+    let owner: &Account = exchange.account(account)?;
+    let terms: &MarginTerms = owner.margin()?;
+
+    Some(figures(exchange, owner, terms)
+        .unwrap_or_else(|| MarginStatus::unrepresentable(account)))
+}
+
+/* none should any of the figures overflow */
+fn figures(exchange: &Exchange, owner: &Account, terms: &MarginTerms) ->
+    Option<MarginStatus> {
+    let balance: Equity =
+        to_equity(owner.balance(terms.currency()).unwrap_or(0))?;
+    let loan: Equity = to_equity(owner.loan(terms.currency()))?;
+    let mut equity: Equity = balance.checked_sub(loan)?;
+    let mut exposure: AccountBalance = 0;
+
+    for position in positions(exchange, owner, terms).iter() {
+        equity = equity.checked_add(position.value()?)?;
+        exposure = exposure.checked_add(position.exposure()?)?;
+    }
+
+    let buying_power: AccountBalance = if equity > 0 {
+        AccountBalance::try_from(equity).ok()?.checked_mul(terms.leverage)?
+            .saturating_sub(exposure)
+    } else {
+        0
+    };
+
+    Some(MarginStatus {
+        account: owner.id(),
+        equity,
+        exposure,
+        buying_power,
+        requirement: exposure.checked_mul(terms.maintenance)? / BASIS_POINTS
+    })
+}
+
+/* every margin account currently below its maintenance requirement */
+pub fn margin_check(exchange: &Exchange) -> Vec<MarginStatus> {
+    let mut ids: Vec<AccountId> = exchange.accounts().keys().copied()
+        .collect();
+    ids.sort_unstable();
+
+    ids.into_iter()
+        .filter_map(|id| status(exchange, id))
+        .filter(|s| !s.is_compliant())
+        .collect()
+}
+
+/* rejects orders which would take a margin account's gross exposure beyond
+ * its buying power, counting what the owner's other orders resting on the
+ * same side of the book would add should they all fill; orders which only
+ * reduce exposure are always allowed */
+pub fn check_order(exchange: &Exchange, book: BookId, order: &Order) ->
+    Result<(), ExchangeError> {
+    let owner: &Account = match exchange.account(order.owner()) {
+        Some(a) => a,
+        None => return Err(ExchangeError::AccountNotFound)
+    };
+
+    let terms: &MarginTerms = match owner.margin() {
+        Some(t) => t,
+        None => return Ok(())
+    };
+
+    let target: &Book = match exchange.book(book) {
+        Some(b) => b,
+        None => return Err(ExchangeError::BookNotFound)
+    };
+
+    if target.quote() != Quote::Currency(terms.currency()) {
+        return Ok(());
+    }
+
+    /* the opposite position, which orders on this side close out before
+     * they add to exposure */
+    let position: AccountPosition =
+        owner.holding(target.ticker()).unwrap_or(0);
+    let mut offset: OrderQuantity = match order.r#type() {
+        OrderType::Bid => position.min(0).unsigned_abs(),
+        OrderType::Ask => position.max(0).unsigned_abs()
+    };
+
+    /* an amended order is counted once, as amended */
+    let resting: Vec<OrderView> = target.orders(order.r#type())
+        .filter(|o| o.owner() == order.owner() && o.id() != order.id())
+        .collect();
+    let mut exposure: Option<AccountBalance> = Some(0);
+    let mut increase: OrderQuantity = 0;
+
+    for (price, quantity) in resting.iter()
+        .map(|o| (o.price(), o.quantity()))
+        .chain(iter::once((order.price(), order.quantity()))) {
+        increase = quantity.saturating_sub(offset);
+        offset = offset.saturating_sub(quantity);
+
+        let worst: OrderPrice =
+            worst_price(target, order.r#type(), price, increase);
+        exposure = exposure.and_then(|e| e.checked_add(target.precision()
+            .notional(worst, increase)?));
+    }
+
+    if increase == 0 {
+        return Ok(());
+    }
+
+    let buying_power: AccountBalance = match status(exchange, order.owner()) {
+        Some(s) => s.buying_power(),
+        None => 0
+    };
+
+    match exposure {
+        Some(exposure) if exposure <= buying_power => Ok(()),
+        _ => Err(ExchangeError::InsufficientBuyingPower)
+    }
+}
+
+/* the price at which `quantity` on one side adds the most exposure: never
+ * below the mark, at the limit for a bid above it, and for a market bid
+ * the furthest ask it would reach, without bound should the asks run out
+ * before it fills */
+fn worst_price(book: &Book, r#type: OrderType, price: OrderPrice,
+    quantity: OrderQuantity) -> OrderPrice {
+    let mark: OrderPrice = book.mark().unwrap_or(0);
+
+    if r#type == OrderType::Ask || price != OrderPrice::MAX {
+        return price.max(mark);
+    }
+
+    let mut remaining: OrderQuantity = quantity;
+
+    for ask in book.orders(OrderType::Ask) {
+        if ask.quantity() >= remaining {
+            return ask.price().max(mark);
+        }
+
+        remaining -= ask.quantity();
+    }
+
+    OrderPrice::MAX
+}
+
+/* submits market orders against the account's largest positions until it is
+ * back above its maintenance requirement (or it has nothing left to sell),
+ * returning the ids of the orders submitted */
+pub fn liquidate(exchange: &mut Exchange, account: AccountId) ->
+    Result<Vec<OrderId>, ExchangeError> {
+    let first: OrderId = exchange.peek_order_id();
+    exchange.apply(Command::Liquidate { account })?;
+    Ok((first..exchange.peek_order_id()).collect())
+}
+
+/* carries out `Command::Liquidate`; the orders it submits skip the pre-trade
+ * checks, so they are only ever worked out here */
+pub(crate) fn sell_down(exchange: &mut Exchange, account: AccountId) ->
+    Result<(), ExchangeError> {
+    let owner: &Account = match exchange.account(account) {
+        Some(a) => a,
+        None => return Err(ExchangeError::AccountNotFound)
+    };
+
+    let terms: MarginTerms = match owner.margin() {
+        Some(t) => t.clone(),
+        None => return Ok(())
+    };
+
+    let mut targets: Vec<Position> = positions(exchange, owner, &terms);
+    targets.sort_by_key(|p| (std::cmp::Reverse(p.exposure()
+        .unwrap_or(AccountBalance::MAX)), p.book));
+
+    for target in targets {
+        let current: MarginStatus = match status(exchange, account) {
+            Some(s) => s,
+            None => return Err(ExchangeError::AccountNotFound)
+        };
+
+        if current.is_compliant() {
+            break;
+        }
+
+        /* the exposure at which current equity would just meet maintenance,
+         * which no exposure can exceed should it overflow */
+        let compliant_exposure: AccountBalance = if terms.maintenance() == 0 {
+            current.exposure()
+        } else {
+            AccountBalance::try_from(current.equity().max(0)).ok()
+                .and_then(|equity| equity.checked_mul(BASIS_POINTS))
+                .map_or(AccountBalance::MAX, |e| e / terms.maintenance())
+        };
+
+        let excess: AccountBalance =
+            current.exposure().saturating_sub(compliant_exposure);
+        let held: OrderQuantity = target.quantity.unsigned_abs();
+        let quantity: OrderQuantity = match target.precision
+            .quantity_for(target.mark, excess) {
+            Some(q) => held.min(q),
+            None => held
+        };
+
+        if quantity == 0 {
+            continue;
+        }
+
+        let side: OrderType = if target.quantity > 0 {
+            OrderType::Ask
+        } else {
+            OrderType::Bid
+        };
+
+        let id: OrderId = exchange.next_order_id();
+        exchange.force_submit_ioc(target.book,
+            Order::market(id, account, side, quantity))?;
+    }
+
+    Ok(())
+}
+
+/* always fits in the narrow build, but not otherwise */
+#[allow(clippy::unnecessary_fallible_conversions)]
+fn to_equity(amount: AccountBalance) -> Option<Equity> {
+    Equity::try_from(amount).ok()
+}
+
+fn positions(exchange: &Exchange, owner: &Account, terms: &MarginTerms) ->
+    Vec<Position> {
+    let quote: Quote = Quote::Currency(terms.currency());
+    let mut positions: Vec<Position> = vec![];
+
+    for (ticker, quantity) in owner.holdings().iter() {
+        if *quantity == 0 {
+            continue;
+        }
+
+        /* value each holding at the first book quoting it in this currency */
+        let found: Option<&Book> = exchange.books().values()
+            .find(|b| b.ticker() == *ticker && b.quote() == quote &&
+                b.mark().is_some());
+
+        if let Some(book) = found {
+            positions.push(Position {
+                book: book.id(),
+                quantity: *quantity,
+                mark: book.mark().unwrap(),
+                precision: book.precision()
+            });
+        }
+    }
+
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::account::AccountBalance;
+    use crate::journal;
+
+    fn account(id: AccountId, cash: AccountBalance, voc: AccountPosition) ->
+        Account {
+        let mut balances: HashMap<Currency, AccountBalance> = HashMap::new();
+        balances.insert("USD".to_string(), cash);
+        let mut holdings: HashMap<String, AccountPosition> = HashMap::new();
+        holdings.insert("VOC".to_string(), voc);
+
+        Account::new(id, format!("Account {}", id), balances, holdings)
+    }
+
+    #[test]
+    fn test_liquidate_restores_compliance() -> Result<(), ExchangeError> {
+        let mut trader: Account = account(1, 1000, 0);
+        trader.set_margin(Some(MarginTerms::new("USD".to_string(), 2, 2500)));
+
+        let mut actual_exchange: Exchange = Exchange::new();
+        actual_exchange.add_account(trader)?;
+        actual_exchange.add_account(account(2, 0, 150))?;
+        actual_exchange.add_account(account(3, 0, 10))?;
+        actual_exchange.add_account(account(4, 1000, 0))?;
+        actual_exchange.add_book(Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string()))?;
+
+        /* too large for the trader's buying power... */
+        actual_exchange.submit(1, Order::new(1, 2, OrderType::Ask, 10, 150))?;
+        assert!(matches!(actual_exchange.submit(1,
+            Order::new(2, 1, OrderType::Bid, 10, 250)),
+            Err(ExchangeError::InsufficientBuyingPower)));
+
+        /* ...but this is not, and is partly funded by a loan */
+        actual_exchange.submit(1, Order::new(3, 1, OrderType::Bid, 10, 150))?;
+        assert_eq!(actual_exchange.account(1).unwrap()
+            .loan("USD".to_string()), 500);
+        assert!(margin_check(&actual_exchange).is_empty());
+
+        /* the price then collapses */
+        actual_exchange.submit(1, Order::new(4, 4, OrderType::Bid, 4, 100))?;
+        actual_exchange.submit(1, Order::new(5, 3, OrderType::Ask, 4, 10))?;
+
+        let calls: Vec<MarginStatus> = margin_check(&actual_exchange);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].account(), 1);
+        assert_eq!(calls[0].equity(), 100);
+        assert_eq!(calls[0].requirement(), 150);
+
+        let orders: Vec<OrderId> = liquidate(&mut actual_exchange, 1)?;
+        let after: MarginStatus = status(&actual_exchange, 1).unwrap();
+
+        assert_eq!(orders, vec![6]);
+        assert!(after.is_compliant());
+        assert_eq!(actual_exchange.account(1).unwrap()
+            .holding("VOC".to_string()), Some(100));
+        assert_eq!(actual_exchange.account(1).unwrap()
+            .loan("USD".to_string()), 300);
+
+        /* only the request is journalled, and replaying it sells the same */
+        assert_eq!(actual_exchange.journal().records().last()
+            .map(|r| r.command().clone()), Some(Command::Liquidate {
+                account: 1
+            }));
+        assert_eq!(journal::replay(actual_exchange.journal()),
+            actual_exchange);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_overflow() -> Result<(), ExchangeError> {
+        let mut trader: Account = account(1, 1000, AccountPosition::MAX - 1);
+        trader.set_margin(Some(MarginTerms::new("USD".to_string(), 2, 2500)));
+
+        let mut actual_exchange: Exchange = Exchange::new();
+        actual_exchange.add_account(trader)?;
+        actual_exchange.add_account(account(2, 1000, 10))?;
+        actual_exchange.add_book(Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string()))?;
+
+        /* a mark of 3 values the trader's holding beyond what fits */
+        actual_exchange.submit(1, Order::new(1, 2, OrderType::Ask, 3, 1))?;
+        actual_exchange.submit(1, Order::new(2, 1, OrderType::Bid, 3, 1))?;
+
+        let actual_status: MarginStatus = status(&actual_exchange, 1).unwrap();
+        assert_eq!(actual_status.buying_power(), 0);
+        assert!(!actual_status.is_compliant());
+        assert_eq!(margin_check(&actual_exchange), vec![actual_status]);
+        assert!(matches!(actual_exchange.submit(1,
+            Order::new(3, 1, OrderType::Bid, 3, 1)),
+            Err(ExchangeError::InsufficientBuyingPower)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_order_above_mark() -> Result<(), ExchangeError> {
+        let mut trader: Account = account(1, 1000, 0);
+        trader.set_margin(Some(MarginTerms::new("USD".to_string(), 2, 2500)));
+
+        let mut actual_exchange: Exchange = Exchange::new();
+        actual_exchange.add_account(trader)?;
+        actual_exchange.add_account(account(2, 1000, 300))?;
+        actual_exchange.add_book(Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string()))?;
+
+        /* marked at 10 by a trade between others */
+        actual_exchange.submit(1, Order::new(1, 2, OrderType::Ask, 10, 1))?;
+        actual_exchange.submit(1, Order::new(2, 2, OrderType::Bid, 10, 1))?;
+        actual_exchange.submit(1, Order::new(3, 2, OrderType::Ask, 10, 100))?;
+        actual_exchange.submit(1, Order::new(4, 2, OrderType::Ask, 100, 100))?;
+
+        /* 150 shares is within $2000 at the mark, but not at the limit,
+         * which would reach the asks at 100 */
+        assert!(matches!(actual_exchange.submit(1,
+            Order::new(5, 1, OrderType::Bid, 100, 150)),
+            Err(ExchangeError::InsufficientBuyingPower)));
+
+        /* and a market bid would reach them too */
+        assert!(matches!(actual_exchange.submit_ioc(1,
+            Order::market(6, 1, OrderType::Bid, 150)),
+            Err(ExchangeError::InsufficientBuyingPower)));
+        actual_exchange.submit_ioc(1,
+            Order::market(7, 1, OrderType::Bid, 100))?;
+
+        assert_eq!(actual_exchange.account(1).unwrap()
+            .holding("VOC".to_string()), Some(100));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_order_resting() -> Result<(), ExchangeError> {
+        let mut trader: Account = account(1, 1000, 0);
+        trader.set_margin(Some(MarginTerms::new("USD".to_string(), 2, 2500)));
+
+        let mut actual_exchange: Exchange = Exchange::new();
+        actual_exchange.add_account(trader)?;
+        actual_exchange.add_account(account(2, 1000, 10))?;
+        actual_exchange.add_book(Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string()))?;
+
+        actual_exchange.submit(1, Order::new(1, 2, OrderType::Ask, 10, 1))?;
+        actual_exchange.submit(1, Order::new(2, 2, OrderType::Bid, 10, 1))?;
+
+        /* each within $2000 of buying power alone, but not together */
+        actual_exchange.submit(1, Order::new(3, 1, OrderType::Bid, 10, 150))?;
+        assert!(matches!(actual_exchange.submit(1,
+            Order::new(4, 1, OrderType::Bid, 9, 150)),
+            Err(ExchangeError::InsufficientBuyingPower)));
+        actual_exchange.submit(1, Order::new(5, 1, OrderType::Bid, 9, 50))?;
+
+        /* nor can a resting bid be amended beyond it */
+        assert!(matches!(actual_exchange.amend(1, 5, 9, 51),
+            Err(ExchangeError::InsufficientBuyingPower)));
+        actual_exchange.amend(1, 3, 10, 100)?;
+        actual_exchange.amend(1, 5, 9, 100)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_order_at_precision() -> Result<(), ExchangeError> {
+        let mut trader: Account = account(1, 1000, 0);
+        trader.set_margin(Some(MarginTerms::new("USD".to_string(), 2, 2500)));
+
+        /* prices in cents, quantities in tenths of a share */
+        let mut book: Book = Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string());
+        book.set_precision(Precision::new(2, 1).unwrap());
+
+        let mut actual_exchange: Exchange = Exchange::new();
+        actual_exchange.add_account(trader)?;
+        actual_exchange.add_account(account(2, 0, 500))?;
+        actual_exchange.add_book(book)?;
+        actual_exchange.submit(1, Order::new(1, 2, OrderType::Ask, 1000, 500))?;
+
+        /* 3.0 shares at $10.00 is beyond the trader's $20.00 of buying
+         * power, but 2.0 shares is just within it */
+        assert!(matches!(actual_exchange.submit(1,
+            Order::new(2, 1, OrderType::Bid, 1000, 30)),
+            Err(ExchangeError::InsufficientBuyingPower)));
+        actual_exchange.submit(1, Order::new(3, 1, OrderType::Bid, 1000, 20))?;
+
+        let actual_status: MarginStatus = status(&actual_exchange, 1).unwrap();
+        assert_eq!(actual_status.exposure(), 2000);
+        assert_eq!(actual_status.equity(), 1000);
+        assert_eq!(actual_status.requirement(), 500);
+        assert_eq!(actual_status.buying_power(), 0);
+        assert!(actual_status.is_compliant());
+
+        /* which leaves no room for even a tenth of a share more */
+        assert!(matches!(actual_exchange.submit(1,
+            Order::new(4, 1, OrderType::Bid, 1000, 1)),
+            Err(ExchangeError::InsufficientBuyingPower)));
+
+        Ok(())
+    }
+}
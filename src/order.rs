@@ -1,12 +1,27 @@
 use std::fmt;
 
-use crate::account::Account;
+use crate::account::AccountId;
+use crate::decimal::{DecimalError, Precision, Price, Quantity};
+use crate::snapshot::{Codec, Decoder, Encoder, SnapshotError};
 
+/* the `narrow` feature halves every order and balance for simulations which
+ * never need more than 64 bits, at the cost of overflowing sooner */
+#[cfg(not(feature = "narrow"))]
 pub type OrderId = u128;
+#[cfg(not(feature = "narrow"))]
 pub type OrderPrice = u128;
+#[cfg(not(feature = "narrow"))]
 pub type OrderQuantity = u128;
 
+#[cfg(feature = "narrow")]
+pub type OrderId = u64;
+#[cfg(feature = "narrow")]
+pub type OrderPrice = u64;
+#[cfg(feature = "narrow")]
+pub type OrderQuantity = u64;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OrderType {
     Bid,
     Ask
@@ -21,17 +36,49 @@ impl fmt::Display for OrderType {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct Order<'a> {
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Rejected,
+    Expired
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderStatus::New => write!(f, "NEW"),
+            OrderStatus::PartiallyFilled => write!(f, "PARTIALLY_FILLED"),
+            OrderStatus::Filled => write!(f, "FILLED"),
+            OrderStatus::Cancelled => write!(f, "CANCELLED"),
+            OrderStatus::Rejected => write!(f, "REJECTED"),
+            OrderStatus::Expired => write!(f, "EXPIRED")
+        }
+    }
+}
+
+impl OrderStatus {
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, OrderStatus::Filled | OrderStatus::Cancelled |
+            OrderStatus::Rejected | OrderStatus::Expired)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Order {
     id: OrderId,
-    owner: &'a mut Account,
+    owner: AccountId,
     order_type: OrderType,
     price: OrderPrice,
     quantity: OrderQuantity
 }
 
-impl<'a> Order<'a> {
-    pub fn new(id: OrderId, owner: &'a mut Account, r#type: OrderType,
+impl Order {
+    pub fn new(id: OrderId, owner: AccountId, r#type: OrderType,
         price: OrderPrice, quantity: OrderQuantity) -> Self {
         Order {
             id,
@@ -42,11 +89,33 @@ impl<'a> Order<'a> {
         }
     }
 
+    /* from decimals, which must be given to the places of the book the
+     * order is for, rather than from bare units */
+    pub fn with_precision(id: OrderId, owner: AccountId, r#type: OrderType,
+        price: Price, quantity: Quantity, precision: Precision) ->
+        Result<Self, DecimalError> {
+        let (price, quantity): (OrderPrice, OrderQuantity) =
+            precision.units(price, quantity)?;
+
+        Ok(Order::new(id, owner, r#type, price, quantity))
+    }
+
+    /* priced so as to cross any resting order on the other side */
+    pub fn market(id: OrderId, owner: AccountId, r#type: OrderType,
+        quantity: OrderQuantity) -> Self {
+        let price: OrderPrice = match r#type {
+            OrderType::Bid => OrderPrice::MAX,
+            OrderType::Ask => 0
+        };
+
+        Order::new(id, owner, r#type, price, quantity)
+    }
+
     pub fn id(&self) -> OrderId {
         self.id
     }
 
-    pub fn owner(&mut self) -> &mut Account {
+    pub fn owner(&self) -> AccountId {
         self.owner
     }
 
@@ -61,31 +130,98 @@ impl<'a> Order<'a> {
     pub fn quantity(&self) -> OrderQuantity {
         self.quantity
     }
+
+    pub fn fill(&mut self, quantity: OrderQuantity) {
+        self.quantity -= quantity;
+    }
+
+    pub fn amend(&mut self, price: OrderPrice, quantity: OrderQuantity) {
+        self.price = price;
+        self.quantity = quantity;
+    }
 }
 
-impl fmt::Display for Order<'_> {
+impl fmt::Display for Order {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: {} {} @ {} for {}", self.id, self.owner.name(),
+        write!(f, "{}: {} {} @ {} for {}", self.id, self.owner,
                 self.order_type, self.price, self.quantity)
     }
 }
 
+impl Codec for OrderType {
+    fn encode(&self, encoder: &mut Encoder) {
+        let tag: u8 = match self {
+            OrderType::Bid => 0,
+            OrderType::Ask => 1
+        };
+
+        tag.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        match u8::decode(decoder)? {
+            0 => Ok(OrderType::Bid),
+            1 => Ok(OrderType::Ask),
+            _ => Err(SnapshotError::Malformed)
+        }
+    }
+}
+
+impl Codec for OrderStatus {
+    fn encode(&self, encoder: &mut Encoder) {
+        let tag: u8 = match self {
+            OrderStatus::New => 0,
+            OrderStatus::PartiallyFilled => 1,
+            OrderStatus::Filled => 2,
+            OrderStatus::Cancelled => 3,
+            OrderStatus::Rejected => 4,
+            OrderStatus::Expired => 5
+        };
+
+        tag.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        match u8::decode(decoder)? {
+            0 => Ok(OrderStatus::New),
+            1 => Ok(OrderStatus::PartiallyFilled),
+            2 => Ok(OrderStatus::Filled),
+            3 => Ok(OrderStatus::Cancelled),
+            4 => Ok(OrderStatus::Rejected),
+            5 => Ok(OrderStatus::Expired),
+            _ => Err(SnapshotError::Malformed)
+        }
+    }
+}
+
+impl Codec for Order {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.id.encode(encoder);
+        self.owner.encode(encoder);
+        self.order_type.encode(encoder);
+        self.price.encode(encoder);
+        self.quantity.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(Order {
+            id: Codec::decode(decoder)?,
+            owner: Codec::decode(decoder)?,
+            order_type: Codec::decode(decoder)?,
+            price: Codec::decode(decoder)?,
+            quantity: Codec::decode(decoder)?
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use std::collections::HashMap;
-
     #[test]
     fn test_new_normal() {
-        let mut actual_acct: Account = Account::new(32, "John Doe".to_string(),
-                                                      100000, HashMap::new());
-
-        let mut expected_acct: Account = Account::new(32, "John Doe".to_string(),
-                                                        100000, HashMap::new());
-        
         let order_id: OrderId = 12;
-        let order_owner: &mut Account = &mut actual_acct;
+        let order_owner: AccountId = 32;
         let order_type: OrderType = OrderType::Bid;
         let order_price: OrderPrice = 330;
         let order_quantity: OrderQuantity = 50;
@@ -94,7 +230,7 @@ mod tests {
                                                 order_price, order_quantity);
         let expected_order: Order = Order {
             id: order_id,
-            owner: &mut expected_acct,
+            owner: order_owner,
             order_type,
             price: order_price,
             quantity: order_quantity
@@ -102,5 +238,39 @@ mod tests {
 
         assert_eq!(actual_order, expected_order);
     }
-}
 
+    #[test]
+    fn test_with_precision_scale_mismatch() {
+        let cents: Precision = Precision::new(2, 0).unwrap();
+
+        assert_eq!(Order::with_precision(12, 32, OrderType::Bid,
+            Price::new(1234, 2), Quantity::new(5, 0), cents),
+            Ok(Order::new(12, 32, OrderType::Bid, 1234, 5)));
+        /* a price in satoshis is not one in cents */
+        assert_eq!(Order::with_precision(12, 32, OrderType::Bid,
+            Price::new(1234, 8), Quantity::new(5, 0), cents),
+            Err(DecimalError::ScaleMismatch(2, 8)));
+    }
+
+    #[test]
+    fn test_fill_partial() {
+        let mut actual_order: Order = Order::new(12, 32, OrderType::Ask, 330,
+            50);
+        actual_order.fill(20);
+
+        assert_eq!(actual_order.quantity(), 30);
+        assert_eq!(actual_order.to_string(), "12: 32 ASK @ 330 for 30");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize_owner_id() {
+        let actual_order: Order = Order::new(12, 32, OrderType::Ask, 330, 50);
+        let json: String = serde_json::to_string(&actual_order).unwrap();
+
+        assert_eq!(json, "{\"id\":12,\"owner\":32,\"order_type\":\"Ask\",\
+            \"price\":330,\"quantity\":50}");
+        assert_eq!(serde_json::from_str::<Order>(&json).unwrap(),
+            actual_order);
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+use crate::account::{AccountBalance, AccountHolding};
+use crate::decimal::Precision;
+use crate::order::{OrderPrice, OrderQuantity};
+use crate::snapshot::{Codec, Decoder, Encoder, SnapshotError};
+
+pub type Pnl = i128;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CostMethod {
+    Average,
+    Fifo
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lot {
+    price: OrderPrice,
+    quantity: OrderQuantity
+}
+
+impl Lot {
+    pub fn new(price: OrderPrice, quantity: OrderQuantity) -> Self {
+        Lot {
+            price,
+            quantity
+        }
+    }
+
+    pub fn price(&self) -> OrderPrice {
+        self.price
+    }
+
+    pub fn quantity(&self) -> OrderQuantity {
+        self.quantity
+    }
+}
+
+/* cost basis is bookkeeping on trades which have already settled, so rather
+ * than refuse them its figures saturate: a cost, proceeds or P&L beyond the
+ * bounds of its type is pinned to that bound instead of overflowing. Costs
+ * are in units of the quote, as worked out by the traded book's precision */
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CostBasis {
+    precision: Precision,
+    quantity: AccountHolding,
+    total_cost: AccountBalance,
+    lots: VecDeque<Lot>,
+    short_quantity: AccountHolding,
+    short_proceeds: AccountBalance,
+    short_lots: VecDeque<Lot>,
+    realized_average: Pnl,
+    realized_fifo: Pnl
+}
+
+impl CostBasis {
+    pub fn new() -> Self {
+        CostBasis::default()
+    }
+
+    pub fn with_precision(precision: Precision) -> Self {
+        CostBasis {
+            precision,
+            ..CostBasis::default()
+        }
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    pub fn quantity(&self) -> AccountHolding {
+        self.quantity
+    }
+
+    pub fn total_cost(&self) -> AccountBalance {
+        self.total_cost
+    }
+
+    pub fn lots(&self) -> &VecDeque<Lot> {
+        &self.lots
+    }
+
+    pub fn average_cost(&self) -> Option<OrderPrice> {
+        self.precision.price_for(self.quantity, self.total_cost)
+    }
+
+    pub fn short_quantity(&self) -> AccountHolding {
+        self.short_quantity
+    }
+
+    pub fn short_lots(&self) -> &VecDeque<Lot> {
+        &self.short_lots
+    }
+
+    pub fn average_short_price(&self) -> Option<OrderPrice> {
+        self.precision.price_for(self.short_quantity, self.short_proceeds)
+    }
+
+    pub fn realized(&self, method: CostMethod) -> Pnl {
+        match method {
+            CostMethod::Average => self.realized_average,
+            CostMethod::Fifo => self.realized_fifo
+        }
+    }
+
+    pub fn unrealized(&self, mark: OrderPrice, method: CostMethod) -> Pnl {
+        let long_value: Pnl = to_pnl(self.notional(mark, self.quantity));
+        let short_value: Pnl =
+            to_pnl(self.notional(mark, self.short_quantity));
+
+        let (long_cost, short_proceeds): (Pnl, Pnl) = match method {
+            CostMethod::Average =>
+                (to_pnl(self.total_cost), to_pnl(self.short_proceeds)),
+            CostMethod::Fifo => (self.value(&self.lots),
+                self.value(&self.short_lots))
+        };
+
+        long_value.saturating_sub(long_cost)
+            .saturating_add(short_proceeds.saturating_sub(short_value))
+    }
+
+    pub fn buy(&mut self, price: OrderPrice, quantity: OrderQuantity) {
+        if quantity == 0 {
+            return;
+        }
+
+        self.quantity = self.quantity.saturating_add(quantity);
+        self.total_cost =
+            self.total_cost.saturating_add(self.notional(price, quantity));
+        self.lots.push_back(Lot::new(price, quantity));
+    }
+
+    /* any quantity sold beyond what has been bought through `buy` (i.e.,
+     * holdings seeded directly into the account) is treated as having a
+     * cost of zero */
+    pub fn sell(&mut self, price: OrderPrice, quantity: OrderQuantity) {
+        if quantity == 0 {
+            return;
+        }
+
+        let proceeds: Pnl = to_pnl(self.notional(price, quantity));
+        let tracked: OrderQuantity = quantity.min(self.quantity);
+
+        /* average cost */
+        let average_cost: AccountBalance = if tracked == self.quantity {
+            self.total_cost
+        } else {
+            share(self.total_cost, tracked, self.quantity)
+        };
+
+        self.total_cost -= average_cost;
+        self.quantity -= tracked;
+        self.realized_average = self.realized_average
+            .saturating_add(proceeds.saturating_sub(to_pnl(average_cost)));
+
+        /* first in, first out */
+        let fifo_cost: AccountBalance =
+            CostBasis::take_lots(self.precision, &mut self.lots, tracked);
+        self.realized_fifo = self.realized_fifo
+            .saturating_add(proceeds.saturating_sub(to_pnl(fifo_cost)));
+    }
+
+    pub fn sell_short(&mut self, price: OrderPrice, quantity: OrderQuantity) {
+        if quantity == 0 {
+            return;
+        }
+
+        self.short_quantity = self.short_quantity.saturating_add(quantity);
+        self.short_proceeds = self.short_proceeds
+            .saturating_add(self.notional(price, quantity));
+        self.short_lots.push_back(Lot::new(price, quantity));
+    }
+
+    pub fn cover(&mut self, price: OrderPrice, quantity: OrderQuantity) {
+        let tracked: OrderQuantity = quantity.min(self.short_quantity);
+
+        if tracked == 0 {
+            return;
+        }
+
+        let cost: Pnl = to_pnl(self.notional(price, tracked));
+
+        /* average cost */
+        let average_proceeds: AccountBalance =
+            if tracked == self.short_quantity {
+                self.short_proceeds
+            } else {
+                share(self.short_proceeds, tracked, self.short_quantity)
+            };
+
+        self.short_proceeds -= average_proceeds;
+        self.short_quantity -= tracked;
+        self.realized_average = self.realized_average
+            .saturating_add(to_pnl(average_proceeds).saturating_sub(cost));
+
+        /* first in, first out */
+        let fifo_proceeds: AccountBalance =
+            CostBasis::take_lots(self.precision, &mut self.short_lots,
+                tracked);
+        self.realized_fifo = self.realized_fifo
+            .saturating_add(to_pnl(fifo_proceeds).saturating_sub(cost));
+    }
+
+    fn notional(&self, price: OrderPrice, quantity: OrderQuantity) ->
+        AccountBalance {
+        saturated(self.precision, price, quantity)
+    }
+
+    fn value(&self, lots: &VecDeque<Lot>) -> Pnl {
+        lots.iter().fold(0, |value: Pnl, lot: &Lot| value
+            .saturating_add(to_pnl(self.notional(lot.price, lot.quantity))))
+    }
+
+    fn take_lots(precision: Precision, lots: &mut VecDeque<Lot>,
+        quantity: OrderQuantity) -> AccountBalance {
+        let mut remaining: OrderQuantity = quantity;
+        let mut value: AccountBalance = 0;
+
+        while remaining > 0 {
+            let lot: &mut Lot = match lots.front_mut() {
+                Some(l) => l,
+                None => break
+            };
+
+            let taken: OrderQuantity = remaining.min(lot.quantity);
+            value = value.saturating_add(saturated(precision, lot.price,
+                taken));
+            lot.quantity -= taken;
+            remaining -= taken;
+
+            if lot.quantity == 0 {
+                lots.pop_front();
+            }
+        }
+
+        value
+    }
+}
+
+fn saturated(precision: Precision, price: OrderPrice,
+    quantity: OrderQuantity) -> AccountBalance {
+    precision.notional(price, quantity).unwrap_or(AccountBalance::MAX)
+}
+
+#[allow(clippy::unnecessary_fallible_conversions)]
+fn to_pnl(amount: AccountBalance) -> Pnl {
+    Pnl::try_from(amount).unwrap_or(Pnl::MAX)
+}
+
+/* `total * part / whole` for `part <= whole`, without the product
+ * overflowing where the result itself fits */
+fn share(total: AccountBalance, part: AccountHolding, whole: AccountHolding) ->
+    AccountBalance {
+    let remainder: AccountBalance = (total % whole).checked_mul(part)
+        .map(|r| r / whole)
+        .unwrap_or(AccountBalance::MAX);
+
+    (total / whole * part).saturating_add(remainder).min(total)
+}
+
+impl Codec for Lot {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.price.encode(encoder);
+        self.quantity.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(Lot {
+            price: Codec::decode(decoder)?,
+            quantity: Codec::decode(decoder)?
+        })
+    }
+}
+
+impl Codec for CostBasis {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.precision.encode(encoder);
+        self.quantity.encode(encoder);
+        self.total_cost.encode(encoder);
+        self.lots.encode(encoder);
+        self.short_quantity.encode(encoder);
+        self.short_proceeds.encode(encoder);
+        self.short_lots.encode(encoder);
+        self.realized_average.encode(encoder);
+        self.realized_fifo.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(CostBasis {
+            precision: Codec::decode(decoder)?,
+            quantity: Codec::decode(decoder)?,
+            total_cost: Codec::decode(decoder)?,
+            lots: Codec::decode(decoder)?,
+            short_quantity: Codec::decode(decoder)?,
+            short_proceeds: Codec::decode(decoder)?,
+            short_lots: Codec::decode(decoder)?,
+            realized_average: Codec::decode(decoder)?,
+            realized_fifo: Codec::decode(decoder)?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sell_average_and_fifo() {
+        let mut actual_basis: CostBasis = CostBasis::new();
+        actual_basis.buy(100, 10);
+        actual_basis.buy(130, 10);
+        actual_basis.sell(150, 15);
+
+        assert_eq!(actual_basis.quantity(), 5);
+        assert_eq!(actual_basis.average_cost(), Some(115));
+        assert_eq!(actual_basis.realized(CostMethod::Average), 525);
+        assert_eq!(actual_basis.realized(CostMethod::Fifo), 600);
+        assert_eq!(actual_basis.lots(),
+            &VecDeque::from(vec![Lot::new(130, 5)]));
+        assert_eq!(actual_basis.unrealized(140, CostMethod::Average), 125);
+        assert_eq!(actual_basis.unrealized(140, CostMethod::Fifo), 50);
+    }
+
+    #[test]
+    fn test_sell_untracked() {
+        let mut actual_basis: CostBasis = CostBasis::new();
+        actual_basis.buy(100, 5);
+        actual_basis.sell(120, 10);
+
+        assert_eq!(actual_basis.quantity(), 0);
+        assert_eq!(actual_basis.average_cost(), None);
+        assert_eq!(actual_basis.realized(CostMethod::Average), 700);
+        assert_eq!(actual_basis.realized(CostMethod::Fifo), 700);
+    }
+
+    #[test]
+    fn test_cover_short() {
+        let mut actual_basis: CostBasis = CostBasis::new();
+        actual_basis.sell_short(200, 10);
+        actual_basis.sell_short(180, 10);
+        actual_basis.cover(150, 10);
+
+        assert_eq!(actual_basis.short_quantity(), 10);
+        assert_eq!(actual_basis.average_short_price(), Some(190));
+        assert_eq!(actual_basis.realized(CostMethod::Average), 400);
+        assert_eq!(actual_basis.realized(CostMethod::Fifo), 500);
+        assert_eq!(actual_basis.unrealized(170, CostMethod::Average), 200);
+        assert_eq!(actual_basis.unrealized(170, CostMethod::Fifo), 100);
+    }
+
+    /* every figure here overflows at either width */
+    #[test]
+    fn test_sell_saturates() {
+        let mut actual_basis: CostBasis = CostBasis::new();
+        actual_basis.buy(OrderPrice::MAX, 2);
+        actual_basis.buy(OrderPrice::MAX, 2);
+
+        assert_eq!(actual_basis.total_cost(), AccountBalance::MAX);
+        assert_eq!(actual_basis.unrealized(OrderPrice::MAX,
+            CostMethod::Average), 0);
+
+        actual_basis.sell(OrderPrice::MAX, 3);
+
+        assert_eq!(actual_basis.quantity(), 1);
+        assert!(actual_basis.total_cost() <= AccountBalance::MAX / 4 + 1);
+        assert_eq!(actual_basis.realized(CostMethod::Fifo), 0);
+
+        /* proceeds pinned to the bound, less what covering cost */
+        actual_basis.sell_short(OrderPrice::MAX, 2);
+        actual_basis.cover(1, 1);
+
+        assert_eq!(actual_basis.short_quantity(), 1);
+        assert_eq!(actual_basis.realized(CostMethod::Fifo),
+            to_pnl(AccountBalance::MAX) - 1);
+        assert!(actual_basis.realized(CostMethod::Average) > 0);
+    }
+}
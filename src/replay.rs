@@ -0,0 +1,696 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use thiserror::Error;
+
+use crate::account::{Account, AccountBalance, AccountId, Accounts, Currency};
+use crate::book::{Book, BookError, BookId};
+use crate::decimal::{DecimalError, Precision};
+use crate::listener::{BookListener, Trade};
+use crate::order::{Order, OrderId, OrderPrice, OrderQuantity, OrderType};
+
+pub const TRADES_FILE: &str = "trades.csv";
+pub const REJECTIONS_FILE: &str = "rejections.csv";
+pub const BOOK_FILE: &str = "book.csv";
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    Io(io::Error),
+    Parse(usize, String)
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "{}", e),
+            ReplayError::Parse(line, reason) =>
+                write!(f, "Line {}: {}", line, reason)
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    JsonLines
+}
+
+impl Format {
+    /* JSON Lines if the extension says so, CSV otherwise */
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("jsonl") | Some("ndjson") | Some("json") => Format::JsonLines,
+            _ => Format::Csv
+        }
+    }
+}
+
+/* one line of input; a deposit creates its account if need be, and credits
+ * shares rather than cash if its symbol is the book's ticker */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Deposit {
+        account: AccountId,
+        amount: AccountBalance,
+        symbol: String
+    },
+    Submit(Order),
+    SubmitIoc(Order),
+    Cancel(OrderId),
+    Amend {
+        id: OrderId,
+        price: OrderPrice,
+        quantity: OrderQuantity
+    }
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Deposit { .. } => "deposit",
+            Action::Submit(_) => "submit",
+            Action::SubmitIoc(_) => "ioc",
+            Action::Cancel(_) => "cancel",
+            Action::Amend { .. } => "amend"
+        }
+    }
+
+    pub fn order(&self) -> Option<OrderId> {
+        match self {
+            Action::Deposit { .. } => None,
+            Action::Submit(order) | Action::SubmitIoc(order) =>
+                Some(order.id()),
+            Action::Cancel(id) | Action::Amend { id, .. } => Some(*id)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    line: usize,
+    action: Action
+}
+
+impl Step {
+    pub fn new(line: usize, action: Action) -> Self {
+        Step {
+            line,
+            action
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn action(&self) -> &Action {
+        &self.action
+    }
+}
+
+type Fields = HashMap<String, String>;
+type Writer = fn(&Replay, &mut dyn Write) -> io::Result<()>;
+
+/* CSV input starts with a header naming its columns, in any order, from
+ * action, id, account, side, price, quantity, amount and symbol; JSON Lines
+ * input has one flat object per line with the same keys. In both, blank
+ * lines and lines starting with '#' are skipped. Prices, quantities and
+ * amounts are decimals, to the places of `book` */
+pub fn parse(input: &str, format: Format, book: &Book) ->
+    Result<Vec<Step>, ReplayError> {
+    let mut header: Option<Vec<String>> = None;
+    let mut steps: Vec<Step> = vec![];
+
+    for (index, text) in input.lines().enumerate() {
+        let line: usize = index + 1;
+        let text: &str = text.trim();
+
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+
+        let fields: Fields = match format {
+            Format::Csv => match &header {
+                Some(columns) => csv_fields(columns, text, line)?,
+                None => {
+                    header = Some(text.split(',')
+                        .map(|c| c.trim().to_lowercase())
+                        .collect());
+                    continue;
+                }
+            },
+            Format::JsonLines => json_fields(text, line)?
+        };
+
+        steps.push(Step::new(line, action(&fields, line, book)?));
+    }
+
+    Ok(steps)
+}
+
+fn csv_fields(columns: &[String], text: &str, line: usize) ->
+    Result<Fields, ReplayError> {
+    let values: Vec<&str> = text.split(',').map(|v| v.trim()).collect();
+
+    if values.len() > columns.len() {
+        return Err(ReplayError::Parse(line,
+            format!("{} fields but only {} columns", values.len(),
+                columns.len())));
+    }
+
+    Ok(columns.iter().cloned()
+        .zip(values)
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(column, value)| (column, value.to_string()))
+        .collect())
+}
+
+/* just enough JSON for a flat object of strings and numbers; nulls are
+ * treated as absent */
+fn json_fields(text: &str, line: usize) -> Result<Fields, ReplayError> {
+    let malformed = || ReplayError::Parse(line,
+        "not a flat JSON object".to_string());
+    let mut fields: Fields = HashMap::new();
+    let mut chars = text.chars().peekable();
+
+    fn skip_whitespace(chars: &mut std::iter::Peekable<std::str::Chars>) {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+
+    fn string(chars: &mut std::iter::Peekable<std::str::Chars>) ->
+        Option<String> {
+        let mut s: String = String::new();
+
+        loop {
+            match chars.next()? {
+                '"' => return Some(s),
+                '\\' => match chars.next()? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    c @ '"' | c @ '\\' | c @ '/' => s.push(c),
+                    _ => return None
+                },
+                c => s.push(c)
+            }
+        }
+    }
+
+    skip_whitespace(&mut chars);
+    if chars.next() != Some('{') {
+        return Err(malformed());
+    }
+
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            if chars.next() != Some('"') {
+                return Err(malformed());
+            }
+            let key: String = string(&mut chars).ok_or_else(malformed)?;
+
+            skip_whitespace(&mut chars);
+            if chars.next() != Some(':') {
+                return Err(malformed());
+            }
+            skip_whitespace(&mut chars);
+
+            let value: String = if chars.peek() == Some(&'"') {
+                chars.next();
+                string(&mut chars).ok_or_else(malformed)?
+            } else {
+                let mut token: String = String::new();
+                while let Some(c) = chars.peek() {
+                    if *c == ',' || *c == '}' || c.is_whitespace() {
+                        break;
+                    }
+                    token.push(*c);
+                    chars.next();
+                }
+
+                if token.is_empty() {
+                    return Err(malformed());
+                }
+                token
+            };
+
+            if value != "null" {
+                fields.insert(key.to_lowercase(), value);
+            }
+
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err(malformed())
+            }
+        }
+    }
+
+    skip_whitespace(&mut chars);
+    if chars.next().is_some() {
+        return Err(malformed());
+    }
+
+    Ok(fields)
+}
+
+/* ids are as wide as the build makes them, so each is parsed to whatever
+ * type it is wanted as */
+fn number<T: FromStr>(fields: &Fields, key: &str, line: usize) ->
+    Result<T, ReplayError> {
+    let value: &str = fields.get(key).map(|v| v.as_str()).ok_or_else(||
+        ReplayError::Parse(line, format!("missing {}", key)))?;
+
+    value.parse().map_err(|_| ReplayError::Parse(line,
+        format!("{} '{}' is not a number", key, value)))
+}
+
+/* a decimal field, in the smallest units `parse` makes of it */
+fn decimal<F>(fields: &Fields, key: &str, line: usize, parse: F) ->
+    Result<AccountBalance, ReplayError>
+    where F: Fn(&str) -> Result<AccountBalance, DecimalError> {
+    let value: &str = fields.get(key).map(|v| v.as_str()).ok_or_else(||
+        ReplayError::Parse(line, format!("missing {}", key)))?;
+
+    parse(value).map_err(|e| ReplayError::Parse(line,
+        format!("{} '{}': {}", key, value, e)))
+}
+
+fn action(fields: &Fields, line: usize, book: &Book) ->
+    Result<Action, ReplayError> {
+    let text = |key: &str| -> Result<&str, ReplayError> {
+        fields.get(key).map(|v| v.as_str()).ok_or_else(||
+            ReplayError::Parse(line, format!("missing {}", key)))
+    };
+    let price = || -> Result<OrderPrice, ReplayError> {
+        decimal(fields, "price", line,
+            |v| book.parse_price(v).map(|p| p.units()))
+    };
+    let quantity = || -> Result<OrderQuantity, ReplayError> {
+        decimal(fields, "quantity", line,
+            |v| book.parse_quantity(v).map(|q| q.units()))
+    };
+    let side = || -> Result<OrderType, ReplayError> {
+        match text("side")?.to_lowercase().as_str() {
+            "bid" | "buy" => Ok(OrderType::Bid),
+            "ask" | "sell" => Ok(OrderType::Ask),
+            other => Err(ReplayError::Parse(line,
+                format!("unknown side '{}'", other)))
+        }
+    };
+
+    match text("action")?.to_lowercase().as_str() {
+        /* shares are to the book's quantity places, cash to its price's */
+        "deposit" => {
+            let symbol: &str = text("symbol")?;
+            let amount: AccountBalance = match symbol == book.ticker() {
+                true => decimal(fields, "amount", line,
+                    |v| book.parse_quantity(v).map(|q| q.units()))?,
+                false => decimal(fields, "amount", line,
+                    |v| book.parse_price(v).map(|p| p.units()))?
+            };
+
+            Ok(Action::Deposit {
+                account: number(fields, "account", line)?,
+                amount,
+                symbol: symbol.to_string()
+            })
+        },
+        "submit" => Ok(Action::Submit(Order::new(
+            number(fields, "id", line)?, number(fields, "account", line)?,
+            side()?, price()?, quantity()?))),
+        /* an IOC order without a price is a market order */
+        "ioc" => {
+            let (id, account, side, quantity): (OrderId, AccountId,
+                OrderType, OrderQuantity) = (number(fields, "id", line)?,
+                number(fields, "account", line)?, side()?, quantity()?);
+
+            Ok(Action::SubmitIoc(match fields.contains_key("price") {
+                true => Order::new(id, account, side, price()?, quantity),
+                false => Order::market(id, account, side, quantity)
+            }))
+        },
+        "cancel" => Ok(Action::Cancel(number(fields, "id", line)?)),
+        "amend" => Ok(Action::Amend {
+            id: number(fields, "id", line)?,
+            price: price()?,
+            quantity: quantity()?
+        }),
+        other => Err(ReplayError::Parse(line,
+            format!("unknown action '{}'", other)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    line: usize,
+    action: &'static str,
+    order: Option<OrderId>,
+    reason: String
+}
+
+impl Rejection {
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn action(&self) -> &'static str {
+        self.action
+    }
+
+    pub fn order(&self) -> Option<OrderId> {
+        self.order
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+struct TradeRecorder {
+    trades: Arc<Mutex<Vec<Trade>>>
+}
+
+impl BookListener for TradeRecorder {
+    fn on_trade(&mut self, _book: BookId, trade: &Trade) {
+        self.trades.lock().unwrap().push(*trade);
+    }
+}
+
+/* runs a sequence of steps through a single book, noting every trade and
+ * every step which the book refused, by the line which caused it */
+#[derive(Debug)]
+pub struct Replay {
+    book: Book,
+    accounts: Accounts,
+    recorded: Arc<Mutex<Vec<Trade>>>,
+    trades: Vec<(usize, Trade)>,
+    rejections: Vec<Rejection>
+}
+
+impl Replay {
+    pub fn new(ticker: String, currency: Currency) -> Self {
+        Replay::with_precision(ticker, currency, Precision::default())
+    }
+
+    pub fn with_precision(ticker: String, currency: Currency,
+        precision: Precision) -> Self {
+        let recorded: Arc<Mutex<Vec<Trade>>> = Arc::new(Mutex::new(vec![]));
+        let mut book: Book = Book::new(1, ticker.clone(), ticker, currency);
+        book.set_precision(precision);
+
+        book.add_listener(Box::new(TradeRecorder {
+            trades: recorded.clone()
+        }));
+
+        Replay {
+            book,
+            accounts: HashMap::new(),
+            recorded,
+            trades: vec![],
+            rejections: vec![]
+        }
+    }
+
+    pub fn book(&self) -> &Book {
+        &self.book
+    }
+
+    pub fn accounts(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    pub fn trades(&self) -> &[(usize, Trade)] {
+        &self.trades
+    }
+
+    pub fn rejections(&self) -> &[Rejection] {
+        &self.rejections
+    }
+
+    pub fn run(&mut self, steps: &[Step]) {
+        for step in steps.iter() {
+            self.step(step);
+        }
+    }
+
+    pub fn step(&mut self, step: &Step) {
+        let result: Result<(), BookError> = match step.action().clone() {
+            Action::Deposit { account, amount, symbol } =>
+                self.deposit(account, amount, symbol),
+            Action::Submit(order) =>
+                self.book.submit(order, &mut self.accounts),
+            Action::SubmitIoc(order) =>
+                self.book.submit_ioc(order, &mut self.accounts).map(|_| ()),
+            Action::Cancel(id) => self.book.cancel(id),
+            Action::Amend { id, price, quantity } =>
+                self.book.amend(id, price, quantity, &mut self.accounts)
+        };
+
+        let line: usize = step.line();
+        self.trades.extend(self.recorded.lock().unwrap().drain(..)
+            .map(|trade| (line, trade)));
+
+        if let Err(e) = result {
+            self.rejections.push(Rejection {
+                line,
+                action: step.action().name(),
+                order: step.action().order(),
+                reason: e.to_string()
+            });
+        }
+    }
+
+    fn deposit(&mut self, id: AccountId, amount: AccountBalance,
+        symbol: String) ->
+        Result<(), BookError> {
+        let account: &mut Account = self.accounts.entry(id)
+            .or_insert_with(|| Account::new(id, format!("Account {}", id),
+                HashMap::new(), HashMap::new()));
+
+        if symbol == self.book.ticker() {
+            account.add_holding(symbol, amount)?;
+        } else {
+            account.add_balance(symbol, amount)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_trades(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "line,price,quantity,aggressor,buy_order,sell_order,\
+            buyer,seller")?;
+
+        for (line, trade) in self.trades.iter() {
+            writeln!(out, "{},{},{},{},{},{},{},{}", line,
+                self.book.price(trade.price()),
+                self.book.quantity(trade.quantity()), trade.aggressor(),
+                trade.buy_order(),
+                trade.sell_order(), trade.buyer(), trade.seller())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_rejections(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "line,action,order,reason")?;
+
+        for rejection in self.rejections.iter() {
+            writeln!(out, "{},{},{},{}", rejection.line(), rejection.action(),
+                rejection.order().map_or(String::new(), |id| id.to_string()),
+                rejection.reason())?;
+        }
+
+        Ok(())
+    }
+
+    /* every resting order, asks from the top of the book down and then
+     * bids, each in priority order */
+    pub fn write_book(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "side,order,account,price,quantity")?;
+
+        let mut asks: Vec<_> = self.book.orders(OrderType::Ask).collect();
+        asks.sort_by_key(|view| Reverse(view.price()));
+
+        for view in asks.iter().chain(self.book.orders(OrderType::Bid)
+            .collect::<Vec<_>>().iter()) {
+            writeln!(out, "{},{},{},{},{}", view.r#type(), view.id(),
+                view.owner(), self.book.price(view.price()),
+                self.book.quantity(view.quantity()))?;
+        }
+
+        Ok(())
+    }
+
+    /* writes trades, rejections and the final book into `directory`,
+     * creating it if need be */
+    pub fn write_to(&self, directory: &Path) -> io::Result<()> {
+        fs::create_dir_all(directory)?;
+
+        let outputs: [(&str, Writer); 3] = [
+            (TRADES_FILE, Replay::write_trades),
+            (REJECTIONS_FILE, Replay::write_rejections),
+            (BOOK_FILE, Replay::write_book)
+        ];
+
+        for (name, write) in outputs.iter() {
+            let mut out: BufWriter<File> =
+                BufWriter::new(File::create(directory.join(name))?);
+            write(self, &mut out)?;
+            out.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\
+action,id,account,side,price,quantity,amount,symbol
+deposit,,1,,,,5000,USD
+deposit,,2,,,,30,VOC
+# two asks, then a bid which crosses one and a half of them
+submit,1,2,ask,100,20
+submit,2,2,ask,101,10
+submit,3,1,bid,101,25
+submit,4,9,bid,99,5
+amend,2,,,102,5
+cancel,7
+ioc,5,1,buy,,10
+submit,6,1,bid,95,3
+submit,7,2,ask,110,4
+";
+
+    const JSONL: &str = r#"
+{"action": "deposit", "account": 1, "amount": 5000, "symbol": "USD"}
+{"action": "deposit", "account": 2, "amount": 30, "symbol": "VOC"}
+# two asks, then a bid which crosses one and a half of them
+{"action": "submit", "id": 1, "account": 2, "side": "ask", "price": 100, "quantity": 20}
+{"action": "submit", "id": 2, "account": 2, "side": "ask", "price": 101, "quantity": 10}
+{"action": "submit", "id": 3, "account": 1, "side": "bid", "price": 101, "quantity": 25}
+{"action": "submit", "id": 4, "account": 9, "side": "bid", "price": 99, "quantity": 5}
+{"action": "amend", "id": 2, "price": 102, "quantity": 5}
+{"action": "cancel", "id": 7}
+{"action": "ioc", "id": 5, "account": 1, "side": "buy", "price": null, "quantity": 10}
+{"action": "submit", "id": 6, "account": 1, "side": "bid", "price": 95, "quantity": 3}
+{"action": "submit", "id": 7, "account": 2, "side": "ask", "price": 110, "quantity": 4}
+"#;
+
+    fn book() -> Book {
+        Book::new(1, "VOC".to_string(), "VOC".to_string(), "USD".to_string())
+    }
+
+    fn output(replay: &Replay, write: Writer) -> String {
+        let mut out: Vec<u8> = vec![];
+        write(replay, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_parse_formats_agree() -> Result<(), ReplayError> {
+        let csv: Vec<Step> = parse(CSV, Format::Csv, &book())?;
+        let jsonl: Vec<Step> = parse(JSONL, Format::JsonLines, &book())?;
+
+        assert_eq!(csv.len(), 11);
+        assert_eq!(csv.iter().map(|s| s.action()).collect::<Vec<_>>(),
+            jsonl.iter().map(|s| s.action()).collect::<Vec<_>>());
+        assert_eq!(csv[8].action(), &Action::SubmitIoc(Order::market(5, 1,
+            OrderType::Bid, 10)));
+        assert_eq!(csv[2].line(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let book: Book = book();
+
+        assert!(matches!(parse("action\nfly\n", Format::Csv, &book),
+            Err(ReplayError::Parse(2, _))));
+        assert!(matches!(parse("action,id\nsubmit,1\n", Format::Csv, &book),
+            Err(ReplayError::Parse(2, _))));
+        assert!(matches!(parse("action,id\ncancel,x\n", Format::Csv, &book),
+            Err(ReplayError::Parse(2, _))));
+        assert!(matches!(parse("action,id,account,side,price,quantity\n\
+            submit,1,1,bid,1.5,10\n", Format::Csv, &book),
+            Err(ReplayError::Parse(2, _))));
+        assert!(matches!(parse("{\"action\": \"cancel\", \"id\": 1",
+            Format::JsonLines, &book), Err(ReplayError::Parse(1, _))));
+        assert!(matches!(parse("[1, 2]", Format::JsonLines, &book),
+            Err(ReplayError::Parse(1, _))));
+    }
+
+    #[test]
+    fn test_run_decimals() -> Result<(), ReplayError> {
+        let mut replay: Replay = Replay::with_precision("VOC".to_string(),
+            "USD".to_string(), Precision::new(2, 1).unwrap());
+        replay.run(&parse("\
+action,id,account,side,price,quantity,amount,symbol
+deposit,,1,,,,100.50,USD
+deposit,,2,,,,5.5,VOC
+submit,1,2,ask,12.34,2.5
+submit,2,1,bid,12.34,1.5
+", Format::Csv, replay.book())?);
+
+        assert_eq!(output(&replay, Replay::write_trades), "\
+line,price,quantity,aggressor,buy_order,sell_order,buyer,seller
+5,12.34,1.5,BID,2,1,1,2
+");
+        assert_eq!(output(&replay, Replay::write_book), "\
+side,order,account,price,quantity
+ASK,1,2,12.34,1.0
+");
+        assert_eq!(replay.accounts()[&1].balance("USD".to_string()),
+            Some(10050 - 1851));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_outputs() -> Result<(), ReplayError> {
+        let mut replay: Replay = Replay::new("VOC".to_string(),
+            "USD".to_string());
+        replay.run(&parse(CSV, Format::Csv, &book())?);
+
+        assert_eq!(output(&replay, Replay::write_trades), "\
+line,price,quantity,aggressor,buy_order,sell_order,buyer,seller
+7,100,20,BID,3,1,1,2
+7,101,5,BID,3,2,1,2
+11,102,5,BID,5,2,1,2
+");
+        assert_eq!(output(&replay, Replay::write_rejections), "\
+line,action,order,reason
+8,submit,4,No such account
+10,cancel,7,No such order in book
+");
+        assert_eq!(output(&replay, Replay::write_book), "\
+side,order,account,price,quantity
+ASK,7,2,110,4
+BID,6,1,95,3
+");
+        assert_eq!(replay.accounts()[&1].balance("USD".to_string()),
+            Some(5000 - 2000 - 505 - 510));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,471 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::account::{AccountBalance, AccountId, AccountPosition,
+    Currency};
+use crate::book::Level;
+use crate::invariant::{self, Totals};
+use crate::listener::Trade;
+use crate::order::{Order, OrderId, OrderPrice, OrderQuantity, OrderStatus,
+    OrderType};
+use crate::replay::{Action, Replay, Step};
+
+pub const DEFAULT_TICKER: &str = "VOC";
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+#[derive(Debug, Error)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse(usize, String),
+    Failed(usize, String)
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "{}", e),
+            ScenarioError::Parse(line, reason) =>
+                write!(f, "Line {}: {}", line, reason),
+            ScenarioError::Failed(line, reason) =>
+                write!(f, "Line {}: {}", line, reason)
+        }
+    }
+}
+
+impl From<io::Error> for ScenarioError {
+    fn from(e: io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Statement {
+    Act(Action),
+    ExpectTrade {
+        quantity: OrderQuantity,
+        price: OrderPrice,
+        buy_order: OrderId,
+        sell_order: OrderId
+    },
+    ExpectDepth(OrderType, Vec<(OrderQuantity, OrderPrice)>),
+    ExpectBalance {
+        account: AccountId,
+        amount: AccountPosition,
+        symbol: String
+    },
+    ExpectStatus(OrderId, OrderStatus),
+    ExpectError(String)
+}
+
+/* a matching scenario, written one statement per line:
+ *
+ *     book <ticker> <currency>
+ *     account <id> [<amount> <symbol>]...
+ *     bid|ask <order> <account> <qty> @ <price>
+ *     ioc bid|ask <order> <account> <qty> [@ <price>]
+ *     amend <order> <qty> @ <price>
+ *     cancel <order>
+ *     expect trade <qty> @ <price> buy <order> sell <order>
+ *     expect depth bid|ask [<qty> @ <price>[, <qty> @ <price>]...]
+ *     expect balance <account> <amount> <symbol>
+ *     expect status <order> <status>
+ *     expect error <message>
+ *
+ * `book`, if given, comes first; otherwise the book trades VOC in USD.
+ * Every trade must be expected, in order, before the next action, and so
+ * must any error, straight after the action which caused it, and the book's
+ * invariants must hold after every action. Blank lines and anything after a
+ * '#' are ignored */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scenario {
+    ticker: String,
+    currency: Currency,
+    statements: Vec<(usize, Statement)>
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Self, ScenarioError> {
+        let mut scenario: Scenario = Scenario {
+            ticker: DEFAULT_TICKER.to_string(),
+            currency: DEFAULT_CURRENCY.to_string(),
+            statements: vec![]
+        };
+
+        for (index, text) in text.lines().enumerate() {
+            let line: usize = index + 1;
+            let text: String = text.split('#').next().unwrap_or("")
+                .replace('@', " @ ")
+                .replace(',', " , ");
+            let words: Vec<&str> = text.split_whitespace().collect();
+            let error = |reason: &str| ScenarioError::Parse(line,
+                reason.to_string());
+
+            match words.as_slice() {
+                [] => {},
+                ["book", ticker, currency] => {
+                    if !scenario.statements.is_empty() {
+                        return Err(error("book must come first"));
+                    }
+
+                    scenario.ticker = ticker.to_string();
+                    scenario.currency = currency.to_string();
+                },
+                ["account", id, deposits @ ..] => {
+                    let account: AccountId = number(id, line)?;
+
+                    if deposits.len() % 2 != 0 {
+                        return Err(error(
+                            "usage: account <id> [<amount> <symbol>]..."));
+                    }
+
+                    /* an account is opened by its first deposit */
+                    let deposits: Vec<(AccountBalance, String)> =
+                        match deposits {
+                            [] => vec![(0, scenario.currency.clone())],
+                            _ => deposits.chunks(2)
+                                .map(|pair| Ok((number(pair[0], line)?,
+                                    pair[1].to_string())))
+                                .collect::<Result<_, ScenarioError>>()?
+                        };
+
+                    for (amount, symbol) in deposits.into_iter() {
+                        scenario.statements.push((line, Statement::Act(
+                            Action::Deposit { account, amount, symbol })));
+                    }
+                },
+                [side @ ("bid" | "ask"), id, account, quantity, "@",
+                    price] => {
+                    let order: Order = Order::new(number(id, line)?,
+                        number(account, line)?, order_type(side, line)?,
+                        number(price, line)?, number(quantity, line)?);
+
+                    scenario.statements.push((line,
+                        Statement::Act(Action::Submit(order))));
+                },
+                ["ioc", side, id, account, quantity, rest @ ..] => {
+                    let (id, account, side, quantity): (OrderId, AccountId,
+                        OrderType, OrderQuantity) = (number(id, line)?,
+                        number(account, line)?, order_type(side, line)?,
+                        number(quantity, line)?);
+                    let order: Order = match rest {
+                        [] => Order::market(id, account, side, quantity),
+                        ["@", price] => Order::new(id, account, side,
+                            number(price, line)?, quantity),
+                        _ => return Err(error(
+                            "usage: ioc bid|ask <order> <account> <qty> \
+                            [@ <price>]"))
+                    };
+
+                    scenario.statements.push((line,
+                        Statement::Act(Action::SubmitIoc(order))));
+                },
+                ["amend", id, quantity, "@", price] =>
+                    scenario.statements.push((line,
+                        Statement::Act(Action::Amend {
+                            id: number(id, line)?,
+                            price: number(price, line)?,
+                            quantity: number(quantity, line)?
+                        }))),
+                ["cancel", id] => scenario.statements.push((line,
+                    Statement::Act(Action::Cancel(number(id, line)?)))),
+                ["expect", "trade", quantity, "@", price, "buy", buy, "sell",
+                    sell] =>
+                    scenario.statements.push((line, Statement::ExpectTrade {
+                        quantity: number(quantity, line)?,
+                        price: number(price, line)?,
+                        buy_order: number(buy, line)?,
+                        sell_order: number(sell, line)?
+                    })),
+                ["expect", "depth", side, levels @ ..] => {
+                    let levels: Vec<(OrderQuantity, OrderPrice)> = levels
+                        .split(|word| *word == ",")
+                        .filter(|level| !level.is_empty())
+                        .map(|level| match level {
+                            [quantity, "@", price] =>
+                                Ok((number(quantity, line)?,
+                                    number(price, line)?)),
+                            _ => Err(error("levels are <qty> @ <price>"))
+                        })
+                        .collect::<Result<_, ScenarioError>>()?;
+
+                    scenario.statements.push((line, Statement::ExpectDepth(
+                        order_type(side, line)?, levels)));
+                },
+                ["expect", "balance", account, amount, symbol] =>
+                    scenario.statements.push((line, Statement::ExpectBalance {
+                        account: number(account, line)?,
+                        amount: amount.parse().map_err(|_|
+                            error("balance is not a number"))?,
+                        symbol: symbol.to_string()
+                    })),
+                ["expect", "status", id, status] =>
+                    scenario.statements.push((line, Statement::ExpectStatus(
+                        number(id, line)?, order_status(status, line)?))),
+                ["expect", "error", message @ ..] if !message.is_empty() =>
+                    scenario.statements.push((line, Statement::ExpectError(
+                        message.join(" ")))),
+                [word, ..] => return Err(error(&format!(
+                    "unknown statement '{}'", word)))
+            }
+        }
+
+        Ok(scenario)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        Scenario::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn statements(&self) -> &[(usize, Statement)] {
+        &self.statements
+    }
+
+    /* stops at the first expectation which does not hold */
+    pub fn run(&self) -> Result<Replay, ScenarioError> {
+        let mut replay: Replay = Replay::new(self.ticker.clone(),
+            self.currency.clone());
+        let mut trades: usize = 0;
+        let mut error: Option<(usize, String)> = None;
+        let mut totals: Totals = Totals::default();
+
+        for (line, statement) in self.statements.iter() {
+            let line: usize = *line;
+            let fail = |reason: String| Err(ScenarioError::Failed(line,
+                reason));
+
+            if let Some((at, reason)) = &error {
+                if !matches!(statement, Statement::ExpectError(_)) {
+                    return Err(ScenarioError::Failed(*at,
+                        format!("unexpected error: {}", reason)));
+                }
+            }
+
+            match statement {
+                Statement::Act(action) => {
+                    if let Some((_, trade)) = replay.trades().get(trades) {
+                        return fail(format!("unexpected trade: {}",
+                            describe(trade)));
+                    }
+
+                    let rejections: usize = replay.rejections().len();
+                    replay.step(&Step::new(line, action.clone()));
+
+                    if let Some(rejection) =
+                        replay.rejections().get(rejections) {
+                        error = Some((line, rejection.reason().to_string()));
+                    }
+
+                    /* deposits are the only way in or out */
+                    if let Action::Deposit { .. } = action {
+                        totals = Totals::of(replay.accounts());
+                    }
+
+                    if let Err(violation) = invariant::check(replay.book(),
+                        replay.accounts(), &totals) {
+                        return fail(format!("invariant broken: {}",
+                            violation));
+                    }
+                },
+                Statement::ExpectTrade { quantity, price, buy_order,
+                    sell_order } => {
+                    let expected: Trade = Trade::new(*price, *quantity,
+                        OrderType::Bid, *buy_order, *sell_order, 0, 0);
+
+                    match replay.trades().get(trades) {
+                        Some((_, trade)) if trade.quantity() == *quantity &&
+                            trade.price() == *price &&
+                            trade.buy_order() == *buy_order &&
+                            trade.sell_order() == *sell_order =>
+                            trades += 1,
+                        Some((_, trade)) => return fail(format!(
+                            "expected trade {} but got {}",
+                            describe(&expected), describe(trade))),
+                        None => return fail(format!(
+                            "expected trade {} but there was none",
+                            describe(&expected)))
+                    }
+                },
+                Statement::ExpectDepth(side, expected) => {
+                    let (bids, asks): (Vec<Level>, Vec<Level>) =
+                        replay.book().depth(usize::MAX);
+                    let actual: Vec<(OrderQuantity, OrderPrice)> =
+                        match side {
+                            OrderType::Bid => bids,
+                            OrderType::Ask => asks
+                        }.iter()
+                        .map(|level| (level.quantity(), level.price()))
+                        .collect();
+
+                    if actual != *expected {
+                        return fail(format!("expected {} depth {} but got {}",
+                            side, levels(expected), levels(&actual)));
+                    }
+                },
+                Statement::ExpectBalance { account, amount, symbol } => {
+                    let actual: AccountPosition =
+                        match replay.accounts().get(account) {
+                            Some(a) if *symbol == replay.book().ticker() =>
+                                a.holding(symbol.clone()).unwrap_or(0),
+                            Some(a) => a.balance(symbol.clone())
+                                .unwrap_or(0) as AccountPosition,
+                            None => return fail(format!("no account {}",
+                                account))
+                        };
+
+                    if actual != *amount {
+                        return fail(format!(
+                            "expected account {} to have {} {} but it has {}",
+                            account, amount, symbol, actual));
+                    }
+                },
+                Statement::ExpectStatus(id, expected) => {
+                    match replay.book().order_status(*id) {
+                        Some(status) if status == *expected => {},
+                        Some(status) => return fail(format!(
+                            "expected order {} to be {} but it is {}", id,
+                            expected, status)),
+                        None => return fail(format!("no order {}", id))
+                    }
+                },
+                Statement::ExpectError(message) => match error.take() {
+                    Some((_, reason)) if reason.eq_ignore_ascii_case(
+                        message) => {},
+                    Some((_, reason)) => return fail(format!(
+                        "expected error '{}' but got '{}'", message, reason)),
+                    None => return fail(format!(
+                        "expected error '{}' but there was none", message))
+                }
+            }
+        }
+
+        if let Some((at, reason)) = error {
+            return Err(ScenarioError::Failed(at,
+                format!("unexpected error: {}", reason)));
+        }
+
+        if let Some((line, trade)) = replay.trades().get(trades) {
+            return Err(ScenarioError::Failed(*line,
+                format!("unexpected trade: {}", describe(trade))));
+        }
+
+        Ok(replay)
+    }
+}
+
+fn number<T: FromStr>(word: &str, line: usize) -> Result<T, ScenarioError> {
+    word.parse().map_err(|_| ScenarioError::Parse(line,
+        format!("'{}' is not a number", word)))
+}
+
+fn order_type(word: &str, line: usize) -> Result<OrderType, ScenarioError> {
+    match word {
+        "bid" => Ok(OrderType::Bid),
+        "ask" => Ok(OrderType::Ask),
+        _ => Err(ScenarioError::Parse(line,
+            format!("unknown statement or side '{}'", word)))
+    }
+}
+
+fn order_status(word: &str, line: usize) ->
+    Result<OrderStatus, ScenarioError> {
+    [OrderStatus::New, OrderStatus::PartiallyFilled, OrderStatus::Filled,
+        OrderStatus::Cancelled, OrderStatus::Rejected, OrderStatus::Expired]
+        .iter()
+        .find(|status| status.to_string().eq_ignore_ascii_case(word))
+        .copied()
+        .ok_or_else(|| ScenarioError::Parse(line,
+            format!("unknown status '{}'", word)))
+}
+
+/* as a scenario would expect it */
+fn describe(trade: &Trade) -> String {
+    format!("{} @ {} buy {} sell {}", trade.quantity(), trade.price(),
+        trade.buy_order(), trade.sell_order())
+}
+
+fn levels(levels: &[(OrderQuantity, OrderPrice)]) -> String {
+    match levels.is_empty() {
+        true => "(empty)".to_string(),
+        false => levels.iter()
+            .map(|(quantity, price)| format!("{} @ {}", quantity, price))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENARIO: &str = "\
+book VOC USD
+account 1 5000 USD
+account 2 30 VOC
+
+ask 1 2 20 @ 100
+bid 2 1 5 @ 100    # crosses
+expect trade 5 @ 100 buy 2 sell 1
+expect depth ask 15 @ 100
+expect depth bid
+bid 3 9 5 @ 100
+expect error No such account
+expect status 1 partially_filled
+";
+
+    #[test]
+    fn test_parse_statements() -> Result<(), ScenarioError> {
+        let scenario: Scenario = Scenario::parse(SCENARIO)?;
+
+        assert_eq!(scenario.statements().len(), 10);
+        assert_eq!(scenario.statements()[3], (6, Statement::Act(
+            Action::Submit(Order::new(2, 1, OrderType::Bid, 100, 5)))));
+        assert_eq!(scenario.statements()[5], (8, Statement::ExpectDepth(
+            OrderType::Ask, vec![(15, 100)])));
+
+        assert!(matches!(Scenario::parse("account 1 5000"),
+            Err(ScenarioError::Parse(1, _))));
+        assert!(matches!(Scenario::parse("bid 1 1 5 100"),
+            Err(ScenarioError::Parse(1, _))));
+        assert!(matches!(Scenario::parse("cancel 1\nbook ABC USD"),
+            Err(ScenarioError::Parse(2, _))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_passes() -> Result<(), ScenarioError> {
+        let replay: Replay = Scenario::parse(SCENARIO)?.run()?;
+
+        assert_eq!(replay.trades().len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_failures() -> Result<(), ScenarioError> {
+        let unexpected_trade: Scenario = Scenario::parse(
+            "account 1 500 USD\naccount 2 5 VOC\nask 1 2 5 @ 100\n\
+            bid 2 1 5 @ 100\n")?;
+        let wrong_depth: Scenario = Scenario::parse(
+            "account 1\nbid 1 1 5 @ 100\nexpect depth bid 5 @ 99\n")?;
+        let unexpected_error: Scenario = Scenario::parse(
+            "cancel 1\nexpect depth bid\n")?;
+        let missing_error: Scenario = Scenario::parse(
+            "account 1\nbid 1 1 5 @ 100\nexpect error No such account\n")?;
+
+        assert!(matches!(unexpected_trade.run(),
+            Err(ScenarioError::Failed(4, _))));
+        assert!(matches!(wrong_depth.run(), Err(ScenarioError::Failed(3, _))));
+        assert!(matches!(unexpected_error.run(),
+            Err(ScenarioError::Failed(1, _))));
+        assert!(matches!(missing_error.run(),
+            Err(ScenarioError::Failed(3, _))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,532 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::account::{Account, AccountBalance, AccountId, AccountPosition,
+    Currency};
+use crate::book::{Book, BookId, Level, Quote};
+use crate::decimal::{DecimalError, Precision, Price, Quantity, Scale};
+use crate::exchange::{Exchange, ExchangeError};
+use crate::execution::{ExecType, ExecutionReport};
+use crate::order::{Order, OrderId, OrderPrice, OrderQuantity, OrderType};
+
+pub const HELP: &str = "\
+new book <ticker> <currency> [places <price> <qty>] [name]
+                                       create a book, with prices and
+                                       quantities to so many decimal places,
+                                       agreeing with other books on the
+                                       places of its ticker and currency
+new account <name>                     create an account and act as it
+use <account>                          act as another account
+deposit <account> <amount> <symbol>    credit cash, or shares if <symbol>
+                                       is the ticker of a book; cash is to
+                                       the price places of books quoting it
+buy <ticker> <qty> [@ <price>] [as <account>]
+sell <ticker> <qty> [@ <price>] [as <account>]
+                                       without a price, a market order
+                                       that is not rested
+amend <order> <qty> @ <price>          change a resting order
+cancel <order>                         cancel a resting order
+show <ticker> [levels]                 show a book's price ladder
+show accounts                          show every account
+help                                   show this message
+quit                                   leave";
+
+const DEFAULT_LEVELS: usize = 10;
+
+#[derive(Debug, Error)]
+pub enum ShellError {
+    UnknownCommand(String),
+    Usage(&'static str),
+    InvalidNumber(String),
+    InvalidDecimal(String, DecimalError),
+    BookNotFound(String),
+    OrderNotFound(OrderId),
+    NoAccount,
+    Exchange(ExchangeError)
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShellError::UnknownCommand(c) =>
+                write!(f, "Unknown command '{}' (try 'help')", c),
+            ShellError::Usage(u) => write!(f, "Usage: {}", u),
+            ShellError::InvalidNumber(n) => write!(f, "'{}' is not a number", n),
+            ShellError::InvalidDecimal(n, e) => write!(f, "'{}': {}", n, e),
+            ShellError::BookNotFound(t) => write!(f, "No book for '{}'", t),
+            ShellError::OrderNotFound(id) =>
+                write!(f, "Order {} is not resting in any book", id),
+            ShellError::NoAccount =>
+                write!(f, "No account to act as (try 'new account')"),
+            ShellError::Exchange(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl From<ExchangeError> for ShellError {
+    fn from(e: ExchangeError) -> Self {
+        ShellError::Exchange(e)
+    }
+}
+
+/* a line-at-a-time command interpreter over an exchange */
+#[derive(Debug, Default)]
+pub struct Shell {
+    exchange: Exchange,
+    account: Option<AccountId>,
+    finished: bool
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        Shell::default()
+    }
+
+    pub fn exchange(&self) -> &Exchange {
+        &self.exchange
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /* runs one line, returning whatever it has to say */
+    pub fn execute(&mut self, line: &str) -> Result<String, ShellError> {
+        let line: String = line.replace('@', " @ ");
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let (command, args): (&str, &[&str]) = match words.split_first() {
+            Some((command, args)) => (command, args),
+            None => return Ok(String::new())
+        };
+
+        match command {
+            "new" => match args.split_first() {
+                Some((&"book", args)) => self.new_book(args),
+                Some((&"account", args)) => self.new_account(args),
+                _ => Err(ShellError::Usage("new book|account ..."))
+            },
+            "use" => self.use_account(args),
+            "deposit" => self.deposit(args),
+            "buy" => self.submit(OrderType::Bid, args),
+            "sell" => self.submit(OrderType::Ask, args),
+            "amend" => self.amend(args),
+            "cancel" => self.cancel(args),
+            "show" => match args {
+                ["accounts"] => Ok(self.show_accounts()),
+                [ticker] => self.show_book(ticker, DEFAULT_LEVELS),
+                [ticker, levels] => self.show_book(ticker, number(levels)?),
+                _ => Err(ShellError::Usage("show <ticker> [levels]|accounts"))
+            },
+            "help" => Ok(HELP.to_string()),
+            "quit" | "exit" => {
+                self.finished = true;
+                Ok(String::new())
+            },
+            _ => Err(ShellError::UnknownCommand(command.to_string()))
+        }
+    }
+
+    fn new_book(&mut self, args: &[&str]) -> Result<String, ShellError> {
+        const USAGE: &str =
+            "new book <ticker> <currency> [places <price> <qty>] [name]";
+
+        let (ticker, currency, args): (&str, &str, &[&str]) = match args {
+            [ticker, currency, rest @ ..] => (ticker, currency, rest),
+            _ => return Err(ShellError::Usage(USAGE))
+        };
+
+        let (precision, name): (Precision, &[&str]) = match args {
+            ["places", price, quantity, rest @ ..] =>
+                (Precision::new(number(price)?, number(quantity)?)
+                    .map_err(|e| ShellError::InvalidDecimal(
+                        format!("{} {}", price, quantity), e))?, rest),
+            ["places", ..] => return Err(ShellError::Usage(USAGE)),
+            _ => (Precision::default(), args)
+        };
+
+        let name: String = match name.is_empty() {
+            true => ticker.to_string(),
+            false => name.join(" ")
+        };
+
+        let id: BookId = self.exchange.books().keys().next_back()
+            .map_or(1, |id| id + 1);
+        let mut book: Book = Book::new(id, name, ticker.to_string(),
+            currency.to_string());
+        book.set_precision(precision);
+        self.exchange.add_book(book)?;
+
+        Ok(format!("book {} trades {} in {}", id, ticker, currency))
+    }
+
+    fn new_account(&mut self, args: &[&str]) -> Result<String, ShellError> {
+        if args.is_empty() {
+            return Err(ShellError::Usage("new account <name>"));
+        }
+
+        let id: AccountId = self.exchange.accounts().keys().max()
+            .map_or(1, |id| id + 1);
+        self.exchange.add_account(Account::new(id, args.join(" "),
+            HashMap::new(), HashMap::new()))?;
+        self.account = Some(id);
+
+        Ok(format!("account {} created, now acting as it", id))
+    }
+
+    fn use_account(&mut self, args: &[&str]) -> Result<String, ShellError> {
+        let id: AccountId = match args {
+            [id] => number(id)?,
+            _ => return Err(ShellError::Usage("use <account>"))
+        };
+
+        if self.exchange.account(id).is_none() {
+            return Err(ExchangeError::AccountNotFound.into());
+        }
+
+        self.account = Some(id);
+        Ok(format!("acting as account {}", id))
+    }
+
+    fn deposit(&mut self, args: &[&str]) -> Result<String, ShellError> {
+        let (id, amount, symbol): (AccountId, &str, &str) = match args {
+            [id, amount, symbol] => (number(id)?, amount, symbol),
+            _ => return Err(ShellError::Usage(
+                "deposit <account> <amount> <symbol>"))
+        };
+
+        let is_holding: bool = self.find_book(symbol).is_ok();
+        let scale: Scale = self.scale(symbol);
+        let units: AccountBalance = decimal(amount, scale)?;
+
+        if is_holding {
+            self.exchange.deposit_holding(id, symbol.to_string(), units)?;
+        } else {
+            self.exchange.deposit(id, symbol.to_string(), units)?;
+        }
+
+        Ok(format!("deposited {} {} to account {}",
+            Price::new(units, scale), symbol, id))
+    }
+
+    fn submit(&mut self, side: OrderType, args: &[&str]) ->
+        Result<String, ShellError> {
+        const USAGE: &str = "buy|sell <ticker> <qty> [@ <price>] [as <account>]";
+
+        let (args, owner): (&[&str], Option<AccountId>) = match args {
+            [rest @ .., "as", id] => (rest, Some(number(id)?)),
+            _ => (args, self.account)
+        };
+
+        let (ticker, quantity, price): (&str, &str, Option<&str>) =
+            match args {
+            [ticker, quantity] => (ticker, quantity, None),
+            [ticker, quantity, "@", price] => (ticker, quantity, Some(price)),
+            _ => return Err(ShellError::Usage(USAGE))
+        };
+
+        let owner: AccountId = owner.ok_or(ShellError::NoAccount)?;
+        let book: BookId = self.find_book(ticker)?;
+        let quantity: OrderQuantity = self.parse_quantity(book, quantity)?;
+        let price: Option<OrderPrice> = match price {
+            Some(p) => Some(self.parse_price(book, p)?),
+            None => None
+        };
+        let id: OrderId = self.exchange.next_order_id();
+
+        let result: Result<(), ExchangeError> = match price {
+            Some(p) => self.exchange.submit(book,
+                Order::new(id, owner, side, p, quantity)),
+            None => self.exchange.submit_ioc(book,
+                Order::market(id, owner, side, quantity))
+        };
+
+        self.reported(book, format!("order {}", id), result)
+    }
+
+    fn amend(&mut self, args: &[&str]) -> Result<String, ShellError> {
+        let (id, quantity, price): (OrderId, &str, &str) = match args {
+            [id, quantity, "@", price] => (number(id)?, quantity, price),
+            _ => return Err(ShellError::Usage("amend <order> <qty> @ <price>"))
+        };
+
+        let book: BookId = self.resting_book(id)?;
+        let quantity: OrderQuantity = self.parse_quantity(book, quantity)?;
+        let price: OrderPrice = self.parse_price(book, price)?;
+        let result: Result<(), ExchangeError> =
+            self.exchange.amend(book, id, price, quantity);
+
+        self.reported(book, format!("order {} amended", id), result)
+    }
+
+    fn cancel(&mut self, args: &[&str]) -> Result<String, ShellError> {
+        let id: OrderId = match args {
+            [id] => number(id)?,
+            _ => return Err(ShellError::Usage("cancel <order>"))
+        };
+
+        let book: BookId = self.resting_book(id)?;
+        let result: Result<(), ExchangeError> = self.exchange.cancel(book, id);
+
+        self.reported(book, format!("order {} cancelled", id), result)
+    }
+
+    fn show_book(&self, ticker: &str, levels: usize) ->
+        Result<String, ShellError> {
+        let book: &Book = self.exchange.book(self.find_book(ticker)?).unwrap();
+        let (bids, asks): (Vec<Level>, Vec<Level>) = book.depth(levels);
+        let quote: String = match book.quote() {
+            Quote::Currency(currency) => currency,
+            Quote::Asset(ticker) => ticker
+        };
+
+        let mut lines: Vec<String> = vec![match book.ltp() {
+            Some(ltp) => format!("{}/{} (last {})", book.ticker(), quote,
+                book.price(ltp)),
+            None => format!("{}/{}", book.ticker(), quote)
+        }];
+
+        for level in asks.iter().rev() {
+            lines.push(format!("{:>12} | {:>8} | {:<12}", "",
+                book.price(level.price()).to_string(),
+                format!("{} ({})", book.quantity(level.quantity()),
+                    level.orders())));
+        }
+
+        for level in bids.iter() {
+            lines.push(format!("{:>12} | {:>8} | {:<12}",
+                format!("{} ({})", book.quantity(level.quantity()),
+                    level.orders()),
+                book.price(level.price()).to_string(), ""));
+        }
+
+        Ok(lines.iter().map(|line| line.trim_end()).collect::<Vec<&str>>()
+            .join("\n"))
+    }
+
+    fn show_accounts(&self) -> String {
+        let mut accounts: Vec<&Account> =
+            self.exchange.accounts().values().collect();
+        accounts.sort_by_key(|account| account.id());
+
+        accounts.iter().map(|account| {
+            let mut balances: Vec<(&Currency, &AccountBalance)> =
+                account.balances().iter().collect();
+            balances.sort();
+            let mut holdings: Vec<(&String, &AccountPosition)> =
+                account.holdings().iter().collect();
+            holdings.sort();
+
+            let assets: Vec<String> = balances.iter()
+                .map(|(currency, amount)| format!("{} {}",
+                    Price::new(**amount, self.scale(currency)), currency))
+                .chain(holdings.iter()
+                    .map(|(ticker, amount)| format!("{}{} {}",
+                        if **amount < 0 { "-" } else { "" },
+                        Quantity::new(amount.unsigned_abs(),
+                            self.scale(ticker)), ticker)))
+                .collect();
+
+            format!("{} {}: {}", account.id(), account.name(),
+                assets.join(", "))
+        }).collect::<Vec<String>>().join("\n")
+    }
+
+    /* the decimal places amounts of a symbol are counted to, none until a
+     * book trades or quotes it */
+    fn scale(&self, symbol: &str) -> Scale {
+        self.exchange.scale(symbol).unwrap_or(0)
+    }
+
+    fn parse_price(&self, book: BookId, word: &str) ->
+        Result<OrderPrice, ShellError> {
+        self.exchange.book(book).unwrap().parse_price(word)
+            .map(|price| price.units())
+            .map_err(|e| ShellError::InvalidDecimal(word.to_string(), e))
+    }
+
+    fn parse_quantity(&self, book: BookId, word: &str) ->
+        Result<OrderQuantity, ShellError> {
+        self.exchange.book(book).unwrap().parse_quantity(word)
+            .map(|quantity| quantity.units())
+            .map_err(|e| ShellError::InvalidDecimal(word.to_string(), e))
+    }
+
+    fn find_book(&self, ticker: &str) -> Result<BookId, ShellError> {
+        self.exchange.books().values()
+            .find(|book| book.ticker() == ticker)
+            .map(|book| book.id())
+            .ok_or_else(|| ShellError::BookNotFound(ticker.to_string()))
+    }
+
+    fn resting_book(&self, id: OrderId) -> Result<BookId, ShellError> {
+        self.exchange.books().values()
+            .find(|book| book.order(id).is_some())
+            .map(|book| book.id())
+            .ok_or(ShellError::OrderNotFound(id))
+    }
+
+    /* the outcome of a request, followed by the execution reports the book
+     * produced while handling it, which are then acknowledged */
+    fn reported(&mut self, book: BookId, outcome: String,
+        result: Result<(), ExchangeError>) -> Result<String, ShellError> {
+        let reports: Vec<ExecutionReport> = match self.exchange
+            .book_mut(book) {
+            Some(b) => b.take_reports(),
+            None => vec![]
+        };
+
+        if let Some(last) = reports.last() {
+            self.exchange.acknowledge(book, last.exec_id())?;
+        }
+
+        let mut lines: Vec<String> = vec![match &result {
+            Ok(()) => outcome,
+            Err(e) => format!("{}: {}", outcome, e)
+        }];
+
+        if let Some(b) = self.exchange.book(book) {
+            lines.extend(reports.iter().map(|report| describe(b, report)));
+        }
+
+        Ok(lines.join("\n"))
+    }
+}
+
+fn describe(book: &Book, report: &ExecutionReport) -> String {
+    match report.exec_type() {
+        ExecType::New => format!("  {} accepted ({} {})", report.order_id(),
+            report.side(), book.quantity(report.leaves_quantity())),
+        ExecType::Trade => format!("  {} traded {} @ {} ({} left)",
+            report.order_id(), book.quantity(report.last_quantity()),
+            book.price(report.last_price()),
+            book.quantity(report.leaves_quantity())),
+        ExecType::Replaced => format!("  {} replaced ({} @ {})",
+            report.order_id(), book.quantity(report.leaves_quantity()),
+            book.price(report.price())),
+        exec_type => format!("  {} {} ({} filled)", report.order_id(),
+            exec_type.to_string().to_lowercase(),
+            book.quantity(report.cumulative_quantity()))
+    }
+}
+
+/* amounts of cash or shares are parsed to a scale of their own */
+fn decimal(word: &str, scale: Scale) -> Result<AccountBalance, ShellError> {
+    Price::parse(word, scale).map(|amount| amount.units())
+        .map_err(|e| ShellError::InvalidDecimal(word.to_string(), e))
+}
+
+fn number<T: std::str::FromStr>(word: &str) -> Result<T, ShellError> {
+    word.parse::<T>().map_err(|_| ShellError::InvalidNumber(word.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(shell: &mut Shell, lines: &[&str]) -> Result<String, ShellError> {
+        let mut output: String = String::new();
+
+        for line in lines.iter() {
+            output = shell.execute(line)?;
+        }
+
+        Ok(output)
+    }
+
+    #[test]
+    fn test_execute_ladder_and_accounts() -> Result<(), ShellError> {
+        let mut actual_shell: Shell = Shell::new();
+
+        let trades: String = run(&mut actual_shell, &[
+            "new book VOC USD Vereenigde Oostindische Compagnie",
+            "new account John Doe",
+            "new account Jane Doe",
+            "deposit 1 10000 USD",
+            "deposit 2 100 VOC",
+            "sell VOC 30 @ 126",
+            "sell VOC 20@125",
+            "buy VOC 25 @ 125 as 1"
+        ])?;
+
+        assert_eq!(trades, "order 2\n  2 accepted (BID 25)\n\
+            \x20 2 traded 20 @ 125 (5 left)\n  1 traded 20 @ 125 (0 left)");
+        assert_eq!(actual_shell.execute("show VOC")?, "VOC/USD (last 125)\n\
+            \x20            |      126 | 30 (1)\n\
+            \x20      5 (1) |      125 |");
+        assert_eq!(actual_shell.execute("show accounts")?,
+            "1 John Doe: 7500 USD, 20 VOC\n2 Jane Doe: 2500 USD, 80 VOC");
+
+        assert_eq!(actual_shell.execute("cancel 2")?,
+            "order 2 cancelled\n  2 cancelled (20 filled)");
+        assert!(!actual_shell.is_finished());
+        actual_shell.execute("quit")?;
+        assert!(actual_shell.is_finished());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_decimals() -> Result<(), ShellError> {
+        let mut actual_shell: Shell = Shell::new();
+
+        let trades: String = run(&mut actual_shell, &[
+            "new book VOC USD places 2 1 Vereenigde Oostindische Compagnie",
+            "new account John Doe",
+            "new account Jane Doe",
+            "deposit 1 100.50 USD",
+            "deposit 2 5.5 VOC",
+            "sell VOC 2.5 @ 12.34",
+            "buy VOC 1.5 @ 12.34 as 1"
+        ])?;
+
+        assert_eq!(trades, "order 1\n  1 accepted (BID 1.5)\n\
+            \x20 1 traded 1.5 @ 12.34 (0.0 left)\n\
+            \x20 0 traded 1.5 @ 12.34 (1.0 left)");
+        assert_eq!(actual_shell.execute("show VOC")?, "VOC/USD (last 12.34)\n\
+            \x20            |    12.34 | 1.0 (1)");
+        assert_eq!(actual_shell.execute("show accounts")?,
+            "1 John Doe: 81.99 USD, 1.5 VOC\n2 Jane Doe: 18.51 USD, 4.0 VOC");
+        assert!(matches!(actual_shell.execute("buy VOC 1.25 @ 12"),
+            Err(ShellError::InvalidDecimal(_, DecimalError::TooPrecise(1)))));
+        assert!(matches!(actual_shell.execute("new book ASML EUR places 2"),
+            Err(ShellError::Usage(_))));
+
+        /* dollars are already counted in cents */
+        assert!(matches!(actual_shell.execute("new book WIC USD places 4 0"),
+            Err(ShellError::Exchange(ExchangeError::ScaleMismatch(_, 2, 4)))));
+        actual_shell.execute("new book WIC USD places 2 0")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_errors() -> Result<(), ShellError> {
+        let mut actual_shell: Shell = Shell::new();
+        actual_shell.execute("new book VOC USD")?;
+
+        assert!(matches!(actual_shell.execute("buy VOC 10 @ 100"),
+            Err(ShellError::NoAccount)));
+        assert!(matches!(actual_shell.execute("buy MSFT 10 @ 100 as 1"),
+            Err(ShellError::BookNotFound(_))));
+        assert!(matches!(actual_shell.execute("buy VOC ten as 1"),
+            Err(ShellError::InvalidDecimal(_, DecimalError::Malformed(_)))));
+        assert!(matches!(actual_shell.execute("use one"),
+            Err(ShellError::InvalidNumber(_))));
+        assert!(matches!(actual_shell.execute("frobnicate"),
+            Err(ShellError::UnknownCommand(_))));
+        assert!(matches!(actual_shell.execute("use 7"),
+            Err(ShellError::Exchange(ExchangeError::AccountNotFound))));
+
+        /* the exchange's refusal is reported rather than raised */
+        run(&mut actual_shell, &["new account John Doe",
+            "new account Jane Doe", "deposit 2 10 VOC", "sell VOC 10 @ 100"])?;
+        assert_eq!(actual_shell.execute("buy VOC 10 @ 100 as 1")?,
+            "order 1: No such currency in account\n  1 accepted (BID 10)\n\
+            \x20 1 cancelled (0 filled)");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,762 @@
+use std::collections::{BTreeMap, HashMap};
+use std::iter;
+use std::mem;
+
+use crate::book::BookError;
+use crate::order::{Order, OrderId, OrderPrice};
+use crate::slab::Slab;
+use crate::snapshot::{Codec, Decoder, Encoder, SnapshotError};
+
+/* the most levels a ladder may hold, as each takes memory whether or not
+ * anything rests there */
+pub const MAX_LEVELS: usize = 1 << 20;
+
+/* a price level: the ends of a list of orders linked through their nodes,
+ * front first */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OrderQueue {
+    head: Option<usize>,
+    tail: Option<usize>,
+    len: usize
+}
+
+impl OrderQueue {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+pub type Tree = BTreeMap<OrderPrice, OrderQueue>;
+
+pub type LevelIter<'a> =
+    Box<dyn DoubleEndedIterator<Item = (OrderPrice, &'a OrderQueue)> + 'a>;
+
+/* where the queues on one side of a book are, by price; a level with
+ * nothing queued at it does not count as a level at all */
+pub trait Side {
+    fn level(&self, price: OrderPrice) -> Option<&OrderQueue>;
+
+    fn level_mut(&mut self, price: OrderPrice) -> Option<&mut OrderQueue>;
+
+    /* the level at `price`, which is made if need be */
+    fn level_entry(&mut self, price: OrderPrice) -> &mut OrderQueue;
+
+    fn clear_level(&mut self, price: OrderPrice);
+
+    fn lowest(&self) -> Option<OrderPrice>;
+
+    fn highest(&self) -> Option<OrderPrice>;
+
+    /* every level, from the lowest price up */
+    fn levels(&self) -> LevelIter<'_>;
+
+    /* whether an order may rest at `price` */
+    fn accepts(&self, price: OrderPrice) -> bool;
+}
+
+impl Side for Tree {
+    fn level(&self, price: OrderPrice) -> Option<&OrderQueue> {
+        self.get(&price)
+    }
+
+    fn level_mut(&mut self, price: OrderPrice) -> Option<&mut OrderQueue> {
+        self.get_mut(&price)
+    }
+
+    fn level_entry(&mut self, price: OrderPrice) -> &mut OrderQueue {
+        self.entry(price).or_default()
+    }
+
+    fn clear_level(&mut self, price: OrderPrice) {
+        self.remove(&price);
+    }
+
+    fn lowest(&self) -> Option<OrderPrice> {
+        self.keys().next().copied()
+    }
+
+    fn highest(&self) -> Option<OrderPrice> {
+        self.keys().next_back().copied()
+    }
+
+    fn levels(&self) -> LevelIter<'_> {
+        Box::new(self.iter().map(|(price, queue)| (*price, queue)))
+    }
+
+    fn accepts(&self, _price: OrderPrice) -> bool {
+        true
+    }
+}
+
+/* the prices an instrument may rest at: every `tick` from `low` to `high` */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Band {
+    low: OrderPrice,
+    high: OrderPrice,
+    tick: OrderPrice
+}
+
+impl Band {
+    pub fn new(low: OrderPrice, high: OrderPrice, tick: OrderPrice) ->
+        Result<Self, BookError> {
+        if tick == 0 || high < low || !(high - low).is_multiple_of(tick) ||
+            (high - low) / tick >= MAX_LEVELS as OrderPrice {
+            return Err(BookError::InvalidBand);
+        }
+
+        Ok(Band {
+            low,
+            high,
+            tick
+        })
+    }
+
+    pub fn low(&self) -> OrderPrice {
+        self.low
+    }
+
+    pub fn high(&self) -> OrderPrice {
+        self.high
+    }
+
+    pub fn tick(&self) -> OrderPrice {
+        self.tick
+    }
+
+    pub fn contains(&self, price: OrderPrice) -> bool {
+        self.index(price).is_some()
+    }
+
+    fn index(&self, price: OrderPrice) -> Option<usize> {
+        match price >= self.low && price <= self.high &&
+            (price - self.low).is_multiple_of(self.tick) {
+            true => Some(((price - self.low) / self.tick) as usize),
+            false => None
+        }
+    }
+
+    fn price(&self, index: usize) -> OrderPrice {
+        self.low + index as OrderPrice * self.tick
+    }
+
+    fn levels(&self) -> usize {
+        ((self.high - self.low) / self.tick) as usize + 1
+    }
+}
+
+/* a level for every price in a band, side by side, so that finding a level
+ * is arithmetic rather than a search; the lowest and highest levels with
+ * anything resting are kept track of, so the best price on either side of
+ * the book is always to hand */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ladder {
+    band: Band,
+    levels: Vec<OrderQueue>,
+    occupied: Option<(usize, usize)>
+}
+
+impl Ladder {
+    pub fn new(band: Band) -> Self {
+        Ladder {
+            band,
+            levels: vec![OrderQueue::default(); band.levels()],
+            occupied: None
+        }
+    }
+
+    pub fn band(&self) -> Band {
+        self.band
+    }
+
+    /* moves the bounds inwards past any levels which have emptied */
+    fn settle(&mut self) {
+        let (mut low, mut high): (usize, usize) = match self.occupied {
+            Some(bounds) => bounds,
+            None => return
+        };
+
+        while low <= high && self.levels[low].is_empty() {
+            low += 1;
+        }
+
+        if low > high {
+            self.occupied = None;
+            return;
+        }
+
+        while self.levels[high].is_empty() {
+            high -= 1;
+        }
+
+        self.occupied = Some((low, high));
+    }
+}
+
+impl Side for Ladder {
+    fn level(&self, price: OrderPrice) -> Option<&OrderQueue> {
+        self.levels.get(self.band.index(price)?)
+            .filter(|queue| !queue.is_empty())
+    }
+
+    fn level_mut(&mut self, price: OrderPrice) -> Option<&mut OrderQueue> {
+        self.levels.get_mut(self.band.index(price)?)
+            .filter(|queue| !queue.is_empty())
+    }
+
+    /* the price must be in the band */
+    fn level_entry(&mut self, price: OrderPrice) -> &mut OrderQueue {
+        let index: usize = self.band.index(price).unwrap();
+
+        self.occupied = match self.occupied {
+            Some((low, high)) => Some((low.min(index), high.max(index))),
+            None => Some((index, index))
+        };
+        &mut self.levels[index]
+    }
+
+    fn clear_level(&mut self, price: OrderPrice) {
+        if let Some(index) = self.band.index(price) {
+            mem::take(&mut self.levels[index]);
+            self.settle();
+        }
+    }
+
+    fn lowest(&self) -> Option<OrderPrice> {
+        self.occupied.map(|(low, _)| self.band.price(low))
+    }
+
+    fn highest(&self) -> Option<OrderPrice> {
+        self.occupied.map(|(_, high)| self.band.price(high))
+    }
+
+    fn levels(&self) -> LevelIter<'_> {
+        let (low, high): (usize, usize) = match self.occupied {
+            Some(bounds) => bounds,
+            None => return Box::new(iter::empty())
+        };
+
+        Box::new(self.levels[low..=high].iter().enumerate()
+            .filter(|(_, queue)| !queue.is_empty())
+            .map(move |(offset, queue)| (self.band.price(low + offset),
+                queue)))
+    }
+
+    fn accepts(&self, price: OrderPrice) -> bool {
+        self.band.contains(price)
+    }
+}
+
+
+/* whichever kind of levels a side was set up with */
+#[derive(Clone, Debug)]
+enum Levels {
+    Tree(Tree),
+    Ladder(Ladder)
+}
+
+impl Levels {
+    fn inner(&self) -> &dyn Side {
+        match self {
+            Levels::Tree(tree) => tree,
+            Levels::Ladder(ladder) => ladder
+        }
+    }
+
+    fn inner_mut(&mut self) -> &mut dyn Side {
+        match self {
+            Levels::Tree(tree) => tree,
+            Levels::Ladder(ladder) => ladder
+        }
+    }
+}
+
+/* an order and its neighbours in the queue at its price */
+#[derive(Clone, Debug)]
+struct Node {
+    order: Order,
+    prev: Option<usize>,
+    next: Option<usize>
+}
+
+/* the resting orders on one side of a book. The orders live in a slab, each
+ * linked to those either side of it in its queue, so that taking one from
+ * anywhere in a queue moves nothing else; the levels only record where each
+ * queue begins and ends, and each order's slot is kept by its id */
+#[derive(Clone, Debug)]
+pub struct BookSide {
+    levels: Levels,
+    nodes: Slab<Node>,
+    slots: HashMap<OrderId, usize>
+}
+
+impl Default for BookSide {
+    fn default() -> Self {
+        BookSide::new(None)
+    }
+}
+
+/* two sides are the same if they queue the same orders in the same way,
+ * wherever in their slabs they happen to keep them */
+impl PartialEq for BookSide {
+    fn eq(&self, other: &Self) -> bool {
+        self.band() == other.band() && self.orders().eq(other.orders())
+    }
+}
+
+impl Eq for BookSide {}
+
+impl BookSide {
+    /* a ladder over the band if there is one, else a tree */
+    pub fn new(band: Option<Band>) -> Self {
+        BookSide {
+            levels: match band {
+                Some(band) => Levels::Ladder(Ladder::new(band)),
+                None => Levels::Tree(Tree::new())
+            },
+            nodes: Slab::new(),
+            slots: HashMap::new()
+        }
+    }
+
+    pub fn band(&self) -> Option<Band> {
+        match &self.levels {
+            Levels::Tree(_) => None,
+            Levels::Ladder(ladder) => Some(ladder.band())
+        }
+    }
+
+    pub fn accepts(&self, price: OrderPrice) -> bool {
+        self.levels.inner().accepts(price)
+    }
+
+    pub fn lowest(&self) -> Option<OrderPrice> {
+        self.levels.inner().lowest()
+    }
+
+    pub fn highest(&self) -> Option<OrderPrice> {
+        self.levels.inner().highest()
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn contains(&self, id: OrderId) -> bool {
+        self.slots.contains_key(&id)
+    }
+
+    /* the ids of the resting orders, in no particular order */
+    pub fn ids(&self) -> impl Iterator<Item = OrderId> + '_ {
+        self.slots.keys().copied()
+    }
+
+    pub fn get(&self, id: OrderId) -> Option<&Order> {
+        self.nodes.get(*self.slots.get(&id)?).map(|node| &node.order)
+    }
+
+    pub fn get_mut(&mut self, id: OrderId) -> Option<&mut Order> {
+        self.nodes.get_mut(*self.slots.get(&id)?).map(|node| &mut node.order)
+    }
+
+    /* the number of orders ahead of this one in its queue */
+    pub fn position(&self, id: OrderId) -> Option<usize> {
+        let mut prev: Option<usize> = self.nodes.get(*self.slots.get(&id)?)?
+            .prev;
+        let mut position: usize = 0;
+
+        while let Some(index) = prev {
+            prev = self.node(index).prev;
+            position += 1;
+        }
+
+        Some(position)
+    }
+
+    /* the queue at `price`, front first */
+    pub fn level(&self, price: OrderPrice) -> Option<Orders<'_>> {
+        self.levels.inner().level(price).map(|queue| self.queue(queue))
+    }
+
+    /* every queue, from the lowest price up */
+    pub fn levels(&self) -> Box<dyn DoubleEndedIterator<Item = (OrderPrice,
+        Orders<'_>)> + '_> {
+        Box::new(self.levels.inner().levels()
+            .map(move |(price, queue)| (price, self.queue(queue))))
+    }
+
+    /* every resting order, from the lowest price up */
+    pub fn orders(&self) -> impl Iterator<Item = &Order> + '_ {
+        self.levels().flat_map(|(_, orders)| orders)
+    }
+
+    pub fn front_mut(&mut self, price: OrderPrice) -> Option<&mut Order> {
+        let index: usize = self.levels.inner().level(price)?.head?;
+
+        Some(&mut self.node_mut(index).order)
+    }
+
+    /* rests the order at the back of its price's queue; the price must be
+     * one the side accepts, and the id must not be resting already */
+    pub fn push(&mut self, order: Order) {
+        let (id, price): (OrderId, OrderPrice) = (order.id(), order.price());
+        let tail: Option<usize> = self.levels.inner().level(price)
+            .and_then(|queue| queue.tail);
+        let index: usize = self.nodes.insert(Node {
+            order,
+            prev: tail,
+            next: None
+        });
+
+        if let Some(tail) = tail {
+            self.node_mut(tail).next = Some(index);
+        }
+
+        let queue: &mut OrderQueue = self.levels.inner_mut()
+            .level_entry(price);
+
+        queue.head = queue.head.or(Some(index));
+        queue.tail = Some(index);
+        queue.len += 1;
+        self.slots.insert(id, index);
+    }
+
+    pub fn pop_front(&mut self, price: OrderPrice) -> Option<Order> {
+        let index: usize = self.levels.inner().level(price)?.head?;
+        let order: Order = self.unlink(index);
+
+        self.slots.remove(&order.id());
+        Some(order)
+    }
+
+    pub fn remove(&mut self, id: OrderId) -> Option<Order> {
+        let index: usize = self.slots.remove(&id)?;
+
+        Some(self.unlink(index))
+    }
+
+    /* takes the order at `index` out of its queue, and the queue out of
+     * the levels should that leave it empty */
+    fn unlink(&mut self, index: usize) -> Order {
+        let node: Node = self.nodes.remove(index).unwrap();
+        let price: OrderPrice = node.order.price();
+        let queue: &mut OrderQueue = self.levels.inner_mut().level_mut(price)
+            .unwrap();
+
+        if node.prev.is_none() {
+            queue.head = node.next;
+        }
+
+        if node.next.is_none() {
+            queue.tail = node.prev;
+        }
+
+        queue.len -= 1;
+
+        if queue.is_empty() {
+            self.levels.inner_mut().clear_level(price);
+        }
+
+        if let Some(prev) = node.prev {
+            self.node_mut(prev).next = node.next;
+        }
+
+        if let Some(next) = node.next {
+            self.node_mut(next).prev = node.prev;
+        }
+
+        node.order
+    }
+
+    fn queue(&self, queue: &OrderQueue) -> Orders<'_> {
+        Orders {
+            nodes: &self.nodes,
+            next: queue.head,
+            len: queue.len
+        }
+    }
+
+    fn node(&self, index: usize) -> &Node {
+        self.nodes.get(index).unwrap()
+    }
+
+    fn node_mut(&mut self, index: usize) -> &mut Node {
+        self.nodes.get_mut(index).unwrap()
+    }
+}
+
+/* the orders in one queue, front first */
+pub struct Orders<'a> {
+    nodes: &'a Slab<Node>,
+    next: Option<usize>,
+    len: usize
+}
+
+impl<'a> Iterator for Orders<'a> {
+    type Item = &'a Order;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node: &'a Node = self.nodes.get(self.next?).unwrap();
+
+        self.next = node.next;
+        self.len -= 1;
+        Some(&node.order)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl ExactSizeIterator for Orders<'_> {}
+
+impl Codec for Band {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.low.encode(encoder);
+        self.high.encode(encoder);
+        self.tick.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Band::new(Codec::decode(decoder)?, Codec::decode(decoder)?,
+            Codec::decode(decoder)?).map_err(|_| SnapshotError::Malformed)
+    }
+}
+
+/* a side is written as its band, if any, and then its orders in priority
+ * order from the lowest level up; where they were in the slab is not kept */
+impl Codec for BookSide {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.band().encode(encoder);
+        self.orders().cloned().collect::<Vec<Order>>().encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        let mut side: BookSide = BookSide::new(Codec::decode(decoder)?);
+
+        for order in Vec::<Order>::decode(decoder)?.into_iter() {
+            if !side.accepts(order.price()) || side.contains(order.id()) {
+                return Err(SnapshotError::Malformed);
+            }
+
+            side.push(order);
+        }
+
+        Ok(side)
+    }
+}
+
+/* serialized as the band and the resting orders from the lowest price up,
+ * leaving the slab and the levels to be rebuilt, so that what is read back
+ * is checked as a snapshot would be */
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct SideOut<'a> {
+    band: Option<Band>,
+    orders: Vec<&'a Order>
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct SideIn {
+    band: Option<Band>,
+    orders: Vec<Order>
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for BookSide {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) ->
+        Result<S::Ok, S::Error> {
+        SideOut {
+            band: self.band(),
+            orders: self.orders().collect()
+        }.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for BookSide {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) ->
+        Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let input: SideIn = SideIn::deserialize(deserializer)?;
+        let band: Option<Band> = match input.band {
+            Some(b) => Some(Band::new(b.low, b.high, b.tick)
+                .map_err(|e| D::Error::custom(e.to_string()))?),
+            None => None
+        };
+        let mut side: BookSide = BookSide::new(band);
+
+        for order in input.orders.into_iter() {
+            if !side.accepts(order.price()) {
+                return Err(D::Error::custom(format!(
+                    "order {} is priced outside the band", order.id())));
+            }
+
+            if side.contains(order.id()) {
+                return Err(D::Error::custom(format!(
+                    "order {} rests more than once", order.id())));
+            }
+
+            side.push(order);
+        }
+
+        Ok(side)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::OrderType;
+
+    fn ask(id: OrderId, price: OrderPrice) -> Order {
+        Order::new(id, 1, OrderType::Ask, price, 10)
+    }
+
+    fn ids(side: &BookSide) -> Vec<OrderId> {
+        side.orders().map(|order| order.id()).collect()
+    }
+
+    fn prices(side: &BookSide) -> Vec<OrderPrice> {
+        side.levels().map(|(price, _)| price).collect()
+    }
+
+    #[test]
+    fn test_band_new() -> Result<(), BookError> {
+        let band: Band = Band::new(100, 200, 5)?;
+
+        assert!(band.contains(100) && band.contains(105) && band.contains(200));
+        assert!(!band.contains(95) && !band.contains(102) &&
+            !band.contains(205));
+        assert_eq!(band.levels(), 21);
+
+        assert!(matches!(Band::new(100, 200, 0), Err(BookError::InvalidBand)));
+        assert!(matches!(Band::new(200, 100, 5), Err(BookError::InvalidBand)));
+        assert!(matches!(Band::new(100, 202, 5), Err(BookError::InvalidBand)));
+        assert!(matches!(Band::new(0, MAX_LEVELS as OrderPrice, 1),
+            Err(BookError::InvalidBand)));
+
+        Ok(())
+    }
+
+    /* a ladder and a tree given the same orders agree on everything */
+    #[test]
+    fn test_ladder_agrees_with_tree() -> Result<(), BookError> {
+        let mut ladder: BookSide = BookSide::new(Some(Band::new(100, 200, 5)?));
+        let mut tree: BookSide = BookSide::new(None);
+
+        for side in [&mut ladder, &mut tree] {
+            side.push(ask(1, 150));
+            side.push(ask(2, 110));
+            side.push(ask(3, 150));
+            side.push(ask(4, 190));
+        }
+
+        assert_eq!(prices(&ladder), vec![110, 150, 190]);
+        assert_eq!(prices(&ladder), prices(&tree));
+        assert_eq!(ids(&ladder), vec![2, 1, 3, 4]);
+        assert_eq!(ids(&ladder), ids(&tree));
+        assert!(ladder.level(155).is_none());
+        assert_eq!((ladder.lowest(), ladder.highest()), (Some(110), Some(190)));
+
+        /* emptying the lowest level moves the cursor up to the next */
+        for side in [&mut ladder, &mut tree] {
+            assert_eq!(side.pop_front(110).map(|order| order.id()), Some(2));
+        }
+
+        assert_eq!(ladder.lowest(), Some(150));
+        assert_eq!(prices(&ladder), prices(&tree));
+
+        for side in [&mut ladder, &mut tree] {
+            side.remove(4);
+            side.remove(1);
+            side.remove(3);
+        }
+
+        assert_eq!((ladder.lowest(), ladder.highest()), (None, None));
+        assert_eq!(prices(&ladder), prices(&tree));
+        assert!(ladder.is_empty() && tree.is_empty());
+        assert!(ladder.accepts(100) && !ladder.accepts(101));
+        assert!(tree.accepts(101));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_middle_of_queue() {
+        let mut side: BookSide = BookSide::default();
+
+        for id in 1..=4 {
+            side.push(ask(id, 150));
+        }
+
+        assert_eq!(side.remove(2).map(|order| order.id()), Some(2));
+        assert_eq!(side.remove(2), None);
+        assert_eq!(ids(&side), vec![1, 3, 4]);
+        assert_eq!((side.position(3), side.position(4)), (Some(1), Some(2)));
+
+        /* the ends of the queue follow the orders taken from them */
+        side.remove(4);
+        side.push(ask(5, 150));
+        side.remove(1);
+        assert_eq!(ids(&side), vec![3, 5]);
+        assert_eq!(side.level(150).map(|orders| orders.len()), Some(2));
+        assert_eq!(side.position(3), Some(0));
+
+        /* the slots freed are used again */
+        side.push(ask(6, 140));
+        side.push(ask(7, 140));
+        assert_eq!(side.nodes.capacity(), 4);
+        assert_eq!(ids(&side), vec![6, 7, 3, 5]);
+
+        let mut equal: BookSide = BookSide::default();
+
+        for id in [6, 7, 3, 5] {
+            equal.push(ask(id, if id < 6 { 150 } else { 140 }));
+        }
+
+        assert_eq!(side, equal);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_validates() -> Result<(), BookError> {
+        let mut actual_side: BookSide = BookSide::new(Some(Band::new(100,
+            200, 5)?));
+        actual_side.push(ask(1, 110));
+        actual_side.push(ask(2, 105));
+        actual_side.push(ask(3, 110));
+        actual_side.remove(2);
+
+        /* only the band and the orders, in price order, are written */
+        let json: String = serde_json::to_string(&actual_side).unwrap();
+        assert!(json.starts_with(
+            "{\"band\":{\"low\":100,\"high\":200,\"tick\":5},\"orders\":["));
+        assert!(!json.contains("slots") && !json.contains("head"));
+        assert_eq!(serde_json::from_str::<BookSide>(&json).unwrap(),
+            actual_side);
+
+        /* and what is read back must make sense */
+        let bad: [String; 3] = [
+            json.replace("\"tick\":5", "\"tick\":0"),
+            json.replace("\"price\":110", "\"price\":111"),
+            json.replace("\"id\":3", "\"id\":1")
+        ];
+
+        for text in bad.iter() {
+            assert!(serde_json::from_str::<BookSide>(text).is_err());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use std::mem;
+
+/* values kept side by side in a vector, each found again by the index it was
+ * given, which stays its own until it is removed; the slots freed by removals
+ * are handed out again before the vector grows */
+#[derive(Clone, Debug)]
+pub struct Slab<T> {
+    slots: Vec<Slot<T>>,
+    free: Option<usize>,
+    len: usize
+}
+
+/* a vacant slot holds the next one in the list of free slots */
+#[derive(Clone, Debug)]
+enum Slot<T> {
+    Occupied(T),
+    Vacant(Option<usize>)
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Slab::new()
+    }
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Self {
+        Slab {
+            slots: vec![],
+            free: None,
+            len: 0
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /* the number of slots, occupied or not */
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn insert(&mut self, value: T) -> usize {
+        self.len += 1;
+
+        match self.free {
+            Some(index) => {
+                if let Slot::Vacant(next) = self.slots[index] {
+                    self.free = next;
+                }
+
+                self.slots[index] = Slot::Occupied(value);
+                index
+            },
+            None => {
+                self.slots.push(Slot::Occupied(value));
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        match self.slots.get(index) {
+            Some(Slot::Occupied(_)) => {},
+            _ => return None
+        };
+
+        self.len -= 1;
+
+        match mem::replace(&mut self.slots[index], Slot::Vacant(self.free)) {
+            Slot::Occupied(value) => {
+                self.free = Some(index);
+                Some(value)
+            },
+            Slot::Vacant(_) => unreachable!()
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        match self.slots.get(index) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None
+        }
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        match self.slots.get_mut(index) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None
+        }
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.get(index).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_reuses_freed_slots() {
+        let mut slab: Slab<char> = Slab::new();
+
+        assert_eq!((slab.insert('a'), slab.insert('b'), slab.insert('c')),
+            (0, 1, 2));
+        assert_eq!(slab.remove(1), Some('b'));
+        assert_eq!(slab.remove(0), Some('a'));
+
+        /* the most recently freed slot goes first */
+        assert_eq!((slab.insert('d'), slab.insert('e'), slab.insert('f')),
+            (0, 1, 3));
+        assert_eq!((slab.len(), slab.capacity()), (4, 4));
+        assert_eq!(slab.get(1), Some(&'e'));
+    }
+
+    #[test]
+    fn test_remove_vacant() {
+        let mut slab: Slab<char> = Slab::new();
+        let index: usize = slab.insert('a');
+
+        assert_eq!(slab.remove(index), Some('a'));
+        assert_eq!(slab.remove(index), None);
+        assert_eq!(slab.remove(7), None);
+        assert!(slab.is_empty() && !slab.contains(index));
+    }
+}
@@ -0,0 +1,512 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::exchange::Exchange;
+use crate::journal::{self, Journal, Sequence};
+
+/* every snapshot begins with these, followed by a little-endian version */
+pub const MAGIC: &[u8; 8] = b"CALOBSNP";
+/* a `narrow` build writes 64-bit numbers, so its files are a format of
+ * their own which the other build refuses */
+#[cfg(not(feature = "narrow"))]
+pub const VERSION: u32 = 1;
+#[cfg(feature = "narrow")]
+pub const VERSION: u32 = 0x8000_0001;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    Malformed,
+    MissingRecords(Sequence)
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::BadMagic => write!(f, "Not a snapshot"),
+            SnapshotError::UnsupportedVersion(v) =>
+                write!(f, "Unsupported snapshot version {}", v),
+            SnapshotError::Truncated => write!(f, "Snapshot is truncated"),
+            SnapshotError::Malformed => write!(f, "Snapshot is malformed"),
+            SnapshotError::MissingRecords(sequence) =>
+                write!(f, "Journal has no record {}", sequence)
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct Encoder {
+    bytes: Vec<u8>
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder::default()
+    }
+
+    pub fn put(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Decoder {
+            bytes,
+            position: 0
+        }
+    }
+
+    pub fn take(&mut self, length: usize) -> Result<&'a [u8], SnapshotError> {
+        let end: usize = match self.position.checked_add(length) {
+            Some(e) if e <= self.bytes.len() => e,
+            _ => return Err(SnapshotError::Truncated)
+        };
+
+        let taken: &'a [u8] = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+}
+
+/* a fixed, platform-independent binary representation: integers are
+ * little-endian, collections are prefixed by their length and maps are
+ * written in key order so that equal states encode identically */
+pub trait Codec: Sized {
+    fn encode(&self, encoder: &mut Encoder);
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError>;
+}
+
+macro_rules! integer_codec {
+    ($($t:ty),*) => {
+        $(
+            impl Codec for $t {
+                fn encode(&self, encoder: &mut Encoder) {
+                    encoder.put(&self.to_le_bytes());
+                }
+
+                fn decode(decoder: &mut Decoder) ->
+                    Result<Self, SnapshotError> {
+                    let bytes: &[u8] =
+                        decoder.take(std::mem::size_of::<$t>())?;
+                    Ok(<$t>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+integer_codec!(u8, u32, u64, i64, u128, i128);
+
+impl Codec for usize {
+    fn encode(&self, encoder: &mut Encoder) {
+        (*self as u64).encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        usize::try_from(u64::decode(decoder)?)
+            .map_err(|_| SnapshotError::Malformed)
+    }
+}
+
+impl Codec for bool {
+    fn encode(&self, encoder: &mut Encoder) {
+        (*self as u8).encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        match u8::decode(decoder)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Malformed)
+        }
+    }
+}
+
+impl Codec for String {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.len().encode(encoder);
+        encoder.put(self.as_bytes());
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        let length: usize = usize::decode(decoder)?;
+
+        String::from_utf8(decoder.take(length)?.to_vec())
+            .map_err(|_| SnapshotError::Malformed)
+    }
+}
+
+impl<T: Codec> Codec for Box<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.as_ref().encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(Box::new(T::decode(decoder)?))
+    }
+}
+
+impl<A: Codec, B: Codec> Codec for (A, B) {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.0.encode(encoder);
+        self.1.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        let first: A = A::decode(decoder)?;
+        Ok((first, B::decode(decoder)?))
+    }
+}
+
+impl<T: Codec> Codec for Option<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            Some(value) => {
+                true.encode(encoder);
+                value.encode(encoder);
+            },
+            None => false.encode(encoder)
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        match bool::decode(decoder)? {
+            true => Ok(Some(T::decode(decoder)?)),
+            false => Ok(None)
+        }
+    }
+}
+
+impl<T: Codec> Codec for Vec<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.len().encode(encoder);
+
+        for value in self.iter() {
+            value.encode(encoder);
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        let length: usize = usize::decode(decoder)?;
+        let mut values: Vec<T> = vec![];
+
+        for _ in 0..length {
+            values.push(T::decode(decoder)?);
+        }
+
+        Ok(values)
+    }
+}
+
+impl<T: Codec> Codec for VecDeque<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.len().encode(encoder);
+
+        for value in self.iter() {
+            value.encode(encoder);
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        Ok(Vec::<T>::decode(decoder)?.into())
+    }
+}
+
+impl<K: Codec + Ord, V: Codec> Codec for BTreeMap<K, V> {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.len().encode(encoder);
+
+        for (key, value) in self.iter() {
+            key.encode(encoder);
+            value.encode(encoder);
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        let length: usize = usize::decode(decoder)?;
+        let mut map: BTreeMap<K, V> = BTreeMap::new();
+
+        for _ in 0..length {
+            let key: K = K::decode(decoder)?;
+            map.insert(key, V::decode(decoder)?);
+        }
+
+        Ok(map)
+    }
+}
+
+impl<K: Codec + Ord + Hash, V: Codec> Codec for HashMap<K, V> {
+    fn encode(&self, encoder: &mut Encoder) {
+        let mut entries: Vec<(&K, &V)> = self.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries.len().encode(encoder);
+
+        for (key, value) in entries {
+            key.encode(encoder);
+            value.encode(encoder);
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, SnapshotError> {
+        let length: usize = usize::decode(decoder)?;
+        let mut map: HashMap<K, V> = HashMap::new();
+
+        for _ in 0..length {
+            let key: K = K::decode(decoder)?;
+            map.insert(key, V::decode(decoder)?);
+        }
+
+        Ok(map)
+    }
+}
+
+pub fn encode(exchange: &Exchange) -> Vec<u8> {
+    let mut encoder: Encoder = Encoder::new();
+
+    encoder.put(MAGIC);
+    VERSION.encode(&mut encoder);
+    exchange.encode(&mut encoder);
+
+    encoder.into_bytes()
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Exchange, SnapshotError> {
+    let mut decoder: Decoder = Decoder::new(bytes);
+
+    if decoder.take(MAGIC.len()).map_err(|_| SnapshotError::BadMagic)? !=
+        MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+
+    let exchange: Exchange = match u32::decode(&mut decoder)? {
+        VERSION => Exchange::decode(&mut decoder)?,
+        version => return Err(SnapshotError::UnsupportedVersion(version))
+    };
+
+    if !decoder.is_empty() {
+        return Err(SnapshotError::Malformed);
+    }
+
+    Ok(exchange)
+}
+
+/* written alongside and then moved into place, so that a crash part way
+ * through never leaves a partial snapshot at `path` */
+pub fn save(exchange: &Exchange, path: &Path) -> Result<(), SnapshotError> {
+    let staging: std::path::PathBuf = path.with_extension("partial");
+    let mut file: File = File::create(&staging)?;
+
+    file.write_all(&encode(exchange))?;
+    file.sync_all()?;
+    fs::rename(&staging, path)?;
+
+    /* the rename itself only survives a crash once the directory is synced */
+    let parent: &Path = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new(".")
+    };
+    File::open(parent)?.sync_all()?;
+
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Exchange, SnapshotError> {
+    decode(&fs::read(path)?)
+}
+
+/* loads the snapshot and then replays whatever the journal holds beyond it,
+ * which must carry on from where the snapshot leaves off */
+pub fn restore(path: &Path, journal: &Journal) ->
+    Result<Exchange, SnapshotError> {
+    let mut exchange: Exchange = load(path)?;
+    let next: Sequence = exchange.journal().next_sequence();
+    let tail: &[journal::Record] = journal.since(next)
+        .ok_or(SnapshotError::MissingRecords(next))?;
+
+    journal::replay_onto(&mut exchange, tail);
+    Ok(exchange)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Account;
+    use crate::book::Book;
+    use crate::exchange::ExchangeError;
+    use crate::margin::MarginTerms;
+    use crate::execution::ExecId;
+    use crate::order::{Order, OrderStatus, OrderType};
+    use crate::side::Band;
+
+    fn exchange() -> Result<Exchange, ExchangeError> {
+        let mut trader: Account = Account::new(1, "John Doe".to_string(),
+            HashMap::new(), HashMap::new());
+        trader.set_margin(Some(MarginTerms::new("USD".to_string(), 2, 2500)));
+
+        let mut exchange: Exchange = Exchange::new();
+        exchange.add_account(trader)?;
+        exchange.add_account(Account::new(2, "Jane Doe".to_string(),
+            HashMap::new(), HashMap::new()))?;
+        exchange.add_book(Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string()))?;
+        exchange.deposit(1, "USD".to_string(), 1000)?;
+        exchange.deposit_holding(2, "VOC".to_string(), 50)?;
+
+        exchange.submit(1, Order::new(1, 2, OrderType::Ask, 10, 20))?;
+        exchange.submit(1, Order::new(2, 2, OrderType::Ask, 10, 20))?;
+        exchange.submit(1, Order::new(3, 2, OrderType::Ask, 11, 10))?;
+        exchange.submit(1, Order::new(4, 1, OrderType::Bid, 10, 25))?;
+        exchange.submit(1, Order::new(5, 1, OrderType::Bid, 9, 5))?;
+
+        let mut ladder: Book = Book::new(2, "Geoctroyeerde Westindische \
+            Compagnie".to_string(), "WIC".to_string(), "USD".to_string());
+        ladder.set_band(Some(Band::new(1, 100, 1)?))?;
+        exchange.add_book(ladder)?;
+        exchange.deposit_holding(2, "WIC".to_string(), 10)?;
+        exchange.submit(2, Order::new(6, 2, OrderType::Ask, 12, 10))?;
+        exchange.submit(2, Order::new(7, 1, OrderType::Bid, 8, 5))?;
+
+        Ok(exchange)
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("calob-{}-{}", std::process::id(),
+            name))
+    }
+
+    #[test]
+    fn test_decode_roundtrip() -> Result<(), ExchangeError> {
+        let actual_exchange: Exchange = exchange()?;
+        let bytes: Vec<u8> = encode(&actual_exchange);
+        let decoded: Exchange = decode(&bytes).unwrap();
+
+        assert_eq!(decoded.accounts(), actual_exchange.accounts());
+        assert_eq!(decoded.books(), actual_exchange.books());
+        assert_eq!(decoded.journal().next_sequence(),
+            actual_exchange.journal().next_sequence());
+        assert_eq!(decoded.book(1).unwrap().queue_position(2), Some(0));
+        assert_eq!(encode(&decoded), bytes);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_acknowledged() -> Result<(), ExchangeError> {
+        let mut actual_exchange: Exchange = exchange()?;
+        let last: ExecId = actual_exchange.book(1).unwrap().reports().last()
+            .map(|report| report.exec_id()).unwrap();
+
+        actual_exchange.acknowledge(1, last)?;
+        actual_exchange.forget(1, last)?;
+        let decoded: Exchange = decode(&encode(&actual_exchange)).unwrap();
+
+        /* filled orders are forgotten, but resting ones are not */
+        assert!(decoded.book(1).unwrap().reports().is_empty());
+        assert_eq!(decoded.book(1).unwrap().order_status(1), None);
+        assert_eq!(decoded.book(1).unwrap().order_status(5),
+            Some(OrderStatus::New));
+        assert_eq!(decoded.books(), actual_exchange.books());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_invalid() -> Result<(), ExchangeError> {
+        let bytes: Vec<u8> = encode(&exchange()?);
+
+        let mut future: Vec<u8> = bytes.clone();
+        future[MAGIC.len()..MAGIC.len() + 4]
+            .copy_from_slice(&(VERSION + 1).to_le_bytes());
+
+        assert!(matches!(decode(b"CALOB"), Err(SnapshotError::BadMagic)));
+        assert!(matches!(decode(&future),
+            Err(SnapshotError::UnsupportedVersion(v)) if v == VERSION + 1));
+        assert!(matches!(decode(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Truncated)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_with_journal_tail() -> Result<(), ExchangeError> {
+        let path: std::path::PathBuf = temp_path("restore.snapshot");
+        let mut actual_exchange: Exchange = exchange()?;
+
+        save(&actual_exchange, &path).unwrap();
+
+        actual_exchange.submit(1, Order::new(6, 1, OrderType::Bid, 11, 10))?;
+        actual_exchange.cancel(1, 5)?;
+        actual_exchange.amend(1, 2, 12, 15)?;
+
+        let restored: Exchange = restore(&path, actual_exchange.journal())
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.accounts(), actual_exchange.accounts());
+        assert_eq!(restored.books(), actual_exchange.books());
+        assert_eq!(restored.journal().next_sequence(),
+            actual_exchange.journal().next_sequence());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_missing_records() -> Result<(), ExchangeError> {
+        let path: std::path::PathBuf = temp_path("missing.snapshot");
+        let later: std::path::PathBuf = temp_path("missing_later.snapshot");
+        let mut actual_exchange: Exchange = exchange()?;
+
+        save(&actual_exchange, &path).unwrap();
+        actual_exchange.submit(1, Order::new(6, 1, OrderType::Bid, 11, 10))?;
+        save(&actual_exchange, &later).unwrap();
+
+        /* a journal kept only since the later snapshot cannot bring the
+         * earlier one up to date */
+        let mut resumed: Exchange = load(&later).unwrap();
+        resumed.deposit(1, "USD".to_string(), 10)?;
+
+        let next: Sequence = load(&path).unwrap().journal().next_sequence();
+        assert!(matches!(restore(&path, resumed.journal()),
+            Err(SnapshotError::MissingRecords(sequence)) if sequence == next));
+        assert_eq!(restore(&later, resumed.journal()).unwrap().journal()
+            .next_sequence(), resumed.journal().next_sequence());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&later).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,502 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::exchange::Exchange;
+use crate::journal::{self, Record, Sequence};
+use crate::snapshot::{Codec, Decoder, Encoder};
+
+/* a log begins with these, followed by a little-endian version; each record
+ * is then framed by its length, a CRC-32 of that length and a CRC-32 of its
+ * contents */
+pub const MAGIC: &[u8; 8] = b"CALOBWAL";
+
+/* as with snapshots, logs written by a `narrow` build are versioned apart */
+#[cfg(not(feature = "narrow"))]
+pub const VERSION: u32 = 1;
+#[cfg(feature = "narrow")]
+pub const VERSION: u32 = 0x8000_0001;
+
+const HEADER_LENGTH: usize = 12;
+const FRAME_HEADER_LENGTH: usize = 12;
+
+#[derive(Debug, Error)]
+pub enum WalError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    Corrupt(u64),
+    MissingRecords(Sequence),
+    Failed
+}
+
+impl fmt::Display for WalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalError::Io(e) => write!(f, "{}", e),
+            WalError::BadMagic => write!(f, "Not a write-ahead log"),
+            WalError::UnsupportedVersion(v) =>
+                write!(f, "Unsupported write-ahead log version {}", v),
+            WalError::Corrupt(offset) =>
+                write!(f, "Write-ahead log is corrupt at byte {}", offset),
+            WalError::MissingRecords(sequence) =>
+                write!(f, "Write-ahead log has no record {}", sequence),
+            WalError::Failed =>
+                write!(f, "Write-ahead log could not be repaired after a \
+                    failed append")
+        }
+    }
+}
+
+impl From<io::Error> for WalError {
+    fn from(e: io::Error) -> Self {
+        WalError::Io(e)
+    }
+}
+
+/* how often appended records are forced out to the disk; anything not yet
+ * synced may be lost (though never half-recovered) should the machine
+ * crash */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+    Always,
+    Batch(usize),
+    Never
+}
+
+#[derive(Debug)]
+pub struct Wal {
+    file: File,
+    path: PathBuf,
+    policy: SyncPolicy,
+    unsynced: usize,
+    /* where the last whole record ends */
+    end: u64,
+    failed: bool,
+    #[cfg(test)]
+    short_write: Option<usize>
+}
+
+impl Wal {
+    /* starts a new, empty log, replacing anything already at `path` */
+    pub fn create(path: &Path, policy: SyncPolicy) -> Result<Wal, WalError> {
+        let mut file: File = OpenOptions::new().read(true).write(true)
+            .create(true).truncate(true).open(path)?;
+
+        file.write_all(&Wal::header())?;
+        file.sync_all()?;
+
+        Ok(Wal::new(file, path, policy, HEADER_LENGTH as u64))
+    }
+
+    /* opens an existing log (or starts one), returning every record intact
+     * in it; a torn record at the end, as left by a crash part way through
+     * an append, is cut off */
+    pub fn open(path: &Path, policy: SyncPolicy) ->
+        Result<(Wal, Vec<Record>), WalError> {
+        let mut file: File = OpenOptions::new().read(true).write(true)
+            .create(true).truncate(false).open(path)?;
+        let mut bytes: Vec<u8> = vec![];
+        file.read_to_end(&mut bytes)?;
+
+        if bytes.len() < HEADER_LENGTH {
+            if !Wal::header().starts_with(&bytes) {
+                return Err(WalError::BadMagic);
+            }
+
+            /* the crash happened while the log was being created */
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&Wal::header())?;
+            bytes = Wal::header();
+        }
+
+        let (records, valid): (Vec<Record>, usize) = Wal::scan(&bytes)?;
+
+        file.set_len(valid as u64)?;
+        file.seek(SeekFrom::Start(valid as u64))?;
+        file.sync_all()?;
+
+        Ok((Wal::new(file, path, policy, valid as u64), records))
+    }
+
+    fn new(file: File, path: &Path, policy: SyncPolicy, end: u64) -> Wal {
+        Wal {
+            file,
+            path: path.to_path_buf(),
+            policy,
+            unsynced: 0,
+            end,
+            failed: false,
+            #[cfg(test)]
+            short_write: None
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn policy(&self) -> SyncPolicy {
+        self.policy
+    }
+
+    /* a frame only partly written is cut off again, so that the next append
+     * follows on from the last whole record; should that fail too, the log
+     * refuses every append after */
+    pub fn append(&mut self, record: &Record) -> Result<(), WalError> {
+        if self.failed {
+            return Err(WalError::Failed);
+        }
+
+        let mut encoder: Encoder = Encoder::new();
+        record.encode(&mut encoder);
+        let payload: Vec<u8> = encoder.into_bytes();
+
+        let length: u32 = match payload.len().try_into() {
+            Ok(l) => l,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "Record too large").into())
+        };
+
+        let mut frame: Vec<u8> =
+            Vec::with_capacity(FRAME_HEADER_LENGTH + payload.len());
+        frame.extend_from_slice(&length.to_le_bytes());
+        frame.extend_from_slice(&crc32(&length.to_le_bytes()).to_le_bytes());
+        frame.extend_from_slice(&crc32(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+
+        if let Err(e) = self.write_frame(&frame) {
+            self.failed = self.file.set_len(self.end)
+                .and_then(|_| self.file.seek(SeekFrom::Start(self.end)))
+                .is_err();
+            return Err(e.into());
+        }
+
+        self.end += frame.len() as u64;
+        self.unsynced += 1;
+
+        match self.policy {
+            SyncPolicy::Always => self.sync(),
+            SyncPolicy::Batch(size) if self.unsynced >= size => self.sync(),
+            _ => Ok(())
+        }
+    }
+
+    pub fn sync(&mut self) -> Result<(), WalError> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    #[cfg(not(test))]
+    fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.file.write_all(frame)
+    }
+
+    /* tests may have a write give out part way through */
+    #[cfg(test)]
+    fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        match self.short_write.take() {
+            Some(length) => {
+                self.file.write_all(&frame[..length.min(frame.len())])?;
+                Err(io::Error::other("Short write"))
+            },
+            None => self.file.write_all(frame)
+        }
+    }
+
+    fn header() -> Vec<u8> {
+        let mut header: Vec<u8> = MAGIC.to_vec();
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header
+    }
+
+    /* the intact records and the length of the log they occupy */
+    fn scan(bytes: &[u8]) -> Result<(Vec<Record>, usize), WalError> {
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(WalError::BadMagic);
+        }
+
+        let version: u32 = u32::from_le_bytes(bytes[MAGIC.len()..HEADER_LENGTH]
+            .try_into().unwrap());
+
+        if version != VERSION {
+            return Err(WalError::UnsupportedVersion(version));
+        }
+
+        let mut records: Vec<Record> = vec![];
+        let mut offset: usize = HEADER_LENGTH;
+
+        while bytes.len() - offset >= FRAME_HEADER_LENGTH {
+            let frame: &[u8] = &bytes[offset..];
+            let length: usize = u32::from_le_bytes(frame[..4].try_into()
+                .unwrap()) as usize;
+            let length_checksum: u32 = u32::from_le_bytes(frame[4..8]
+                .try_into().unwrap());
+            let checksum: u32 = u32::from_le_bytes(frame[8..12].try_into()
+                .unwrap());
+
+            /* a whole frame header was written, so a bad length is
+             * corruption rather than a torn append */
+            if crc32(&frame[..4]) != length_checksum {
+                return Err(WalError::Corrupt(offset as u64));
+            }
+
+            /* whereas a good length running past the end of the file is
+             * the one record a crash cut short */
+            if frame.len() - FRAME_HEADER_LENGTH < length {
+                break;
+            }
+
+            let end: usize = FRAME_HEADER_LENGTH + length;
+            let payload: &[u8] = &frame[FRAME_HEADER_LENGTH..end];
+
+            /* a bad final record is torn, anywhere else it is corrupt */
+            if crc32(payload) != checksum {
+                if end == frame.len() {
+                    break;
+                }
+
+                return Err(WalError::Corrupt(offset as u64));
+            }
+
+            let mut decoder: Decoder = Decoder::new(payload);
+            let record: Record = match Record::decode(&mut decoder) {
+                Ok(r) if decoder.is_empty() => r,
+                _ => return Err(WalError::Corrupt(offset as u64))
+            };
+
+            if let Some(last) = records.last() {
+                if record.sequence() != last.sequence() + 1 {
+                    return Err(WalError::Corrupt(offset as u64));
+                }
+            }
+
+            records.push(record);
+            offset += end;
+        }
+
+        Ok((records, offset))
+    }
+}
+
+/* replays the log onto `exchange` (either a fresh one or one just loaded
+ * from a snapshot), skipping whatever it already reflects, and then has
+ * the exchange carry on writing to the log */
+pub fn recover(exchange: &mut Exchange, path: &Path, policy: SyncPolicy) ->
+    Result<(), WalError> {
+    let (wal, records): (Wal, Vec<Record>) = Wal::open(path, policy)?;
+    exchange.set_wal(None);
+
+    let next: Sequence = exchange.journal().next_sequence();
+    let tail: &[Record] = match records.iter()
+        .position(|record| record.sequence() >= next) {
+        Some(start) => &records[start..],
+        None => &[]
+    };
+
+    if let Some(first) = tail.first() {
+        if first.sequence() != next {
+            return Err(WalError::MissingRecords(next));
+        }
+    }
+
+    journal::replay_onto(exchange, tail);
+    exchange.set_wal(Some(wal));
+    Ok(())
+}
+
+/* CRC-32 (IEEE 802.3), as used by zlib and friends */
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+
+    for byte in bytes.iter() {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            let mask: u32 = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use crate::account::Account;
+    use crate::book::Book;
+    use crate::exchange::ExchangeError;
+    use crate::order::{Order, OrderType};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("calob-{}-{}", std::process::id(),
+            name))
+    }
+
+    fn trade(exchange: &mut Exchange) -> Result<(), ExchangeError> {
+        exchange.add_account(Account::new(1, "John Doe".to_string(),
+            HashMap::new(), HashMap::new()))?;
+        exchange.add_account(Account::new(2, "Jane Doe".to_string(),
+            HashMap::new(), HashMap::new()))?;
+        exchange.add_book(Book::new(1,
+            "Vereenigde Oostindische Compagnie".to_string(), "VOC".to_string(),
+            "USD".to_string()))?;
+        exchange.deposit(1, "USD".to_string(), 1000)?;
+        exchange.deposit_holding(2, "VOC".to_string(), 50)?;
+        exchange.submit(1, Order::new(1, 2, OrderType::Ask, 10, 20))?;
+        exchange.submit(1, Order::new(2, 1, OrderType::Bid, 10, 5))?;
+        exchange.amend(1, 1, 11, 10)?;
+        exchange.cancel(1, 1)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_recover_after_crash_at_any_offset() -> Result<(), ExchangeError> {
+        let path: PathBuf = temp_path("crash.wal");
+        let mut expected_exchange: Exchange = Exchange::new();
+        expected_exchange.set_wal(Some(Wal::create(&path,
+            SyncPolicy::Batch(4))?));
+        trade(&mut expected_exchange)?;
+        expected_exchange.set_wal(None);
+
+        let bytes: Vec<u8> = fs::read(&path).unwrap();
+        let crashed: PathBuf = temp_path("crashed.wal");
+
+        for offset in 0..=bytes.len() {
+            fs::write(&crashed, &bytes[..offset]).unwrap();
+
+            let mut actual_exchange: Exchange = Exchange::new();
+            recover(&mut actual_exchange, &crashed, SyncPolicy::Never)?;
+
+            /* exactly the records which made it to disk in full survive */
+            let survivors: usize = actual_exchange.journal().len();
+            assert_eq!(actual_exchange.journal().records(),
+                &expected_exchange.journal().records()[..survivors]);
+            assert!(survivors == expected_exchange.journal().len() ||
+                offset < bytes.len());
+            assert!(fs::metadata(&crashed).unwrap().len() as usize <= offset
+                .max(HEADER_LENGTH));
+
+            /* and the log can be carried on with (even refused commands are
+             * logged) */
+            assert!(actual_exchange.cancel(9, 9).is_err());
+            actual_exchange.set_wal(None);
+
+            let (_, records): (Wal, Vec<Record>) =
+                Wal::open(&crashed, SyncPolicy::Never)?;
+            assert_eq!(records.len(), survivors + 1);
+        }
+
+        let mut actual_exchange: Exchange = Exchange::new();
+        recover(&mut actual_exchange, &path, SyncPolicy::Always)?;
+        assert_eq!(actual_exchange, expected_exchange);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&crashed).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_corrupt() -> Result<(), ExchangeError> {
+        let path: PathBuf = temp_path("corrupt.wal");
+        let mut exchange: Exchange = Exchange::new();
+        exchange.set_wal(Some(Wal::create(&path, SyncPolicy::Always)?));
+        trade(&mut exchange)?;
+        exchange.set_wal(None);
+
+        let mut bytes: Vec<u8> = fs::read(&path).unwrap();
+        let last: usize = bytes.len() - 1;
+
+        /* damage to the final record is indistinguishable from a torn write */
+        bytes[last] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+        let (_, records): (Wal, Vec<Record>) =
+            Wal::open(&path, SyncPolicy::Never)?;
+        assert_eq!(records.len(), exchange.journal().len() - 1);
+
+        /* whereas anywhere else it is not */
+        bytes[HEADER_LENGTH + FRAME_HEADER_LENGTH] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(Wal::open(&path, SyncPolicy::Never),
+            Err(WalError::Corrupt(12))));
+
+        fs::write(&path, b"CALOBSNP").unwrap();
+        assert!(matches!(Wal::open(&path, SyncPolicy::Never),
+            Err(WalError::BadMagic)));
+
+        fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_corrupt_length() -> Result<(), ExchangeError> {
+        let path: PathBuf = temp_path("corrupt_length.wal");
+        let mut exchange: Exchange = Exchange::new();
+        exchange.set_wal(Some(Wal::create(&path, SyncPolicy::Always)?));
+        exchange.add_account(Account::new(1, "John Doe".to_string(),
+            HashMap::new(), HashMap::new()))?;
+        exchange.deposit(1, "USD".to_string(), 1000)?;
+        exchange.deposit(1, "USD".to_string(), 500)?;
+        exchange.set_wal(None);
+        assert_eq!(exchange.journal().len(), 3);
+
+        /* a first record claiming to run past the end of the file must not
+         * be mistaken for a torn tail, taking the two after it with it */
+        let mut bytes: Vec<u8> = fs::read(&path).unwrap();
+        bytes[HEADER_LENGTH + 2] ^= 0x01;
+        fs::write(&path, &bytes).unwrap();
+
+        assert!(matches!(Wal::open(&path, SyncPolicy::Never),
+            Err(WalError::Corrupt(12))));
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_after_short_write() -> Result<(), ExchangeError> {
+        let path: PathBuf = temp_path("short_write.wal");
+        let mut actual_exchange: Exchange = Exchange::new();
+        actual_exchange.set_wal(Some(Wal::create(&path, SyncPolicy::Always)?));
+        actual_exchange.add_account(Account::new(1, "John Doe".to_string(),
+            HashMap::new(), HashMap::new()))?;
+
+        let mut wal: Wal = actual_exchange.set_wal(None).unwrap();
+        wal.short_write = Some(7);
+        actual_exchange.set_wal(Some(wal));
+
+        /* the account is neither added nor leaves half a frame behind for
+         * the next record to follow */
+        assert!(matches!(actual_exchange.add_account(Account::new(2,
+            "Jane Doe".to_string(), HashMap::new(), HashMap::new())),
+            Err(ExchangeError::Wal(WalError::Io(_)))));
+        assert!(actual_exchange.account(2).is_none());
+        actual_exchange.deposit(1, "USD".to_string(), 1000)?;
+        actual_exchange.deposit(1, "USD".to_string(), 500)?;
+        actual_exchange.set_wal(None);
+
+        let mut recovered: Exchange = Exchange::new();
+        recover(&mut recovered, &path, SyncPolicy::Never)?;
+        assert_eq!(recovered, actual_exchange);
+        assert_eq!(recovered.journal().len(), 3);
+
+        fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use calob::scenario::Scenario;
+
+/* every file under tests/scenarios is a scenario, and all of them are run
+ * so that one failure does not hide another */
+#[test]
+fn test_scenarios() {
+    let directory: PathBuf = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests").join("scenarios");
+    let mut paths: Vec<PathBuf> = fs::read_dir(&directory).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "scenario"))
+        .collect();
+    paths.sort();
+
+    let failures: Vec<String> = paths.iter()
+        .filter_map(|path| Scenario::load(path).and_then(|s| s.run()).err()
+            .map(|e| format!("{}: {}", path.display(), e)))
+        .collect();
+
+    assert!(!paths.is_empty());
+    assert!(failures.is_empty(), "{} of {} scenarios failed:\n{}",
+        failures.len(), paths.len(), failures.join("\n"));
+}